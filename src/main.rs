@@ -1,424 +1,10573 @@
 use anyhow::{anyhow, Context, Result};
 use argon2::Argon2;
 use chacha20poly1305::aead::stream::{
-    DecryptorBE32, EncryptorBE32, Nonce as NonceStream, StreamBE32,
+    NewStream, Nonce as NonceStream, StreamBE32, StreamPrimitive,
 };
-use chacha20poly1305::{ChaCha20Poly1305, KeyInit};
-use clap::{Parser, Subcommand};
-use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use chacha20poly1305::aead::Payload;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, XChaCha20Poly1305};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use indicatif::{HumanBytes, HumanCount, MultiProgress, ProgressBar, ProgressStyle};
 use rand::Rng;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::fs::{self, File};
-use std::io::{BufWriter, Read, Write};
-use std::path::PathBuf;
+use std::io::{BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use zeroize::Zeroize;
 use zstd::stream::read::Decoder as ZstdDecoder;
 use zstd::stream::write::Encoder as ZstdEncoder;
 
-type TipeNonce = NonceStream<ChaCha20Poly1305, StreamBE32<ChaCha20Poly1305>>;
-
+// Default plaintext chunk size, and the only one before format 4
 const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+// --chunk-size bounds as powers of two (4 KiB to 8 MiB)
+const MIN_CHUNK_LOG: u8 = 12;
+const MAX_CHUNK_LOG: u8 = 23;
+
+// Format 1 archives start directly with the salt; later formats prepend magic + version,
+// format 3 follows them with the Argon2 parameters, format 4 with the chunk size,
+// format 5 puts a flags byte right after the version, format 6 starts the
+// encrypted header with its version, format 7 encodes it as tagged records,
+// format 8 seals the trailer as the stream's last chunk, format 9 authenticates
+// the prologue as associated data of every chunk, format 10 ends the prologue
+// with a key commitment, format 11 records the cipher after the flags and format 12
+// ends the file with a checksum footer
+const MAGIC: &[u8; 4] = b"RSTF";
+const FORMAT_VERSION: u8 = 12;
+// BLAKE3 derive_key context of the key commitment
+const KEY_COMMITMENT_CONTEXT: &str = "rstf 2026-10 archive key commitment";
+// Prologue flag bits this rstf understands; archives with others set need a newer rstf
+const KNOWN_FLAGS: u8 = FLAG_PLAINTEXT;
+// Packed with --no-encrypt: chunks are stored in the clear with a checksum for a tag
+const FLAG_PLAINTEXT: u8 = 0x01;
+// BLAKE3 derive_key context of the checksum tags of --no-encrypt chunks
+const PLAIN_CHUNK_CONTEXT: &str = "rstf 2026-10 plaintext chunk checksum";
+const MAX_COMMENT_LEN: usize = 64 * 1024;
+// Largest zstd --long window (1 GiB); the decoder needs that much memory
+const MAX_WINDOW_LOG: u32 = 30;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug, Clone, Default)]
 struct RstfHeader {
     is_dir: bool,
     original_name: String,
     original_size: u64,
+    // Fields below were appended after 1.0.0; older headers simply end early
+    /// Top-level names of a multi-input archive (empty for single inputs)
+    members: Vec<String>,
+    /// Directory entries are stored relative to the directory itself
+    contents_only: bool,
+    /// Number of files and directories in a directory archive
+    file_count: u64,
+    dir_count: u64,
+    /// Creation time (Unix seconds) and the rstf version that wrote the archive
+    created_at: u64,
+    tool_version: String,
+    /// Free-form note attached with --comment (authenticated like every field)
+    comment: String,
+    /// Plaintext length of the trailer chunk after the payload (0 = none)
+    trailer_len: u32,
+    /// Owner of a single-file input (directory entries carry their own in tar)
+    owner: Option<FileOwner>,
+    /// Permission bits and mtime (Unix seconds) of a single-file input; Windows
+    /// only records and restores the read-only flag, as 0o444 versus 0o644
+    mode: Option<u32>,
+    mtime: Option<i64>,
+    /// Data extents (offset, length) of a single file packed with --sparse; the
+    /// payload then holds only these bytes and everything else is a hole
+    extents: Option<Vec<(u64, u64)>>,
+    /// Payload codec id (see Compression::id); headers without it are zstd
+    compression: u8,
+    /// zstd window log of a --long pack, which the decoder has to allow
+    window_log: Option<u32>,
+    /// ID of the zstd dictionary a --dict pack needs (0 for raw-content dictionaries)
+    dict_id: Option<u32>,
+    /// The dictionary itself when packed with --embed-dict; readers put the
+    /// --dict file here otherwise
+    dictionary: Option<Vec<u8>>,
+    /// Compression level (zstd and xz; None for codecs without levels)
+    level: Option<i32>,
+    /// The payload is a --smart-compress segment stream (see SmartEncoder)
+    segmented: bool,
+    /// zstd level per file class of an --adaptive pack (None: stored), which
+    /// the payload's frames follow
+    class_levels: Option<Vec<(String, Option<i32>)>>,
+    /// Length of the encoded header as read from an archive (not stored)
+    encoded_len: usize,
+    /// Container format the header was read from (not stored)
+    format_version: u8,
+    /// Cipher recorded in the prologue (not stored)
+    cipher: Cipher,
+    /// Packed with --no-encrypt (not stored)
+    plaintext: bool,
+    /// Plaintext chunk size from the prologue (not stored)
+    chunk_size: usize,
+    /// Largest zstd window unpack --memory-limit lets the decoder use (not stored)
+    window_log_limit: Option<u32>,
+    /// Trailer authenticated when the archive was opened (not stored)
+    trailer: Option<Trailer>,
+    /// Payload bytes between the independent zstd frames of a --index pack, whose
+    /// chunk index follows the payload in the stream
+    index_span: Option<u64>,
+    /// Chunk index read when the archive was opened (not stored)
+    index: Option<ChunkIndex>,
+    /// Packed with --pad: filler follows the payload (and any chunk index) in the
+    /// stream, its length in the trailer
+    padded: bool,
 }
 
-#[derive(Parser)]
-#[command(version, about)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
-}
-
-#[derive(Subcommand)]
-enum Commands {
-    Pack {
-        input: PathBuf,
-        #[arg(long)]
-        wipe: bool,
-        #[arg(long, default_value = "5")]
-        level: i32,
-        #[arg(long, short = 'k')]
-        keyfile: Option<PathBuf>,
-    },
-    Unpack {
-        input: PathBuf,
-        #[arg(long, short = 'k')]
-        keyfile: Option<PathBuf>,
-    },
-    List {
-        input: PathBuf,
-        #[arg(long, short = 'k')]
-        keyfile: Option<PathBuf>,
-    },
-}
-
-// Credential Processing Helper
-fn process_credentials(salt: &[u8], keyfile_path: Option<PathBuf>) -> Result<[u8; 32]> {
-    let mut password =
-        rpassword::prompt_password("Enter password: ").context("Failed to read password")?;
+// Header version of format 6 bincode headers: how many of RstfHeader's field groups
+// (in declaration order) a header carries. 1 is is_dir, original_name and
+// original_size; then members, contents_only, file and dir counts, created_at and
+// tool_version, comment, trailer_len, owner, mode and mtime, extents, compression,
+// window_log, dict_id and dictionary, level, segmented and class_levels. Older
+// formats stop wherever their writer did
+const HEADER_VERSION: u16 = 16;
 
-    let mut combined_credentials = password.as_bytes().to_vec();
+// Header records (format 7 on): a tag byte, a u32 LE value length and the value.
+// Integers are little-endian and text is UTF-8; fields at their default are left
+// out. Tags with HEADER_CRITICAL set change how the payload has to be read, so
+// readers refuse those they do not know and skip the others
+const HEADER_CRITICAL: u8 = 0x80;
+/// Empty value; present when the input was a directory
+const HEADER_IS_DIR: u8 = 0x01;
+const HEADER_NAME: u8 = 0x02;
+/// u64
+const HEADER_SIZE: u8 = 0x03;
+/// One record per top-level name of a multi-input archive, in order
+const HEADER_MEMBER: u8 = 0x04;
+/// Empty value
+const HEADER_CONTENTS_ONLY: u8 = 0x05;
+/// u64 each
+const HEADER_FILE_COUNT: u8 = 0x06;
+const HEADER_DIR_COUNT: u8 = 0x07;
+/// u64 Unix seconds
+const HEADER_CREATED_AT: u8 = 0x08;
+const HEADER_TOOL_VERSION: u8 = 0x09;
+const HEADER_COMMENT: u8 = 0x0a;
+/// u32 uid, u32 gid, u32 user name length, user name, then the group name
+const HEADER_OWNER: u8 = 0x0b;
+/// u32 permission bits
+const HEADER_MODE: u8 = 0x0c;
+/// i64 Unix seconds
+const HEADER_MTIME: u8 = 0x0d;
+/// i32
+const HEADER_LEVEL: u8 = 0x0e;
+/// One record per --adaptive class: 1 and an i32 level, or 0 when stored, then the name
+const HEADER_CLASS_LEVEL: u8 = 0x0f;
+/// u32
+const HEADER_TRAILER_LEN: u8 = 0x81;
+/// (u64 offset, u64 length) pairs
+const HEADER_EXTENTS: u8 = 0x82;
+/// u8 codec id (see Compression::id)
+const HEADER_COMPRESSION: u8 = 0x83;
+/// u32 each
+const HEADER_WINDOW_LOG: u8 = 0x84;
+const HEADER_DICT_ID: u8 = 0x85;
+/// Raw dictionary bytes
+const HEADER_DICTIONARY: u8 = 0x86;
+/// Empty value
+const HEADER_SEGMENTED: u8 = 0x87;
+/// u64
+const HEADER_INDEX_SPAN: u8 = 0x88;
+/// Empty value
+const HEADER_PADDED: u8 = 0x89;
 
-    if let Some(path) = keyfile_path {
-        println!("Reading keyfile: {}", path.display());
-        let mut file = File::open(path).context("Failed to open keyfile")?;
-        let mut hasher = Sha256::new();
-        std::io::copy(&mut file, &mut hasher).context("Failed to read keyfile")?;
-        let hash = hasher.finalize();
-        combined_credentials.extend_from_slice(&hash);
+// RstfHeader Encoding
+impl RstfHeader {
+    /// Empty header stamped with the current time and tool version
+    fn stamped() -> Self {
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        RstfHeader {
+            created_at,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            trailer_len: Trailer::LEN as u32,
+            ..Default::default()
+        }
     }
 
-    let argon2 = Argon2::default();
-    let mut key = [0u8; 32];
-    argon2
-        .hash_password_into(&combined_credentials, salt, &mut key)
-        .map_err(|_| anyhow!("Key derivation failed"))?;
-
-    password.zeroize();
-    combined_credentials.zeroize();
-
-    Ok(key)
-}
-
-struct EncryptedWriter<W: Write> {
-    inner: W,
-    encryptor: EncryptorBE32<ChaCha20Poly1305>,
-    buffer: Vec<u8>,
-}
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut record = |tag: u8, value: &[u8]| {
+            bytes.push(tag);
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(value);
+        };
+        if self.is_dir {
+            record(HEADER_IS_DIR, &[]);
+        }
+        record(HEADER_NAME, self.original_name.as_bytes());
+        record(HEADER_SIZE, &self.original_size.to_le_bytes());
+        for member in &self.members {
+            record(HEADER_MEMBER, member.as_bytes());
+        }
+        if self.contents_only {
+            record(HEADER_CONTENTS_ONLY, &[]);
+        }
+        if self.file_count > 0 {
+            record(HEADER_FILE_COUNT, &self.file_count.to_le_bytes());
+        }
+        if self.dir_count > 0 {
+            record(HEADER_DIR_COUNT, &self.dir_count.to_le_bytes());
+        }
+        if self.created_at > 0 {
+            record(HEADER_CREATED_AT, &self.created_at.to_le_bytes());
+        }
+        if !self.tool_version.is_empty() {
+            record(HEADER_TOOL_VERSION, self.tool_version.as_bytes());
+        }
+        if !self.comment.is_empty() {
+            record(HEADER_COMMENT, self.comment.as_bytes());
+        }
+        if let Some(owner) = &self.owner {
+            let mut value = Vec::new();
+            value.extend_from_slice(&owner.uid.to_le_bytes());
+            value.extend_from_slice(&owner.gid.to_le_bytes());
+            value.extend_from_slice(&(owner.user.len() as u32).to_le_bytes());
+            value.extend_from_slice(owner.user.as_bytes());
+            value.extend_from_slice(owner.group.as_bytes());
+            record(HEADER_OWNER, &value);
+        }
+        if let Some(mode) = self.mode {
+            record(HEADER_MODE, &mode.to_le_bytes());
+        }
+        if let Some(mtime) = self.mtime {
+            record(HEADER_MTIME, &mtime.to_le_bytes());
+        }
+        if let Some(level) = self.level {
+            record(HEADER_LEVEL, &level.to_le_bytes());
+        }
+        for (class, level) in self.class_levels.iter().flatten() {
+            let mut value = match level {
+                Some(level) => [&[1][..], &level.to_le_bytes()].concat(),
+                None => vec![0],
+            };
+            value.extend_from_slice(class.as_bytes());
+            record(HEADER_CLASS_LEVEL, &value);
+        }
+        if self.trailer_len > 0 {
+            record(HEADER_TRAILER_LEN, &self.trailer_len.to_le_bytes());
+        }
+        if let Some(extents) = &self.extents {
+            let value: Vec<u8> = extents
+                .iter()
+                .flat_map(|&(offset, len)| [offset.to_le_bytes(), len.to_le_bytes()])
+                .flatten()
+                .collect();
+            record(HEADER_EXTENTS, &value);
+        }
+        record(HEADER_COMPRESSION, &[self.compression]);
+        if let Some(window_log) = self.window_log {
+            record(HEADER_WINDOW_LOG, &window_log.to_le_bytes());
+        }
+        if let Some(dict_id) = self.dict_id {
+            record(HEADER_DICT_ID, &dict_id.to_le_bytes());
+        }
+        if let Some(dictionary) = &self.dictionary {
+            record(HEADER_DICTIONARY, dictionary);
+        }
+        if self.segmented {
+            record(HEADER_SEGMENTED, &[]);
+        }
+        if let Some(span) = self.index_span {
+            record(HEADER_INDEX_SPAN, &span.to_le_bytes());
+        }
+        if self.padded {
+            record(HEADER_PADDED, &[]);
+        }
+        Ok(bytes)
+    }
 
-// EncryptedWriter Implementation
-impl<W: Write> EncryptedWriter<W> {
-    fn new(inner: W, encryptor: EncryptorBE32<ChaCha20Poly1305>) -> Self {
-        Self {
-            inner,
-            encryptor,
-            buffer: Vec::with_capacity(CHUNK_SIZE),
+    /// Decodes a header of the given container format
+    fn from_bytes(bytes: &[u8], format_version: u8) -> Result<Self> {
+        if format_version >= 7 {
+            return Ok(RstfHeader {
+                encoded_len: bytes.len(),
+                ..Self::decode_records(bytes)?
+            });
+        }
+        let mut reader = bytes;
+        let version = if format_version >= 6 {
+            let version: u16 =
+                bincode::deserialize_from(&mut reader).context("Archive header is truncated")?;
+            if version > HEADER_VERSION {
+                return Err(ArchiveError::UnsupportedVersion(format!(
+                    "Archive header was created by a newer rstf (header version {}, this rstf reads up to {})",
+                    version,
+                    HEADER_VERSION
+                ))
+                .into());
+            }
+            Some(version)
+        } else {
+            None
+        };
+        let header = Self::decode_fields(&mut reader, version)
+            .context("Archive header is truncated or malformed")?;
+        if version.is_some() && !reader.is_empty() {
+            return Err(anyhow!(
+                "Archive header has {} unexpected trailing byte(s)",
+                reader.len()
+            ));
         }
+        Ok(RstfHeader {
+            encoded_len: bytes.len(),
+            ..header
+        })
     }
 
-    fn flush_chunk(&mut self, final_chunk: bool) -> std::io::Result<()> {
-        if self.buffer.is_empty() && !final_chunk {
-            return Ok(());
+    /// Tagged records of a format 7 header
+    fn decode_records(bytes: &[u8]) -> Result<Self> {
+        let mut header = RstfHeader::default();
+        let mut seen = [false; 256];
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let (tag, value) = next_header_record(&mut rest)?;
+            let repeatable = matches!(tag, HEADER_MEMBER | HEADER_CLASS_LEVEL);
+            if std::mem::replace(&mut seen[tag as usize], true) && !repeatable {
+                return Err(anyhow!("Archive header repeats field {:#04x}", tag));
+            }
+            match tag {
+                HEADER_IS_DIR => header.is_dir = header_flag(tag, value)?,
+                HEADER_NAME => header.original_name = header_text(tag, value)?,
+                HEADER_SIZE => header.original_size = u64::from_le_bytes(header_value(tag, value)?),
+                HEADER_MEMBER => header.members.push(header_text(tag, value)?),
+                HEADER_CONTENTS_ONLY => header.contents_only = header_flag(tag, value)?,
+                HEADER_FILE_COUNT => {
+                    header.file_count = u64::from_le_bytes(header_value(tag, value)?)
+                }
+                HEADER_DIR_COUNT => {
+                    header.dir_count = u64::from_le_bytes(header_value(tag, value)?)
+                }
+                HEADER_CREATED_AT => {
+                    header.created_at = u64::from_le_bytes(header_value(tag, value)?)
+                }
+                HEADER_TOOL_VERSION => header.tool_version = header_text(tag, value)?,
+                HEADER_COMMENT => header.comment = header_text(tag, value)?,
+                HEADER_OWNER => header.owner = Some(decode_owner(value)?),
+                HEADER_MODE => header.mode = Some(u32::from_le_bytes(header_value(tag, value)?)),
+                HEADER_MTIME => header.mtime = Some(i64::from_le_bytes(header_value(tag, value)?)),
+                HEADER_LEVEL => header.level = Some(i32::from_le_bytes(header_value(tag, value)?)),
+                HEADER_CLASS_LEVEL => {
+                    let (level, name) = match value.split_first() {
+                        Some((0, name)) => (None, name),
+                        Some((1, rest)) if rest.len() >= 4 => {
+                            let (level, name) = rest.split_at(4);
+                            (Some(i32::from_le_bytes(header_value(tag, level)?)), name)
+                        }
+                        _ => return Err(anyhow!("Archive header has a malformed class level")),
+                    };
+                    header
+                        .class_levels
+                        .get_or_insert_with(Vec::new)
+                        .push((header_text(tag, name)?, level));
+                }
+                HEADER_TRAILER_LEN => {
+                    header.trailer_len = u32::from_le_bytes(header_value(tag, value)?)
+                }
+                HEADER_EXTENTS => {
+                    if value.len() % 16 != 0 {
+                        return Err(anyhow!("Archive header has malformed sparse extents"));
+                    }
+                    let extents = value
+                        .chunks_exact(16)
+                        .map(|pair| {
+                            let (offset, len) = pair.split_at(8);
+                            Ok((
+                                u64::from_le_bytes(header_value(tag, offset)?),
+                                u64::from_le_bytes(header_value(tag, len)?),
+                            ))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    header.extents = Some(extents);
+                }
+                HEADER_COMPRESSION => header.compression = header_value::<1>(tag, value)?[0],
+                HEADER_WINDOW_LOG => {
+                    header.window_log = Some(u32::from_le_bytes(header_value(tag, value)?))
+                }
+                HEADER_DICT_ID => {
+                    header.dict_id = Some(u32::from_le_bytes(header_value(tag, value)?))
+                }
+                HEADER_DICTIONARY => header.dictionary = Some(value.to_vec()),
+                HEADER_SEGMENTED => header.segmented = header_flag(tag, value)?,
+                HEADER_INDEX_SPAN => {
+                    header.index_span = Some(u64::from_le_bytes(header_value(tag, value)?))
+                }
+                HEADER_PADDED => header.padded = header_flag(tag, value)?,
+                tag if tag & HEADER_CRITICAL != 0 => {
+                    return Err(ArchiveError::UnsupportedVersion(format!(
+                        "Archive header uses field {:#04x}, which requires a newer rstf",
+                        tag
+                    ))
+                    .into())
+                }
+                // Informational fields from newer versions
+                _ => {}
+            }
         }
-        let ciphertext = self
-            .encryptor
-            .encrypt_next(self.buffer.as_slice())
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Encryption failed"))?;
+        Ok(header)
+    }
 
-        self.inner.write_all(&ciphertext)?;
-        self.buffer.clear();
-        Ok(())
+    /// Field groups up to `version`, or as many as there are bytes for (format 5 and older)
+    fn decode_fields(reader: &mut &[u8], version: Option<u16>) -> bincode::Result<Self> {
+        let has = |rest: &[u8], group: u16| version.map_or(!rest.is_empty(), |v| group <= v);
+        let mut header = RstfHeader {
+            is_dir: bincode::deserialize_from(&mut *reader)?,
+            original_name: bincode::deserialize_from(&mut *reader)?,
+            original_size: bincode::deserialize_from(&mut *reader)?,
+            ..Default::default()
+        };
+        if has(reader, 2) {
+            header.members = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 3) {
+            header.contents_only = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 4) {
+            header.file_count = bincode::deserialize_from(&mut *reader)?;
+            header.dir_count = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 5) {
+            header.created_at = bincode::deserialize_from(&mut *reader)?;
+            header.tool_version = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 6) {
+            header.comment = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 7) {
+            header.trailer_len = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 8) {
+            header.owner = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 9) {
+            header.mode = bincode::deserialize_from(&mut *reader)?;
+            header.mtime = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 10) {
+            header.extents = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 11) {
+            header.compression = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 12) {
+            header.window_log = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 13) {
+            header.dict_id = bincode::deserialize_from(&mut *reader)?;
+            header.dictionary = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 14) {
+            header.level = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 15) {
+            header.segmented = bincode::deserialize_from(&mut *reader)?;
+        }
+        if has(reader, 16) {
+            header.class_levels = bincode::deserialize_from(&mut *reader)?;
+        }
+        Ok(header)
     }
-}
 
-// Write Trait for EncryptedWriter
-impl<W: Write> Write for EncryptedWriter<W> {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let mut total_written = 0;
-        while total_written < buf.len() {
-            let space_left = CHUNK_SIZE - self.buffer.len();
-            let to_copy = min(space_left, buf.len() - total_written);
-            self.buffer
-                .extend_from_slice(&buf[total_written..total_written + to_copy]);
-            total_written += to_copy;
+    /// Codec the payload was written with, if this rstf knows its id
+    fn codec(&self) -> Result<Compression> {
+        Compression::from_id(self.compression).ok_or_else(|| {
+            ArchiveError::UnsupportedVersion(format!(
+                "Archive uses compression id {}, which requires a newer rstf",
+                self.compression
+            ))
+            .into()
+        })
+    }
 
-            if self.buffer.len() == CHUNK_SIZE {
-                self.flush_chunk(false)?;
-            }
+    /// Codec name for listings (`unknown (id 7)` for ids from newer versions)
+    fn codec_name(&self) -> String {
+        match Compression::from_id(self.compression) {
+            Some(codec) => codec.name().to_string(),
+            None => format!("unknown (id {})", self.compression),
         }
-        Ok(total_written)
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.flush_chunk(true)?;
-        self.inner.flush()
+    /// Codec and level (`zstd level 19`; archives before levels were recorded show the codec)
+    fn codec_summary(&self) -> String {
+        let codec = match self.level {
+            Some(level) => format!("{} level {}", self.codec_name(), level),
+            None => self.codec_name(),
+        };
+        if let Some(classes) = &self.class_levels {
+            let levels: Vec<String> = classes
+                .iter()
+                .map(|(class, level)| match level {
+                    Some(level) => format!("{} {}", class, level),
+                    None => format!("{} stored", class),
+                })
+                .collect();
+            format!("{}, adaptive: {}", codec, levels.join(", "))
+        } else if self.segmented {
+            format!("{}, incompressible files stored", codec)
+        } else {
+            codec
+        }
     }
 }
 
-// Drop Trait for EncryptedWriter
-impl<W: Write> Drop for EncryptedWriter<W> {
-    fn drop(&mut self) {
-        let _ = self.flush_chunk(true);
-    }
+// Header Record Reader (tag and value of the next record, advancing past it)
+fn next_header_record<'a>(rest: &mut &'a [u8]) -> Result<(u8, &'a [u8])> {
+    let truncated = || anyhow!("Archive header is truncated");
+    let (&tag, after_tag) = rest.split_first().ok_or_else(truncated)?;
+    let len_bytes: [u8; 4] = after_tag
+        .get(..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(truncated)?;
+    let len = u32::from_le_bytes(len_bytes) as usize;
+    let value = after_tag.get(4..4 + len).ok_or_else(truncated)?;
+    *rest = &after_tag[4 + len..];
+    Ok((tag, value))
 }
 
-struct DecryptedReader<R: Read> {
-    inner: R,
-    decryptor: DecryptorBE32<ChaCha20Poly1305>,
-    buffer: Vec<u8>,
-    offset: usize,
-    eof: bool,
+// Header Value Helpers (fixed-size values must have exactly their size)
+fn header_value<const N: usize>(tag: u8, value: &[u8]) -> Result<[u8; N]> {
+    value.try_into().map_err(|_| {
+        anyhow!(
+            "Archive header field {:#04x} is {} byte(s) long instead of {}",
+            tag,
+            value.len(),
+            N
+        )
+    })
 }
 
-// DecryptedReader Implementation
-impl<R: Read> DecryptedReader<R> {
-    fn new(inner: R, decryptor: DecryptorBE32<ChaCha20Poly1305>) -> Self {
-        Self {
-            inner,
-            decryptor,
-            buffer: Vec::new(),
-            offset: 0,
-            eof: false,
-        }
-    }
+fn header_flag(tag: u8, value: &[u8]) -> Result<bool> {
+    header_value::<0>(tag, value).map(|_| true)
 }
 
-// Read Trait for DecryptedReader
-impl<R: Read> Read for DecryptedReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.offset >= self.buffer.len() {
-            if self.eof {
-                return Ok(0);
-            }
-
-            let encrypted_chunk_size = CHUNK_SIZE + 16;
-            let mut encrypted_buf = vec![0u8; encrypted_chunk_size];
+fn header_text(tag: u8, value: &[u8]) -> Result<String> {
+    String::from_utf8(value.to_vec())
+        .map_err(|_| anyhow!("Archive header field {:#04x} is not valid UTF-8", tag))
+}
 
-            let mut read_bytes = 0;
-            while read_bytes < encrypted_chunk_size {
-                match self.inner.read(&mut encrypted_buf[read_bytes..]) {
-                    Ok(0) => break,
-                    Ok(n) => read_bytes += n,
-                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
-                    Err(e) => return Err(e),
-                }
-            }
+fn decode_owner(value: &[u8]) -> Result<FileOwner> {
+    let malformed = || anyhow!("Archive header has a malformed owner");
+    if value.len() < 12 {
+        return Err(malformed());
+    }
+    let word = |i: usize| u32::from_le_bytes([value[i], value[i + 1], value[i + 2], value[i + 3]]);
+    let names = &value[12..];
+    let user_len = word(8) as usize;
+    if user_len > names.len() {
+        return Err(malformed());
+    }
+    let (user, group) = names.split_at(user_len);
+    Ok(FileOwner {
+        uid: word(0),
+        gid: word(4),
+        user: header_text(HEADER_OWNER, user)?,
+        group: header_text(HEADER_OWNER, group)?,
+    })
+}
 
-            if read_bytes == 0 {
-                self.eof = true;
-                return Ok(0);
-            }
+// Stored owner of a file: numeric ids plus the names they had where it was packed
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+struct FileOwner {
+    uid: u32,
+    gid: u32,
+    user: String,
+    group: String,
+}
 
-            let chunk_to_decrypt = &encrypted_buf[..read_bytes];
+// Authenticated trailer sealed after the last payload chunk (format 2)
+#[derive(Debug, Clone, PartialEq)]
+struct Trailer {
+    /// BLAKE3 of the uncompressed payload
+    digest: [u8; 32],
+    payload_size: u64,
+}
 
-            let plaintext = self.decryptor.decrypt_next(chunk_to_decrypt).map_err(|_| {
-                std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Decryption failed (MAC Error)",
-                )
-            })?;
+// Trailer Encoding (algorithm id, digest, u64 LE payload size; indexed archives add the
+// u64 LE length of the chunk index, see index_len, and padded ones both that and the u64
+// LE length of the padding, see padding_len)
+impl Trailer {
+    const LEN: usize = 1 + 32 + 8;
+    const INDEXED_LEN: usize = Self::LEN + 8;
+    const PADDED_LEN: usize = Self::INDEXED_LEN + 8;
+    const BLAKE3: u8 = 1;
 
-            self.buffer = plaintext;
-            self.offset = 0;
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.push(Self::BLAKE3);
+        bytes.extend_from_slice(&self.digest);
+        bytes.extend_from_slice(&self.payload_size.to_le_bytes());
+        bytes
+    }
 
-            if read_bytes < encrypted_chunk_size {
-                self.eof = true;
-            }
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < Self::LEN {
+            return Err(anyhow!("Archive trailer is too short"));
+        }
+        if bytes[0] != Self::BLAKE3 {
+            return Err(anyhow!("Unknown digest algorithm {} in trailer", bytes[0]));
         }
+        Ok(Trailer {
+            digest: bytes[1..33].try_into()?,
+            payload_size: u64::from_le_bytes(bytes[33..41].try_into()?),
+        })
+    }
 
-        let available = self.buffer.len() - self.offset;
-        let to_copy = min(available, buf.len());
-        buf[..to_copy].copy_from_slice(&self.buffer[self.offset..self.offset + to_copy]);
-        self.offset += to_copy;
+    fn digest_string(&self) -> String {
+        format!("blake3:{}", to_hex(&self.digest))
+    }
 
-        Ok(to_copy)
+    /// Length of the chunk index sealed between the payload and the trailer (0 = none)
+    fn index_len(bytes: &[u8]) -> Result<u64> {
+        match bytes.get(Self::LEN..Self::INDEXED_LEN) {
+            Some(len) => Ok(u64::from_le_bytes(len.try_into()?)),
+            None => Ok(0),
+        }
     }
-}
 
-// Main Entry Point
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    match cli.command {
-        Commands::Pack {
-            input,
-            wipe,
-            level,
-            keyfile,
-        } => pack(input, wipe, level, keyfile),
-        Commands::Unpack { input, keyfile } => unpack(input, keyfile),
-        Commands::List { input, keyfile } => list(input, keyfile),
+    /// Length of the filler sealed after the payload and chunk index (0 = none)
+    fn padding_len(bytes: &[u8]) -> Result<u64> {
+        match bytes.get(Self::INDEXED_LEN..Self::PADDED_LEN) {
+            Some(len) => Ok(u64::from_le_bytes(len.try_into()?)),
+            None => Ok(0),
+        }
     }
 }
 
-// Pack Function
-fn pack(input_path: PathBuf, wipe: bool, level: i32, keyfile: Option<PathBuf>) -> Result<()> {
-    let salt: [u8; 16] = rand::thread_rng().gen();
-
-    let key = process_credentials(&salt, keyfile)?;
-
-    let mut output_path = input_path.clone();
-    if let Some(name) = input_path.file_name() {
-        let mut new_name = name.to_os_string();
-        new_name.push(".rstf");
-        output_path.set_file_name(new_name);
-    } else {
-        output_path.set_extension("rstf");
+// Padded Stream Length (Padmé: rounds up to a size whose low bits are zero, so the length
+// gives away O(log log n) bits instead of O(log n), for at most 12% more data)
+fn padme_len(len: u64) -> u64 {
+    if len < 2 {
+        return len;
     }
+    let exponent = 63 - len.leading_zeros();
+    let exponent_bits = 32 - exponent.leading_zeros();
+    let mask = (1u64 << (exponent - exponent_bits)) - 1;
+    len.saturating_add(mask) & !mask
+}
 
-    let output_file = File::create(&output_path).context("Failed to create output file")?;
-    let mut writer = BufWriter::with_capacity(CHUNK_SIZE, output_file);
-
-    let nonce: [u8; 7] = rand::thread_rng().gen();
-    writer.write_all(&salt)?;
-    writer.write_all(&nonce)?;
-
-    let metadata = fs::metadata(&input_path).context("Failed to read metadata")?;
-    let is_dir = metadata.is_dir();
-    let total_size = if is_dir { 0 } else { metadata.len() };
+// Chunk index of a --index pack, sealed in the stream right after the compressed
+// payload: where each independent zstd frame and each tar entry starts, so readers
+// can seek instead of decoding everything before what they want
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ChunkIndex {
+    /// (payload offset, compressed offset) of every frame, the first at (0, 0)
+    frames: Vec<(u64, u64)>,
+    /// Tar entry paths and the payload offset of their first header block
+    entries: Vec<(String, u64)>,
+    /// Where the payload chunks start in the file and their sealed length, the
+    /// index included (not stored)
+    payload_start: u64,
+    ciphertext_len: u64,
+    /// Length of the compressed payload, where the index begins (not stored)
+    packed_len: u64,
+}
 
-    let header = RstfHeader {
-        is_dir,
-        original_name: input_path
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string(),
-        original_size: total_size,
-    };
-    let header_bytes = bincode::serialize(&header)?;
-    let header_len = header_bytes.len() as u32;
+// Chunk Index Encoding (u32 LE frame count and u64 LE offset pairs, then u32 LE entry
+// count and per entry a u64 LE offset, a u32 LE path length and the UTF-8 path)
+impl ChunkIndex {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for &(offset, packed) in &self.frames {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            bytes.extend_from_slice(&packed.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for (path, offset) in &self.entries {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            bytes.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(path.as_bytes());
+        }
+        bytes
+    }
 
-    let key_struct = chacha20poly1305::Key::from_slice(&key);
-    let aead = ChaCha20Poly1305::new(key_struct);
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+            if rest.len() < len {
+                return Err(anyhow!("Chunk index is truncated"));
+            }
+            let (value, tail) = rest.split_at(len);
+            *rest = tail;
+            Ok(value)
+        }
+        let u32_at = |rest: &mut &[u8]| -> Result<u32> {
+            Ok(u32::from_le_bytes(take(rest, 4)?.try_into()?))
+        };
+        let u64_at = |rest: &mut &[u8]| -> Result<u64> {
+            Ok(u64::from_le_bytes(take(rest, 8)?.try_into()?))
+        };
 
-    let s_nonce = TipeNonce::from_slice(&nonce);
-    let encryptor = EncryptorBE32::from_aead(aead, s_nonce);
+        let mut rest = bytes;
+        let mut index = ChunkIndex::default();
+        for _ in 0..u32_at(&mut rest)? {
+            index.frames.push((u64_at(&mut rest)?, u64_at(&mut rest)?));
+        }
+        for _ in 0..u32_at(&mut rest)? {
+            let offset = u64_at(&mut rest)?;
+            let len = u32_at(&mut rest)? as usize;
+            let path = String::from_utf8(take(&mut rest, len)?.to_vec())
+                .context("Chunk index holds a path that is not UTF-8")?;
+            index.entries.push((path, offset));
+        }
+        if !rest.is_empty() || index.frames.first() != Some(&(0, 0)) {
+            return Err(anyhow!("Chunk index is malformed"));
+        }
+        Ok(index)
+    }
 
-    let mut crypto_writer = EncryptedWriter::new(writer, encryptor);
+    /// Payload offset of the first entry in or below one of `wanted` (normalized
+    /// paths), None when some of them are not indexed
+    fn first_entry(&self, wanted: &[String]) -> Option<u64> {
+        let mut first = u64::MAX;
+        for path in wanted {
+            let below = format!("{}/", path);
+            let offset = self
+                .entries
+                .iter()
+                .filter(|(entry, _)| {
+                    let entry = normalize_entry_path(entry);
+                    entry == *path || entry.starts_with(&below)
+                })
+                .map(|&(_, offset)| offset)
+                .min()?;
+            first = first.min(offset);
+        }
+        (first != u64::MAX).then_some(first)
+    }
 
-    crypto_writer.write_all(&header_len.to_le_bytes())?;
-    crypto_writer.write_all(&header_bytes)?;
+    /// Last frame starting at or before payload `offset`, as (payload, compressed) offsets
+    fn frame_before(&self, offset: u64) -> (u64, u64) {
+        let after = self.frames.partition_point(|&(start, _)| start <= offset);
+        self.frames[after.saturating_sub(1)]
+    }
+}
 
-    let mut zstd_writer = ZstdEncoder::new(crypto_writer, level)?;
-    zstd_writer.multithread(num_cpus::get() as u32)?;
+// Checksum footer in the clear after the sealed trailer (format 12): BLAKE3 of every
+// archive byte before it, so damage shows without the password
+#[derive(Debug, Clone, PartialEq)]
+struct ChecksumFooter {
+    /// Digest algorithm id; only BLAKE3 is written
+    algorithm: u8,
+    digest: [u8; 32],
+    /// Footer length as stored, so a longer future footer is still stripped whole
+    len: u64,
+}
 
-    println!("Packing {}...", input_path.display());
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")?
-        .progress_chars("#>-"));
+// Footer Encoding (algorithm id, digest, then u32 LE footer length and the magic at EOF)
+impl ChecksumFooter {
+    const MAGIC: &'static [u8; 8] = b"RSTFCSUM";
+    const LEN: usize = 1 + 32 + 4 + 8;
+    const BLAKE3: u8 = 1;
 
-    if is_dir {
-        let mut tar_builder = tar::Builder::new(&mut zstd_writer);
-        tar_builder.append_dir_all(&header.original_name, &input_path)?;
-        tar_builder.finish()?;
-        pb.finish_with_message("Directory packed");
-    } else {
-        let input_file = File::open(&input_path)?;
-        let mut input_with_pb = pb.wrap_read(input_file);
-        std::io::copy(&mut input_with_pb, &mut zstd_writer)?;
-        pb.finish_with_message("File packed");
+    fn blake3(digest: [u8; 32]) -> Self {
+        ChecksumFooter {
+            algorithm: Self::BLAKE3,
+            digest,
+            len: Self::LEN as u64,
+        }
     }
 
-    zstd_writer.finish()?;
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.push(self.algorithm);
+        bytes.extend_from_slice(&self.digest);
+        bytes.extend_from_slice(&(Self::LEN as u32).to_le_bytes());
+        bytes.extend_from_slice(Self::MAGIC);
+        bytes
+    }
 
-    if wipe {
-        print!(
-            "\nDelete original file/folder '{}'? (y/N): ",
-            input_path.display()
-        );
-        std::io::stdout().flush()?;
+    /// Footer ending a file of `file_size` bytes, None when it does not end with the
+    /// magic. Moves the file position
+    fn find(file: &mut File, file_size: u64) -> Result<Option<Self>> {
+        let tail = (4 + Self::MAGIC.len()) as u64;
+        if file_size < tail {
+            return Ok(None);
+        }
+        let mut end = [0u8; 12];
+        file.seek(SeekFrom::Start(file_size - tail))?;
+        file.read_exact(&mut end)
+            .context("Failed to read checksum footer")?;
+        if &end[4..] != Self::MAGIC {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(end[..4].try_into()?) as u64;
+        if len < Self::LEN as u64 || len > file_size {
+            return Err(anyhow!(
+                "Malformed checksum footer (length {} in a {} byte file)",
+                len,
+                file_size
+            ));
+        }
+        let mut start = [0u8; 33];
+        file.seek(SeekFrom::Start(file_size - len))?;
+        file.read_exact(&mut start)
+            .context("Failed to read checksum footer")?;
+        Ok(Some(ChecksumFooter {
+            algorithm: start[0],
+            digest: start[1..].try_into()?,
+            len,
+        }))
+    }
 
-        let mut input_string = String::new();
-        std::io::stdin()
-            .read_line(&mut input_string)
-            .context("Failed to read input")?;
+    /// Whether `tail` is the start of a footer that was cut off: the algorithm byte,
+    /// then (as far as it goes) any digest, the footer length and the magic
+    fn is_cut_short(tail: &[u8]) -> bool {
+        let mut fixed = vec![Self::BLAKE3];
+        fixed.extend_from_slice(&[0; 32]);
+        fixed.extend_from_slice(&(Self::LEN as u32).to_le_bytes());
+        fixed.extend_from_slice(Self::MAGIC);
+        !tail.is_empty()
+            && tail.len() < Self::LEN
+            && tail
+                .iter()
+                .zip(&fixed)
+                .enumerate()
+                .all(|(i, (byte, expected))| (1..33).contains(&i) || byte == expected)
+    }
 
-        if input_string.trim().to_lowercase() == "y" {
-            if is_dir {
-                fs::remove_dir_all(&input_path).context("Failed to wipe directory")?;
-            } else {
-                fs::remove_file(&input_path).context("Failed to wipe file")?;
-            }
-            println!("Original data wiped.");
-        } else {
-            println!("Wipe cancelled. Original data preserved.");
-        }
+    fn digest_string(&self) -> String {
+        format!("blake3:{}", to_hex(&self.digest))
     }
+}
 
-    Ok(())
+// Argon2id cost parameters (memory in KiB, passes, lanes); formats 1 and 2 always
+// used the argon2 crate defaults
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KdfParams {
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
 }
 
-// Unpack Function
-fn unpack(input_path: PathBuf, keyfile: Option<PathBuf>) -> Result<()> {
-    let mut input_file = File::open(&input_path).context("Failed to open .rstf")?;
+// Most Argon2 memory an archive may ask for (4 GiB), so a crafted prologue cannot
+// exhaust memory before the password is even checked
+const MAX_KDF_MEMORY_KIB: u32 = 4 * 1024 * 1024;
 
-    let mut salt = [0u8; 16];
-    let mut nonce = [0u8; 7];
-    input_file.read_exact(&mut salt)?;
-    input_file.read_exact(&mut nonce)?;
+// Most Argon2 passes an archive may ask for; rstf writes 2 or 4, and a crafted prologue
+// with millions would keep unpack busy for days before the password is checked
+const MAX_KDF_PASSES: u32 = 64;
 
-    let key = process_credentials(&salt, keyfile)?;
+// KdfParams Encoding (three u32 LE in the clear prologue)
+impl KdfParams {
+    const LEN: usize = 12;
 
-    let key_struct = chacha20poly1305::Key::from_slice(&key);
-    let aead = ChaCha20Poly1305::new(key_struct);
+    fn standard() -> Self {
+        let params = argon2::Params::default();
+        KdfParams {
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+        }
+    }
 
-    let s_nonce = TipeNonce::from_slice(&nonce);
-    let decryptor = DecryptorBE32::from_aead(aead, s_nonce);
+    /// 9 MiB with an extra pass to make up for it (--low-memory)
+    fn low_memory() -> Self {
+        KdfParams {
+            m_cost: 9 * 1024,
+            t_cost: 4,
+            p_cost: 1,
+        }
+    }
 
-    let mut crypto_reader = DecryptedReader::new(input_file, decryptor);
+    fn for_pack(args: &PackArgs) -> Self {
+        if args.low_memory {
+            Self::low_memory()
+        } else {
+            Self::standard()
+        }
+    }
 
-    let mut len_bytes = [0u8; 4];
-    crypto_reader
-        .read_exact(&mut len_bytes)
-        .context("Failed to decrypt header (Wrong password or Wrong Keyfile?)")?;
-    let header_len = u32::from_le_bytes(len_bytes) as usize;
+    fn to_bytes(self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[..4].copy_from_slice(&self.m_cost.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.t_cost.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.p_cost.to_le_bytes());
+        bytes
+    }
 
-    let mut header_data = vec![0u8; header_len];
-    crypto_reader.read_exact(&mut header_data)?;
-    let header: RstfHeader = bincode::deserialize(&header_data)?;
+    fn from_bytes(bytes: &[u8; Self::LEN]) -> Self {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        KdfParams {
+            m_cost: word(0),
+            t_cost: word(4),
+            p_cost: word(8),
+        }
+    }
 
-    println!("Unpacking: {}", header.original_name);
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        if self.m_cost > MAX_KDF_MEMORY_KIB {
+            return Err(anyhow!(
+                "Archive asks for {} of Argon2 memory (limit is {})",
+                HumanBytes(self.m_cost as u64 * 1024),
+                HumanBytes(MAX_KDF_MEMORY_KIB as u64 * 1024)
+            ));
+        }
+        if self.t_cost > MAX_KDF_PASSES {
+            return Err(anyhow!(
+                "Archive asks for {} Argon2 passes (limit is {})",
+                self.t_cost,
+                MAX_KDF_PASSES
+            ));
+        }
+        let params = argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, None)
+            .map_err(|e| anyhow!("Invalid Argon2 parameters in the archive: {}", e))?;
+        Ok(Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            params,
+        ))
+    }
+}
 
-    let mut zstd_reader = ZstdDecoder::new(crypto_reader)?;
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+// Default is only a base for settings built in code (repack); clap fills every field
+#[derive(Args, Default, Clone)]
+struct PackArgs {
+    /// Files or directories to pack (several inputs produce one tar archive),
+    /// or `-` to read the payload from stdin
+    #[arg(required_unless_present = "files_from")]
+    inputs: Vec<PathBuf>,
+    #[arg(long, conflicts_with = "files_from")]
+    wipe: bool,
+    /// Compression level: zstd runs from -131072 (fastest) to 22, xz from 0
+    /// to 9 [default: 5, or the --preset level]
+    #[arg(long, allow_negative_numbers = true, value_parser = parse_level)]
+    level: Option<i32>,
+    /// Named level and long-mode combination; explicit --level and --long win
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+    /// Payload compression algorithm
+    #[arg(long, value_enum, value_name = "ALGORITHM", default_value_t = Compression::Zstd)]
+    compression: Compression,
+    /// Skip compression entirely (same as `--compression none`), for media and
+    /// other data that is already compressed
+    #[arg(long, conflicts_with = "compression")]
+    store: bool,
+    /// zstd long-distance matching over a 2^WINDOW_LOG byte window (default 27,
+    /// 128 MiB) for large files with distant repeats. Capped at 30 (1 GiB):
+    /// unpacking needs about the window size in memory
+    #[arg(
+        long,
+        value_name = "WINDOW_LOG",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "27",
+        value_parser = clap::value_parser!(u32).range(10..=MAX_WINDOW_LOG as i64)
+    )]
+    long: Option<u32>,
+    /// Compress with a zstd dictionary (see `rstf train-dict`); unpacking then
+    /// needs the same file via --dict unless --embed-dict is given
+    #[arg(long, value_name = "FILE")]
+    dict: Option<PathBuf>,
+    /// Store the --dict dictionary in the encrypted header so the archive is
+    /// self-contained
+    #[arg(long, requires = "dict")]
+    embed_dict: bool,
+    /// Compression worker threads: 0 uses every core, 1 stays single-threaded.
+    /// With --each this is the number of archives written at once instead
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    threads: usize,
+    /// Plaintext bytes per encrypted chunk, a power of two from 4K to 8M. Each
+    /// chunk costs a 16-byte tag: larger chunks suit big archives on fast
+    /// disks, smaller ones streaming
+    #[arg(long, value_name = "SIZE", default_value = "64K", value_parser = parse_chunk_size)]
+    chunk_size: usize,
+    /// AEAD the chunks are sealed with (recorded in the clear prologue)
+    #[arg(long, value_enum, default_value_t = Cipher::ChaCha20)]
+    cipher: Cipher,
+    /// Keep peak memory low for small devices: Argon2 with 9 MiB instead of
+    /// 19 MiB (recorded in the archive), one compression thread, a zstd window
+    /// of at most 1 MiB and smaller read buffers, for a peak of about 16 MiB.
+    /// --preset max keeps its level but not its long window
+    #[arg(long, conflicts_with = "long")]
+    low_memory: bool,
+    /// Keep peak memory under SIZE (e.g. 256M): threads and then the level are
+    /// lowered until the estimate fits, unless they were given explicitly, in
+    /// which case settings that do not fit are an error
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    memory_limit: Option<u64>,
+    /// Store files that do not compress (media, archives, encrypted data)
+    /// without running them through zstd, judged by a trial compression of
+    /// their first 64 KiB. A single-file input is stored whole; stdin is
+    /// always compressed
+    #[arg(long, conflicts_with = "store")]
+    smart_compress: bool,
+    /// Compressed size, as a percentage of the sample, at or above which
+    /// --smart-compress stores a file
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 95,
+        requires = "smart_compress",
+        value_parser = clap::value_parser!(u8).range(1..=100)
+    )]
+    smart_threshold: u8,
+    /// Choose the zstd level by file type: text and source at level 19 (or a
+    /// higher --level), media and archives that are compressed already
+    /// stored, everything else at --level. Directory packs group their files
+    /// by type; a single-file input takes its type's setting
+    #[arg(long, conflicts_with = "store")]
+    adaptive: bool,
+    /// Write a chunk index so `unpack --entry` and `cat` can seek instead of
+    /// decoding everything before the entry. zstd restarts its frame every
+    /// SPAN of payload (default 4M), which costs a little compression
+    #[arg(
+        long,
+        value_name = "SPAN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "4M",
+        value_parser = parse_size,
+        conflicts_with_all = ["smart_compress", "adaptive"]
+    )]
+    index: Option<u64>,
+    /// Pad the archive with encrypted filler so its size only narrows the
+    /// original down to a range (Padmé rounding, at most 12% larger)
+    #[arg(long)]
+    pad: bool,
+    /// Contents of --dict, read once before packing
+    #[arg(skip)]
+    dictionary: Option<Vec<u8>>,
+    #[arg(long, short = 'k')]
+    keyfile: Option<PathBuf>,
+    /// Do not encrypt: no password is asked and anyone can read the archive.
+    /// Chunks keep checksums, so damage and truncation are still detected. For
+    /// artifacts that travel over an already encrypted channel
+    #[arg(long, conflicts_with_all = ["keyfile", "cipher"])]
+    no_encrypt: bool,
+    /// Output file, or directory to place `<input>.rstf` in
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
+    /// Replace an existing archive at the output path
+    #[arg(long)]
+    overwrite: bool,
+    /// Allow packing a file that is already an .rstf archive
+    #[arg(long)]
+    force: bool,
+    /// Insert the current time into the archive name (strftime format)
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "%Y-%m-%dT%H%M")]
+    timestamp: Option<String>,
+    /// Use UTC instead of local time for --timestamp
+    #[arg(long, requires = "timestamp")]
+    utc: bool,
+    /// Read paths to pack from FILE (`-` for stdin); listed directories are not recursed
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+    /// Paths in --files-from are NUL-delimited (e.g. `find -print0`)
+    #[arg(long, short = '0', requires = "files_from")]
+    null: bool,
+    /// Directory that --files-from paths are relative to and stored under
+    #[arg(long, value_name = "DIR", requires = "files_from")]
+    base_dir: Option<PathBuf>,
+    /// Skip --files-from paths that do not exist instead of aborting
+    #[arg(long, requires = "files_from")]
+    ignore_missing: bool,
+    /// Name stored in the archive when packing stdin
+    #[arg(long)]
+    name: Option<String>,
+    /// Pack every input (or every item in a lone directory) into its own archive
+    #[arg(long)]
+    each: bool,
+    /// Store a directory's contents without wrapping them in its name
+    #[arg(long, conflicts_with_all = ["files_from", "each"])]
+    contents_only: bool,
+    /// Note stored in the encrypted header and shown by `list`
+    #[arg(long, value_name = "TEXT")]
+    comment: Option<String>,
+    /// Read the comment from a UTF-8 text file
+    #[arg(long, value_name = "PATH", conflicts_with = "comment")]
+    comment_file: Option<PathBuf>,
+    /// Leave out paths matching GLOB (repeatable); matched against each file name
+    /// and the path inside the input, and a matching directory is skipped whole
+    #[arg(long, value_name = "GLOB", conflicts_with = "wipe")]
+    exclude: Vec<String>,
+    /// Leave out what git would ignore (nested .gitignore files, .git/info/exclude
+    /// and the global excludes file)
+    #[arg(long, conflicts_with = "wipe")]
+    gitignore: bool,
+    /// Leave out version control metadata (.git, .hg, .svn, .bzr, CVS)
+    #[arg(long, conflicts_with = "wipe")]
+    exclude_vcs: bool,
+    /// Pack the files and directories symlinks point to instead of the links
+    /// (broken and looping links are reported and skipped)
+    #[arg(long, short = 'L')]
+    dereference: bool,
+    /// Leave symlinks out of directory archives
+    #[arg(long, conflicts_with_all = ["dereference", "wipe"])]
+    skip_symlinks: bool,
+    /// Stay on the filesystem of each input directory, skipping mount points below
+    /// it (Unix only; elsewhere it only warns)
+    #[arg(long, short = 'x', conflicts_with = "wipe")]
+    one_file_system: bool,
+    /// Leave out regular files bigger than SIZE (e.g. `500M`, `2G`)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with = "wipe")]
+    exclude_larger_than: Option<u64>,
+    /// Leave out regular files smaller than SIZE
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with = "wipe")]
+    exclude_smaller_than: Option<u64>,
+    /// Only pack files modified after TIME: RFC 3339, a local `YYYY-MM-DD`, or an
+    /// age like `7d` (units s, m, h, d, w); directories are always kept
+    #[arg(long, value_name = "TIME", value_parser = parse_time, conflicts_with = "wipe")]
+    newer_than: Option<std::time::SystemTime>,
+    /// Like --newer-than, using the modification time of FILE (e.g. the last backup)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["newer_than", "wipe"])]
+    newer_than_file: Option<PathBuf>,
+    /// Only pack files modified before TIME (same formats as --newer-than)
+    #[arg(long, value_name = "TIME", value_parser = parse_time, conflicts_with = "wipe")]
+    older_than: Option<std::time::SystemTime>,
+    /// Print each entry as it is packed (-vv adds sizes and timings) and list
+    /// the files left out by the size filters
+    #[arg(long, short = 'v', action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Skip files and directories that cannot be read instead of aborting, report
+    /// them at the end and exit with status 9
+    #[arg(long, conflicts_with = "wipe")]
+    skip_unreadable: bool,
+    /// Store extended attributes of directory archive entries as PAX records
+    /// (Unix builds with the `xattrs` feature)
+    #[arg(long)]
+    xattrs: bool,
+    /// Store POSIX access and default ACLs of directory archive entries (Linux
+    /// builds with the `acls` feature; elsewhere a warning and no-op)
+    #[arg(long)]
+    acls: bool,
+    /// Skip the holes of sparse files instead of storing their zeros; files are
+    /// read densely where the filesystem cannot report holes
+    #[arg(long)]
+    sparse: bool,
+    /// Store every hard-linked file in full instead of linking later occurrences
+    /// to the first one
+    #[arg(long)]
+    no_hard_links: bool,
+    /// Store FIFOs and device nodes as tar special entries (recreated on unpack;
+    /// device nodes only when extracting as root). Sockets are always skipped
+    #[arg(long)]
+    special_files: bool,
+    /// Make the payload byte-identical across packs of the same tree: sorted
+    /// entries, uid/gid 0 without names, mtimes clamped to SOURCE_DATE_EPOCH
+    /// (when set) and single-threaded zstd. The salt and nonce stay random, so
+    /// the archive bytes still differ; compare the stored digests instead
+    #[arg(long)]
+    reproducible: bool,
+    /// Store only the numeric uid/gid of entries, without the user and group names
+    #[arg(long)]
+    numeric_owner: bool,
+    /// SOURCE_DATE_EPOCH, read by --reproducible
+    #[arg(skip)]
+    source_date_epoch: Option<u64>,
+    /// Tar header format of directory archives; pax stores any path length and
+    /// size, ustar suits old readers
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = TarFormat::Pax)]
+    tar_format: TarFormat,
+    /// Re-read the finished archive with the same key and check its payload
+    /// against the digest taken while packing
+    #[arg(long)]
+    verify: bool,
+    /// Hide the progress bar and status lines (warnings are still printed)
+    #[arg(long, short = 'q')]
+    quiet: bool,
+    /// Print the pack summary as a JSON document on stdout instead of text
+    ///
+    /// Schema: {"archive": string, "input_size": int, "archive_size": int,
+    /// "elapsed_secs": float, "bytes_per_sec": int, "digest": string,
+    /// "bypassed_size": int, "class_sizes": {"text": int, "other": int,
+    /// "compressed": int}}, class_sizes only with --adaptive
+    #[arg(long, conflicts_with = "each")]
+    json: bool,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+enum Compression {
+    /// Zstandard at --level 1-22 (the default)
+    #[default]
+    Zstd,
+    /// LZ4 frames: fast, lighter compression, --level is ignored (`lz4` feature)
+    Lz4,
+    /// xz/LZMA2 at --level 0-9: slow, smallest output for text (`xz` feature)
+    Xz,
+    /// Store the payload uncompressed, e.g. for media that is already compressed
+    None,
+}
+
+// Compression Codec Ids (stored in the encrypted header, never reordered)
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::Zstd => 0,
+            Compression::Lz4 => 1,
+            Compression::Xz => 2,
+            Compression::None => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::Zstd),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Xz),
+            3 => Some(Compression::None),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+            Compression::Xz => "xz",
+            Compression::None => "none",
+        }
+    }
+
+    /// Fails for codecs left out of this build by their Cargo feature
+    fn check_available(self) -> Result<()> {
+        let built = match self {
+            Compression::Lz4 => cfg!(feature = "lz4"),
+            Compression::Xz => cfg!(feature = "xz"),
+            Compression::Zstd | Compression::None => true,
+        };
+        if built {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} compression is not available: this rstf was built without the `{}` feature",
+                self.name(),
+                self.name()
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+enum Cipher {
+    /// ChaCha20-Poly1305 with a 7-byte random stream nonce (the default, readable
+    /// by every rstf)
+    #[default]
+    #[value(name = "chacha20")]
+    ChaCha20,
+    /// XChaCha20-Poly1305 with a 19-byte random stream nonce, leaving no realistic
+    /// chance of two archives sharing a nonce
+    #[value(name = "xchacha20")]
+    XChaCha20,
+    /// AES-256-GCM with a 7-byte random stream nonce, faster on CPUs with AES-NI
+    /// (`aes-gcm` feature)
+    #[value(name = "aes256-gcm")]
+    Aes256Gcm,
+}
+
+// Cipher Ids (stored in the prologue from format 11, never reordered)
+impl Cipher {
+    fn id(self) -> u8 {
+        match self {
+            Cipher::ChaCha20 => 0,
+            Cipher::XChaCha20 => 1,
+            Cipher::Aes256Gcm => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Cipher::ChaCha20),
+            1 => Ok(Cipher::XChaCha20),
+            2 => Ok(Cipher::Aes256Gcm),
+            _ => Err(ArchiveError::UnsupportedVersion(format!(
+                "Archive uses cipher id {} and requires a newer rstf",
+                id
+            ))
+            .into()),
+        }
+    }
+
+    /// STREAM nonce prefix: the AEAD nonce minus the 4-byte counter and last-chunk flag
+    fn nonce_len(self) -> usize {
+        match self {
+            Cipher::ChaCha20 | Cipher::Aes256Gcm => 7,
+            Cipher::XChaCha20 => 19,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Cipher::ChaCha20 => "ChaCha20-Poly1305",
+            Cipher::XChaCha20 => "XChaCha20-Poly1305",
+            Cipher::Aes256Gcm => "AES-256-GCM",
+        }
+    }
+
+    /// Fails for ciphers left out of this build by their Cargo feature
+    fn check_available(self) -> Result<()> {
+        if self == Cipher::Aes256Gcm && !cfg!(feature = "aes-gcm") {
+            return Err(anyhow!(
+                "{} is not available: this rstf was built without the `aes-gcm` feature",
+                self.name()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+enum TarFormat {
+    /// POSIX ustar only: paths up to 255 bytes, files under 8 GiB
+    Ustar,
+    /// GNU headers with long-name entries
+    Gnu,
+    /// ustar headers plus PAX records for whatever does not fit
+    #[default]
+    Pax,
+}
+
+#[derive(Args)]
+struct RepackArgs {
+    /// Archive to recompress
+    input: PathBuf,
+    /// Where to write the new archive; without it --replace repacks in place
+    #[arg(long, short = 'o', required_unless_present = "replace")]
+    output: Option<PathBuf>,
+    #[arg(long, short = 'k')]
+    keyfile: Option<PathBuf>,
+    /// Delete the old archive once the new one is written and checked
+    #[arg(long)]
+    replace: bool,
+    /// Replace an existing file at --output
+    #[arg(long)]
+    overwrite: bool,
+    /// zstd dictionary the old archive was packed with (unless it was embedded)
+    #[arg(long, value_name = "FILE")]
+    old_dict: Option<PathBuf>,
+    /// Compression level for the new archive (see `pack --level`)
+    #[arg(long, allow_negative_numbers = true, value_parser = parse_level)]
+    level: Option<i32>,
+    /// Named level and long-mode combination; explicit --level and --long win
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+    /// Payload compression algorithm of the new archive
+    #[arg(long, value_enum, value_name = "ALGORITHM", default_value_t = Compression::Zstd)]
+    compression: Compression,
+    /// Store the payload uncompressed (same as `--compression none`)
+    #[arg(long, conflicts_with = "compression")]
+    store: bool,
+    /// zstd long-distance matching over a 2^WINDOW_LOG byte window (see `pack --long`)
+    #[arg(
+        long,
+        value_name = "WINDOW_LOG",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "27",
+        value_parser = clap::value_parser!(u32).range(10..=MAX_WINDOW_LOG as i64)
+    )]
+    long: Option<u32>,
+    /// Compress the new archive with a zstd dictionary
+    #[arg(long, value_name = "FILE")]
+    dict: Option<PathBuf>,
+    /// Store the --dict dictionary in the new archive's encrypted header
+    #[arg(long, requires = "dict")]
+    embed_dict: bool,
+    /// Compression worker threads: 0 uses every core
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    threads: usize,
+    /// Plaintext bytes per encrypted chunk of the new archive (4K to 8M)
+    #[arg(long, value_name = "SIZE", default_value = "64K", value_parser = parse_chunk_size)]
+    chunk_size: usize,
+    /// AEAD the new archive's chunks are sealed with (see `pack --cipher`)
+    #[arg(long, value_enum, default_value_t = Cipher::ChaCha20)]
+    cipher: Cipher,
+    /// Write the new archive unencrypted (see `pack --no-encrypt`)
+    #[arg(long, conflicts_with = "cipher")]
+    no_encrypt: bool,
+    /// Lighter Argon2 and zstd settings for small devices (see `pack --low-memory`)
+    #[arg(long, conflicts_with = "long")]
+    low_memory: bool,
+    /// Re-read the whole new archive and check its payload digest, not only
+    /// its final chunk
+    #[arg(long)]
+    verify: bool,
+    /// Hide the progress bar and status lines
+    #[arg(long, short = 'q')]
+    quiet: bool,
+}
+
+#[derive(Args)]
+struct UnpackArgs {
+    /// Archives to unpack; directories mean every .rstf directly inside
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    #[arg(long, short = 'k')]
+    keyfile: Option<PathBuf>,
+    /// Directory to extract into (created if missing), or `-` for stdout
+    #[arg(long = "to", short = 'C', default_value = ".")]
+    dest: PathBuf,
+    /// Overwrite existing files in the destination (same as --on-conflict overwrite)
+    #[arg(long)]
+    force: bool,
+    /// What to do when an output path already exists
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value_t = ConflictPolicy::Abort,
+        conflicts_with = "force"
+    )]
+    on_conflict: ConflictPolicy,
+    /// Name suffix used by --on-conflict rename; `{n}` counts up from 1
+    #[arg(long, value_name = "TEMPLATE", default_value = ".{n}")]
+    conflict_suffix: String,
+    /// Extract under a different file or top-level directory name
+    #[arg(long = "as", value_name = "NAME")]
+    rename: Option<String>,
+    /// Write the decrypted payload to stdout (directory archives as a raw tar stream)
+    #[arg(long, conflicts_with_all = ["dest", "rename"])]
+    stdout: bool,
+    /// Extract directory archives into a new directory named after the archive file
+    #[arg(long)]
+    mkdir: bool,
+    /// Delete the .rstf after it has been fully extracted and verified
+    #[arg(long)]
+    remove_archive: bool,
+    /// Do not ask for confirmation before --remove-archive deletes anything
+    #[arg(long, short = 'y', requires = "remove_archive")]
+    yes: bool,
+    /// Only extract this path from a directory archive (repeatable; directories
+    /// include their contents)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["stdout", "remove_archive"])]
+    entry: Vec<String>,
+    /// Only extract entries whose path matches GLOB (repeatable)
+    #[arg(long, value_name = "GLOB", conflicts_with = "stdout")]
+    include: Vec<String>,
+    /// Skip entries whose path matches GLOB (repeatable, wins over --include)
+    #[arg(long, value_name = "GLOB", conflicts_with = "stdout")]
+    exclude: Vec<String>,
+    /// Drop the first N path elements of every directory archive entry
+    /// (filters and --entry still match the full stored path)
+    #[arg(long, value_name = "N", default_value_t = 0, conflicts_with_all = ["stdout", "rename"])]
+    strip_components: usize,
+    /// Decrypt and verify everything but only print what would be written where
+    #[arg(long, conflicts_with_all = ["stdout", "remove_archive"])]
+    dry_run: bool,
+    /// Ask before overwriting each existing file (needs a terminal on stdin)
+    #[arg(long, short = 'i', conflicts_with_all = ["force", "on_conflict", "stdout", "dry_run"])]
+    interactive: bool,
+    /// Extract entries with absolute paths or `..` components where they point,
+    /// even outside the destination
+    #[arg(long)]
+    allow_unsafe_paths: bool,
+    /// Extract symlinks even when they point outside the destination
+    #[arg(long, conflicts_with = "skip_symlinks")]
+    keep_symlinks: bool,
+    /// Leave symlinks out of the extraction entirely
+    #[arg(long)]
+    skip_symlinks: bool,
+    /// Stop once more than BYTES would be extracted (e.g. 512M, 20G) [default: the
+    /// recorded size plus 25%, at least 64 MiB more; no limit if the size is unknown]
+    #[arg(long, value_name = "BYTES", value_parser = parse_size)]
+    max_extract_size: Option<u64>,
+    /// Stop once the archive holds more than N entries [default: the recorded file
+    /// and directory count plus 25% and 1000]
+    #[arg(long, value_name = "N")]
+    max_entries: Option<u64>,
+    /// Confine the process to the destination and the archives with Landlock and
+    /// seccomp before any payload is decrypted (Linux builds with the `sandbox` feature)
+    #[arg(long, conflicts_with = "remove_archive")]
+    sandbox: bool,
+    /// Leave partially written output in place when extraction fails
+    #[arg(long)]
+    keep_partial: bool,
+    /// Give extracted files and directories the current time instead of the
+    /// modification times stored in the archive
+    #[arg(long)]
+    no_preserve_mtimes: bool,
+    /// Restore stored permission bits exactly, ignoring the umask (setuid and
+    /// setgid bits are only kept when running as root)
+    #[arg(short = 'p', long)]
+    preserve_permissions: bool,
+    /// Restore owners from the stored uid/gid without looking up the stored user
+    /// and group names (ownership is only restored when running as root)
+    #[arg(long)]
+    numeric_owner: bool,
+    /// Extract even when the destination filesystem looks too full for the archive
+    #[arg(long)]
+    no_space_check: bool,
+    /// Restore extended attributes stored with --xattrs, warning about any the
+    /// destination refuses (Unix builds with the `xattrs` feature)
+    #[arg(long)]
+    xattrs: bool,
+    /// Restore POSIX ACLs stored with --acls, reporting entries where the
+    /// destination refuses them (Linux builds with the `acls` feature)
+    #[arg(long)]
+    acls: bool,
+    /// Print each entry as it is extracted (-vv adds sizes and timings)
+    #[arg(long, short = 'v', action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// zstd dictionary the archives were packed with (unless it was embedded)
+    #[arg(long, value_name = "FILE")]
+    dict: Option<PathBuf>,
+    /// Refuse archives whose key derivation or zstd window would take more
+    /// than SIZE of memory (e.g. 256M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    memory_limit: Option<u64>,
+    /// Unpack archives that have other data appended after their end (such as
+    /// a second archive), ignoring that data instead of refusing the archive
+    #[arg(long)]
+    allow_trailing: bool,
+    /// Replace payload chunks that fail authentication with zeros and keep going,
+    /// then list the damaged regions; the output is incomplete and the exit status
+    /// is 3 (one archive at a time; implies --keep-partial). Compressed archives are
+    /// only recovered past the damage when packed with --index
+    #[arg(long, conflicts_with_all = ["remove_archive", "dry_run"])]
+    salvage: bool,
+}
+
+impl PackArgs {
+    fn level(&self) -> i32 {
+        self.level.unwrap_or(DEFAULT_LEVEL)
+    }
+}
+
+// Level used without --level or --preset
+const DEFAULT_LEVEL: i32 = 5;
+
+// Level Argument Parser (the zstd range; xz's narrower one is checked once the codec is known)
+fn parse_level(value: &str) -> std::result::Result<i32, String> {
+    let level: i32 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a level", value))?;
+    let range = zstd::compression_level_range();
+    if !range.contains(&level) {
+        return Err(format!(
+            "levels run from {} to {} (negative levels trade ratio for speed)",
+            range.start(),
+            range.end()
+        ));
+    }
+    Ok(level)
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Preset {
+    /// zstd level 1
+    Fast,
+    /// zstd level 9
+    Balanced,
+    /// zstd level 19 with a 128 MiB long-distance window
+    Max,
+}
+
+impl Preset {
+    /// Level and --long window log the preset stands for
+    fn settings(self) -> (i32, Option<u32>) {
+        match self {
+            Preset::Fast => (1, None),
+            Preset::Balanced => (9, None),
+            Preset::Max => (19, Some(27)),
+        }
+    }
+}
+
+impl UnpackArgs {
+    fn conflict_policy(&self) -> ConflictPolicy {
+        if self.force {
+            ConflictPolicy::Overwrite
+        } else {
+            self.on_conflict
+        }
+    }
+
+    // Decompression-bomb limits, derived from what the header recorded unless given
+    fn limits(&self, header: &RstfHeader) -> (Option<u64>, Option<u64>) {
+        let size = header.original_size;
+        let max_size = self
+            .max_extract_size
+            .or((size > 0).then(|| size.saturating_add((size / 4).max(64 * 1024 * 1024))));
+        let count = header.file_count + header.dir_count;
+        let max_entries = self
+            .max_entries
+            .or((count > 0).then(|| count.saturating_add(count / 4 + 1000)));
+        (max_size, max_entries)
+    }
+
+    // Output of a failed extraction is kept with --keep-partial, and always with --salvage
+    fn keeps_partial(&self) -> bool {
+        self.keep_partial || self.salvage
+    }
+
+    // Permission bits kept from stored modes: all of them with -p, else minus the umask
+    fn mode_mask(&self) -> u32 {
+        #[cfg(unix)]
+        {
+            if !self.preserve_permissions {
+                return 0o777 & !process_umask();
+            }
+            // SAFETY: geteuid has no preconditions and cannot fail
+            if unsafe { libc::geteuid() } == 0 {
+                0o7777
+            } else {
+                0o1777
+            }
+        }
+        #[cfg(not(unix))]
+        0o777
+    }
+}
+
+// Umask Helper (umask(2) can only be read by setting it, so it is put straight back)
+#[cfg(unix)]
+fn process_umask() -> u32 {
+    // SAFETY: umask has no preconditions; nothing creates files between the two calls
+    let mask = unsafe { libc::umask(0) };
+    unsafe { libc::umask(mask) };
+    mask as u32
+}
+
+// Time Argument Parser (RFC 3339, a local `YYYY-MM-DD`, or an age like `7d`)
+fn parse_time(value: &str) -> std::result::Result<std::time::SystemTime, String> {
+    let value = value.trim();
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.into());
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return date
+            .and_hms_opt(0, 0, 0)
+            .and_then(|t| t.and_local_timezone(chrono::Local).earliest())
+            .map(Into::into)
+            .ok_or_else(|| format!("'{}' is not a valid local date", value));
+    }
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+    let number: u64 = digits
+        .parse()
+        .map_err(|_| format!("'{}' is not a date or an age", value))?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "unknown age unit in '{}' (use s, m, h, d or w)",
+                value
+            ))
+        }
+    };
+    let age = std::time::Duration::from_secs(number.saturating_mul(unit_seconds));
+    std::time::SystemTime::now()
+        .checked_sub(age)
+        .ok_or_else(|| format!("'{}' reaches too far back", value))
+}
+
+// Size Argument Parser (`4096`, `512K`, `20G`, `1TiB`; binary multiples)
+fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+    let number: u64 = digits
+        .parse()
+        .map_err(|_| format!("'{}' is not a size", value))?;
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => return Err(format!("unknown size unit in '{}'", value)),
+    };
+    number
+        .checked_mul(1u64 << shift)
+        .ok_or_else(|| format!("'{}' is too large", value))
+}
+
+// Chunk Size Argument Parser (a size that is a power of two within the format's bounds)
+fn parse_chunk_size(value: &str) -> std::result::Result<usize, String> {
+    let size = parse_size(value)?;
+    let range = (1u64 << MIN_CHUNK_LOG)..=(1u64 << MAX_CHUNK_LOG);
+    if !size.is_power_of_two() || !range.contains(&size) {
+        return Err(format!(
+            "chunk sizes are powers of two from {} to {}",
+            HumanBytes(*range.start()),
+            HumanBytes(*range.end())
+        ));
+    }
+    Ok(size as usize)
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ConflictPolicy {
+    /// Stop with an error
+    Abort,
+    /// Replace the existing file
+    Overwrite,
+    /// Keep both, writing the new file as `name.1`, `name.2`, ...
+    Rename,
+}
+
+#[derive(Args)]
+struct ListArgs {
+    input: PathBuf,
+    #[arg(long, short = 'k')]
+    keyfile: Option<PathBuf>,
+    /// Show mode, owner, exact size and modification time of each entry
+    #[arg(long, short = 'l')]
+    long: bool,
+    /// Print a single JSON document on stdout instead of text
+    ///
+    /// Schema: {"original_name": string, "is_dir": bool, "original_size": int,
+    /// "compressed_size": int, "archive_size": int, "file_count": int,
+    /// "dir_count": int, "created_at": int, "tool_version": string,
+    /// "comment": string, "members": [string], "compression": string,
+    /// "level": int|null, "entries": [{"path": string,
+    /// "is_dir": bool, "size": int, "mtime": int}]}. Times are Unix seconds
+    /// (0 when not recorded); `entries` is only present for directory archives.
+    #[arg(long, conflicts_with = "long")]
+    json: bool,
+    /// Only show entries whose path matches GLOB (repeatable, `*` also matches `/`)
+    #[arg(long, visible_alias = "filter", value_name = "GLOB")]
+    include: Vec<String>,
+    /// Hide entries whose path matches GLOB (repeatable, wins over --include)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Sort the entry listing
+    ///
+    /// Sorting keeps the path, size and mtime of every matching entry in memory
+    /// (on the order of 100 bytes each, so ~100 MB per million entries). With
+    /// --limit only the top N entries are retained.
+    #[arg(long, value_enum, value_name = "KEY")]
+    sort: Option<SortKey>,
+    /// Reverse the sort order (largest or newest first)
+    #[arg(long, requires = "sort")]
+    reverse: bool,
+    /// Show at most N entries
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+    /// zstd dictionary the archive was packed with (unless it was embedded)
+    #[arg(long, value_name = "FILE")]
+    dict: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SortKey {
+    Name,
+    Size,
+    Mtime,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    Pack(PackArgs),
+    Unpack(UnpackArgs),
+    List(ListArgs),
+    /// Recompress an archive with new settings, without extracting it to disk
+    Repack(RepackArgs),
+    /// Show the container's format and cryptographic parameters
+    Info {
+        input: PathBuf,
+        #[arg(long, short = 'k')]
+        keyfile: Option<PathBuf>,
+        /// Only show what is stored in the clear (skips the password prompt)
+        #[arg(long, conflicts_with = "keyfile")]
+        clear_only: bool,
+    },
+    /// Print the payload digest stored in an archive
+    Checksum {
+        input: PathBuf,
+        #[arg(long, short = 'k')]
+        keyfile: Option<PathBuf>,
+        /// Decrypt and re-hash the payload to confirm the stored digest
+        #[arg(long)]
+        recompute: bool,
+        /// zstd dictionary the archive was packed with (for --recompute)
+        #[arg(long, value_name = "FILE")]
+        dict: Option<PathBuf>,
+    },
+    /// Check archives for damage against their checksum footer (no password needed)
+    Check {
+        /// Archives to check; directories mean every .rstf directly inside
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Dump the raw container layout for troubleshooting (never writes anything)
+    DebugHeader {
+        input: PathBuf,
+        /// Also prompt for the password and decode the encrypted header
+        #[arg(long)]
+        decrypt: bool,
+        #[arg(long, short = 'k', requires = "decrypt")]
+        keyfile: Option<PathBuf>,
+    },
+    /// Stream a single file from an archive to stdout
+    Cat {
+        input: PathBuf,
+        /// Path of the entry inside a directory archive
+        entry: Option<String>,
+        #[arg(long, short = 'k')]
+        keyfile: Option<PathBuf>,
+        /// zstd dictionary the archive was packed with (unless it was embedded)
+        #[arg(long, value_name = "FILE")]
+        dict: Option<PathBuf>,
+    },
+    /// Train a zstd dictionary from sample files, for `pack --dict`
+    TrainDict {
+        /// Sample files, or directories whose files are all used as samples
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Where to write the dictionary
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+        /// Largest dictionary to build (e.g. 110K, 1M)
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "110K")]
+        max_size: u64,
+        /// Replace the output file if it exists
+        #[arg(long)]
+        overwrite: bool,
+    },
+}
+
+// Keyfiles are hashed in 1 MiB reads, which keeps the progress bar moving smoothly
+const KEYFILE_BUFFER_SIZE: usize = 1024 * 1024;
+
+// Credentials (password plus optional keyfile digest), wiped on drop
+struct Credentials {
+    secret: Vec<u8>,
+}
+
+// Credential Processing Helper
+impl Credentials {
+    fn read(keyfile_path: Option<&Path>, quiet: bool) -> Result<Self> {
+        let mut password = Self::prompt().context("Failed to read password")?;
+
+        let mut secret = password.as_bytes().to_vec();
+        password.zeroize();
+
+        if let Some(path) = keyfile_path {
+            if !quiet {
+                eprintln!("Reading keyfile: {}", path.display());
+            }
+            let mut file = File::open(path).context("Failed to open keyfile")?;
+            // Large keyfiles (videos, disk images) take a while, so show how far along it is
+            let pb = if quiet || !std::io::stderr().is_terminal() {
+                ProgressBar::hidden()
+            } else {
+                let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+                let pb = ProgressBar::new(size);
+                pb.set_style(ProgressStyle::default_bar()
+                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")?
+                    .progress_chars("#>-"));
+                pb
+            };
+            let mut hasher = Sha256::new();
+            let mut buf = vec![0u8; KEYFILE_BUFFER_SIZE];
+            loop {
+                let n = match file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(anyhow!(e).context("Failed to read keyfile")),
+                };
+                hasher.update(&buf[..n]);
+                pb.inc(n as u64);
+            }
+            pb.finish_and_clear();
+            let hash = hasher.finalize();
+            secret.extend_from_slice(&hash);
+        }
+
+        Ok(Self { secret })
+    }
+
+    #[cfg(not(test))]
+    fn prompt() -> std::io::Result<String> {
+        rpassword::prompt_password("Enter password: ")
+    }
+
+    // Test builds take the password the running test set instead of asking the terminal
+    #[cfg(test)]
+    fn prompt() -> std::io::Result<String> {
+        Ok(tests::password())
+    }
+
+    // No password or keyfile: for --no-encrypt archives, which take no key
+    fn none() -> Self {
+        Self { secret: Vec::new() }
+    }
+
+    // Credentials for reading `archives`; nothing is asked when none of them is encrypted
+    fn for_archives(archives: &[PathBuf], keyfile_path: Option<&Path>) -> Result<Self> {
+        if !archives.is_empty() && archives.iter().all(|path| is_plaintext_archive(path)) {
+            return Ok(Self::none());
+        }
+        Self::read(keyfile_path, false)
+    }
+
+    fn derive_key(&self, salt: &[u8], kdf: &KdfParams) -> Result<[u8; 32]> {
+        let argon2 = kdf.argon2()?;
+        let mut key = [0u8; 32];
+        argon2
+            .hash_password_into(&self.secret, salt, &mut key)
+            .map_err(|_| anyhow!("Key derivation failed"))?;
+        Ok(key)
+    }
+}
+
+// Drop Trait for Credentials
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+// STREAM construction (big-endian 32-bit chunk counter plus last-chunk flag) over
+// the archive's cipher; readers and writers keep the chunk position themselves
+enum ChunkCipher {
+    ChaCha20(StreamBE32<ChaCha20Poly1305>),
+    XChaCha20(StreamBE32<XChaCha20Poly1305>),
+    /// Boxed: the expanded AES key schedule is about a kilobyte
+    #[cfg(feature = "aes-gcm")]
+    Aes256Gcm(Box<StreamBE32<aes_gcm::Aes256Gcm>>),
+    /// --no-encrypt: the chunk in the clear, then a 16-byte BLAKE3 checksum over the
+    /// same position, last-chunk flag and associated data an AEAD would cover
+    Plain,
+}
+
+impl ChunkCipher {
+    fn new(prologue: &Prologue, key: &[u8; 32]) -> Result<Self> {
+        if prologue.is_plaintext() {
+            return Ok(ChunkCipher::Plain);
+        }
+        let chacha_key = chacha20poly1305::Key::from_slice(key);
+        let nonce = prologue.nonce.as_slice();
+        Ok(match prologue.cipher {
+            Cipher::ChaCha20 => ChunkCipher::ChaCha20(StreamBE32::from_aead(
+                ChaCha20Poly1305::new(chacha_key),
+                NonceStream::<ChaCha20Poly1305, StreamBE32<_>>::from_slice(nonce),
+            )),
+            Cipher::XChaCha20 => ChunkCipher::XChaCha20(StreamBE32::from_aead(
+                XChaCha20Poly1305::new(chacha_key),
+                NonceStream::<XChaCha20Poly1305, StreamBE32<_>>::from_slice(nonce),
+            )),
+            #[cfg(feature = "aes-gcm")]
+            Cipher::Aes256Gcm => ChunkCipher::Aes256Gcm(Box::new(StreamBE32::from_aead(
+                aes_gcm::Aes256Gcm::new(aes_gcm::Key::<aes_gcm::Aes256Gcm>::from_slice(key)),
+                NonceStream::<aes_gcm::Aes256Gcm, StreamBE32<_>>::from_slice(nonce),
+            ))),
+            #[cfg(not(feature = "aes-gcm"))]
+            cipher => return Err(cipher.check_available().unwrap_err()),
+        })
+    }
+
+    fn encrypt(&self, position: u32, last: bool, payload: Payload) -> std::io::Result<Vec<u8>> {
+        match self {
+            ChunkCipher::Plain => {
+                let mut chunk = payload.msg.to_vec();
+                chunk.extend_from_slice(&plain_chunk_tag(position, last, payload));
+                Ok(chunk)
+            }
+            ChunkCipher::ChaCha20(stream) => stream.encrypt(position, last, payload),
+            ChunkCipher::XChaCha20(stream) => stream.encrypt(position, last, payload),
+            #[cfg(feature = "aes-gcm")]
+            ChunkCipher::Aes256Gcm(stream) => stream.encrypt(position, last, payload),
+        }
+        .map_err(|_| std::io::Error::other("Encryption failed"))
+    }
+
+    /// None when the chunk does not authenticate at this position
+    fn decrypt(&self, position: u32, last: bool, payload: Payload) -> Option<Vec<u8>> {
+        match self {
+            ChunkCipher::Plain => {
+                let split = payload.msg.len().checked_sub(TAG_SIZE)?;
+                let (msg, tag) = payload.msg.split_at(split);
+                let payload = Payload {
+                    msg,
+                    aad: payload.aad,
+                };
+                let valid = plain_chunk_tag(position, last, payload) == tag;
+                return valid.then(|| msg.to_vec());
+            }
+            ChunkCipher::ChaCha20(stream) => stream.decrypt(position, last, payload),
+            ChunkCipher::XChaCha20(stream) => stream.decrypt(position, last, payload),
+            #[cfg(feature = "aes-gcm")]
+            ChunkCipher::Aes256Gcm(stream) => stream.decrypt(position, last, payload),
+        }
+        .ok()
+    }
+}
+
+// Plaintext Chunk Checksum (--no-encrypt tags: integrity without secrecy)
+fn plain_chunk_tag(position: u32, last: bool, payload: Payload) -> [u8; TAG_SIZE] {
+    let mut hasher = blake3::Hasher::new_derive_key(PLAIN_CHUNK_CONTEXT);
+    hasher.update(&position.to_be_bytes());
+    hasher.update(&[last as u8]);
+    hasher.update(&(payload.aad.len() as u64).to_le_bytes());
+    hasher.update(payload.aad);
+    hasher.update(payload.msg);
+    let mut tag = [0u8; TAG_SIZE];
+    hasher.finalize_xof().fill(&mut tag);
+    tag
+}
+
+// Chunk Position Helper (the STREAM counter must not wrap)
+fn next_chunk_position(position: &mut u32) -> std::io::Result<u32> {
+    let current = *position;
+    *position = current.checked_add(1).ok_or_else(|| {
+        std::io::Error::other("Archive has too many chunks for the stream counter")
+    })?;
+    Ok(current)
+}
+
+struct EncryptedWriter<W: Write> {
+    inner: W,
+    cipher: ChunkCipher,
+    /// Position of the next chunk in the stream
+    position: u32,
+    /// Set once the trailer is sealed as the last chunk; nothing may follow it
+    finished: bool,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    /// Associated data sealed with every chunk (the prologue)
+    aad: Vec<u8>,
+}
+
+// EncryptedWriter Implementation
+impl<W: Write> EncryptedWriter<W> {
+    fn new(inner: W, cipher: ChunkCipher, chunk_size: usize, aad: Vec<u8>) -> Self {
+        Self {
+            inner,
+            cipher,
+            position: 0,
+            finished: false,
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+            aad,
+        }
+    }
+
+    fn seal(&mut self, data: &[u8], last: bool) -> std::io::Result<Vec<u8>> {
+        if self.finished {
+            return Err(std::io::Error::other("Archive stream is already finished"));
+        }
+        let position = next_chunk_position(&mut self.position)?;
+        let payload = Payload {
+            msg: data,
+            aad: &self.aad,
+        };
+        self.cipher.encrypt(position, last, payload)
+    }
+
+    fn flush_chunk(&mut self, final_chunk: bool) -> std::io::Result<()> {
+        if self.finished || (self.buffer.is_empty() && !final_chunk) {
+            return Ok(());
+        }
+        let chunk = std::mem::take(&mut self.buffer);
+        let sealed = self.seal(&chunk, false);
+        self.buffer = chunk;
+        let ciphertext = sealed?;
+
+        self.inner.write_all(&ciphertext)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Seal `data` as a chunk of its own, ahead of any buffered stream data
+    fn write_chunk(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.flush_chunk(false)?;
+        let ciphertext = self.seal(data, false)?;
+        self.inner.write_all(&ciphertext)
+    }
+
+    /// Close the payload with its final chunk, then seal the trailer after it as
+    /// the stream's last chunk, so a reader can tell a complete archive from a cut one
+    fn write_trailer(&mut self, trailer: &[u8]) -> std::io::Result<()> {
+        self.flush_chunk(true)?;
+        let ciphertext = self.seal(trailer, true)?;
+        self.finished = true;
+        self.inner.write_all(&ciphertext)?;
+        self.inner.flush()
+    }
+
+    /// Plaintext sealed or buffered after the two header chunks
+    fn stream_len(&self) -> u64 {
+        u64::from(self.position.saturating_sub(2)) * self.chunk_size as u64
+            + self.buffer.len() as u64
+    }
+
+    /// Writer beneath the stream, for the clear checksum footer after the trailer
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+// Write Trait for EncryptedWriter
+impl<W: Write> Write for EncryptedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut total_written = 0;
+        while total_written < buf.len() {
+            let space_left = self.chunk_size - self.buffer.len();
+            let to_copy = min(space_left, buf.len() - total_written);
+            self.buffer
+                .extend_from_slice(&buf[total_written..total_written + to_copy]);
+            total_written += to_copy;
+
+            if self.buffer.len() == self.chunk_size {
+                self.flush_chunk(false)?;
+            }
+        }
+        Ok(total_written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_chunk(true)?;
+        self.inner.flush()
+    }
+}
+
+// Drop Trait for EncryptedWriter
+impl<W: Write> Drop for EncryptedWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush_chunk(true);
+    }
+}
+
+struct DecryptedReader<R: Read> {
+    inner: R,
+    cipher: ChunkCipher,
+    /// Position of the next chunk in the stream
+    position: u32,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    offset: usize,
+    eof: bool,
+    /// Associated data every chunk was sealed with (the prologue, format 9 on)
+    aad: Vec<u8>,
+    /// Plaintext left to hand out; a chunk index after the payload is not
+    remaining: u64,
+    /// Archive offset of the next sealed chunk
+    file_offset: u64,
+    /// With unpack --salvage, chunks that fail authentication are logged here and
+    /// read as zeros instead of ending the stream
+    salvage: Option<DamageLog>,
+}
+
+// Damaged Chunk (a payload chunk unpack --salvage replaced with zeros)
+#[derive(Clone, Copy)]
+struct DamagedChunk {
+    /// Stream position of the chunk (header chunks are 0 and 1)
+    chunk: u32,
+    /// Where its ciphertext starts in the archive file
+    file_offset: u64,
+    /// Decrypted stream bytes lost with it
+    len: u64,
+}
+
+type DamageLog = std::sync::Arc<std::sync::Mutex<Vec<DamagedChunk>>>;
+
+// DecryptedReader Implementation
+impl<R: Read> DecryptedReader<R> {
+    fn new(inner: R, cipher: ChunkCipher, chunk_size: usize, aad: Vec<u8>) -> Self {
+        Self {
+            inner,
+            cipher,
+            position: 0,
+            chunk_size,
+            buffer: Vec::new(),
+            offset: 0,
+            eof: false,
+            aad,
+            remaining: u64::MAX,
+            file_offset: 0,
+            salvage: None,
+        }
+    }
+
+    /// Read and open one chunk of a known sealed size (format 2 header chunks)
+    fn read_chunk(&mut self, sealed_len: usize) -> std::io::Result<Vec<u8>> {
+        let mut sealed = vec![0u8; sealed_len];
+        self.inner.read_exact(&mut sealed).map_err(|e| {
+            if e.kind() != std::io::ErrorKind::UnexpectedEof {
+                return e;
+            }
+            ArchiveError::Truncated(format!(
+                "archive is truncated: chunk {} at ciphertext offset {} needs {} bytes",
+                self.position,
+                HumanCount(self.file_offset),
+                sealed_len
+            ))
+            .into_io(e.kind())
+        })?;
+        self.file_offset += sealed_len as u64;
+        self.open(&sealed)
+    }
+
+    /// Zeros standing in for a chunk that failed to open, once it is logged for --salvage
+    fn salvage_chunk(&mut self, sealed_len: usize) -> Option<Vec<u8>> {
+        let log = self.salvage.as_ref()?;
+        let len = sealed_len.saturating_sub(TAG_SIZE);
+        log.lock().ok()?.push(DamagedChunk {
+            chunk: self.position - 1,
+            file_offset: self.file_offset - sealed_len as u64,
+            len: len as u64,
+        });
+        Some(vec![0u8; len])
+    }
+
+    /// Open the chunk that ends at `file_offset`
+    fn open(&mut self, sealed: &[u8]) -> std::io::Result<Vec<u8>> {
+        let chunk = self.position;
+        let position = next_chunk_position(&mut self.position)?;
+        let payload = Payload {
+            msg: sealed,
+            aad: &self.aad,
+        };
+        self.cipher
+            .decrypt(position, false, payload)
+            .ok_or_else(|| {
+                ArchiveError::Corrupted(format!(
+                    "authentication failed at chunk {} (ciphertext offset {})",
+                    chunk,
+                    HumanCount(self.file_offset - sealed.len() as u64)
+                ))
+                .into_io(std::io::ErrorKind::InvalidData)
+            })
+    }
+}
+
+// Read Trait for DecryptedReader
+impl<R: Read> Read for DecryptedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.buffer.len() {
+            if self.eof {
+                return Ok(0);
+            }
+
+            let encrypted_chunk_size = self.chunk_size + TAG_SIZE;
+            let mut encrypted_buf = vec![0u8; encrypted_chunk_size];
+
+            let mut read_bytes = 0;
+            while read_bytes < encrypted_chunk_size {
+                match self.inner.read(&mut encrypted_buf[read_bytes..]) {
+                    Ok(0) => break,
+                    Ok(n) => read_bytes += n,
+                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if read_bytes == 0 {
+                self.eof = true;
+                return Ok(0);
+            }
+            if read_bytes < TAG_SIZE {
+                // Even an empty final chunk carries its whole tag
+                return Err(ArchiveError::Truncated(format!(
+                    "archive is truncated: chunk {} at ciphertext offset {} has {} of at least {} bytes",
+                    self.position,
+                    HumanCount(self.file_offset),
+                    read_bytes,
+                    TAG_SIZE
+                ))
+                .into_io(std::io::ErrorKind::UnexpectedEof));
+            }
+
+            self.file_offset += read_bytes as u64;
+            let plaintext = match self.open(&encrypted_buf[..read_bytes]) {
+                Ok(plaintext) => plaintext,
+                Err(e) if read_bytes < encrypted_chunk_size => {
+                    // A short chunk is the last one the file holds; it fails to open when
+                    // it was cut, not just when it was damaged
+                    let e = ArchiveError::Corrupted(format!(
+                        "{}, a short final chunk of {} bytes (the archive may be truncated or damaged)",
+                        e, read_bytes
+                    ))
+                    .into_io(e.kind());
+                    self.salvage_chunk(read_bytes).ok_or(e)?
+                }
+                Err(e) => self.salvage_chunk(read_bytes).ok_or(e)?,
+            };
+
+            self.buffer = plaintext;
+            self.offset = 0;
+
+            if read_bytes < encrypted_chunk_size {
+                self.eof = true;
+            }
+        }
+
+        let available = (self.buffer.len() - self.offset) as u64;
+        let to_copy = min(min(available, self.remaining), buf.len() as u64) as usize;
+        buf[..to_copy].copy_from_slice(&self.buffer[self.offset..self.offset + to_copy]);
+        self.offset += to_copy;
+        self.remaining -= to_copy as u64;
+
+        Ok(to_copy)
+    }
+}
+
+// Payload Seeking (indexed archives only need the chunks from the one holding `offset`)
+impl DecryptedReader<std::io::Take<File>> {
+    /// Continues at plaintext `offset` of the payload stream, stopping at `end`
+    fn seek_payload(&mut self, index: &ChunkIndex, offset: u64, end: u64) -> std::io::Result<()> {
+        let chunk = offset / self.chunk_size as u64;
+        let skipped = chunk * (self.chunk_size + TAG_SIZE) as u64;
+        self.inner
+            .get_mut()
+            .seek(SeekFrom::Start(index.payload_start + skipped))?;
+        self.file_offset = index.payload_start + skipped;
+        self.inner
+            .set_limit(index.ciphertext_len.saturating_sub(skipped));
+        // Header chunks are 0 and 1
+        self.position = u32::try_from(2 + chunk)
+            .map_err(|_| std::io::Error::other("Chunk index points past the last chunk"))?;
+        self.buffer.clear();
+        self.offset = 0;
+        self.eof = false;
+        self.remaining = end.saturating_sub(chunk * self.chunk_size as u64);
+        let within = offset % self.chunk_size as u64;
+        let copied = std::io::copy(&mut self.by_ref().take(within), &mut std::io::sink())?;
+        if copied < within {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Chunk index points past the end of the payload",
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Main Entry Point
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let result = run(cli);
+    if let Err(e) = &result {
+        if let Some(failure) = ArchiveError::classify(e) {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(failure.exit_code());
+        }
+    }
+    result
+}
+
+// Command Dispatch
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Commands::Pack(args) => pack(args),
+        Commands::Unpack(args) => unpack(args),
+        Commands::List(args) => list(args),
+        Commands::Repack(args) => repack(args),
+        Commands::Info {
+            input,
+            keyfile,
+            clear_only,
+        } => info(input, keyfile, clear_only),
+        Commands::Checksum {
+            input,
+            keyfile,
+            recompute,
+            dict,
+        } => checksum(input, keyfile, recompute, dict),
+        Commands::Check { inputs } => check(&inputs),
+        Commands::DebugHeader {
+            input,
+            decrypt,
+            keyfile,
+        } => debug_header(input, decrypt, keyfile),
+        Commands::Cat {
+            input,
+            entry,
+            keyfile,
+            dict,
+        } => cat(input, entry, keyfile, dict),
+        Commands::TrainDict {
+            inputs,
+            output,
+            max_size,
+            overwrite,
+        } => train_dict(&inputs, &output, max_size, overwrite),
+    }
+}
+
+// Input Path Normalization (`dir/`, `dir/.` and `dir` name the same archive)
+fn normalize_input_path(input_path: &Path) -> PathBuf {
+    let normalized: PathBuf = input_path.components().collect();
+    if normalized.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        normalized
+    }
+}
+
+// Archive Base Name Helper
+fn archive_base_name(input_path: &Path) -> String {
+    // `.`, `..` and filesystem roots have no file_name of their own
+    let name = match input_path.file_name() {
+        Some(name) => Some(name.to_os_string()),
+        None => fs::canonicalize(input_path)
+            .ok()
+            .and_then(|p| p.file_name().map(|n| n.to_os_string())),
+    };
+    match name {
+        Some(name) if !name.is_empty() => name.to_string_lossy().to_string(),
+        _ => "root".to_string(),
+    }
+}
+
+// Output Path Helper
+fn derive_output_path(input_path: &Path, output: Option<&Path>, suffix: Option<&str>) -> PathBuf {
+    let archive_name = match suffix {
+        Some(suffix) => format!("{}-{}.rstf", archive_base_name(input_path), suffix),
+        None => format!("{}.rstf", archive_base_name(input_path)),
+    };
+
+    match output {
+        Some(dir) if dir.is_dir() => dir.join(archive_name),
+        Some(file) => file.to_path_buf(),
+        None if input_path.file_name().is_some() => input_path.with_file_name(archive_name),
+        // Place archives of `.`, `..` or a root next to the resolved directory
+        None => match fs::canonicalize(input_path)
+            .ok()
+            .and_then(|p| p.parent().map(Path::to_path_buf))
+        {
+            Some(parent) => parent.join(archive_name),
+            None => PathBuf::from(archive_name),
+        },
+    }
+}
+
+// Timestamp Formatting Helper
+fn format_timestamp(format: &str, utc: bool) -> Result<String> {
+    use chrono::format::{Item, StrftimeItems};
+
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(anyhow!("Invalid timestamp format '{}'", format));
+    }
+    let stamp = if utc {
+        chrono::Utc::now().format(format).to_string()
+    } else {
+        chrono::Local::now().format(format).to_string()
+    };
+    if stamp.contains(['/', '\\']) {
+        return Err(anyhow!(
+            "Timestamp format '{}' must not produce path separators",
+            format
+        ));
+    }
+    Ok(stamp)
+}
+
+// Archive Extension Check
+fn is_rstf_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("rstf"))
+}
+
+// Same Inode Check (catches hard links)
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+// Input/Output Overlap Guard
+fn check_pack_paths(input_path: &Path, output_path: &Path, is_dir: bool) -> Result<()> {
+    let input_canon = fs::canonicalize(input_path).context("Failed to resolve input path")?;
+
+    // The output may not exist yet, so resolve its parent directory instead
+    let output_parent = match output_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let output_canon = match (fs::canonicalize(output_parent), output_path.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => return Ok(()),
+    };
+    let output_canon = fs::canonicalize(&output_canon).unwrap_or(output_canon);
+
+    if output_canon == input_canon || is_same_file(input_path, output_path) {
+        return Err(anyhow!(
+            "Output '{}' is the same file as the input",
+            output_path.display()
+        ));
+    }
+    if is_dir && output_canon.starts_with(&input_canon) {
+        return Err(anyhow!(
+            "Output '{}' is inside the directory being packed",
+            output_path.display()
+        ));
+    }
+    Ok(())
+}
+
+// Input Glob Expansion (for shells such as cmd/PowerShell that pass `*.log` through)
+fn expand_input_globs(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::with_capacity(paths.len());
+    for path in paths {
+        let pattern = path.to_string_lossy();
+        if path.exists() || !pattern.contains(['*', '?', '[']) {
+            expanded.push(path.clone());
+            continue;
+        }
+
+        let mut matches = glob::glob(&pattern)
+            .with_context(|| format!("Invalid glob pattern '{}'", pattern))?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Failed to expand glob pattern")?;
+        if matches.is_empty() {
+            return Err(anyhow!("Pattern '{}' did not match any files", pattern));
+        }
+        // Sorted so the same pattern always yields the same archive layout
+        matches.sort();
+        expanded.extend(matches);
+    }
+    Ok(expanded)
+}
+
+struct PackInput {
+    path: PathBuf,
+    name: String,
+    is_dir: bool,
+    size: u64,
+    /// Directories from --files-from are stored as a single entry
+    recursive: bool,
+    /// Contents of a recursive directory, relative to it (empty otherwise)
+    entries: Vec<TreeEntry>,
+    /// Paths below it the walk left out
+    skips: WalkSkips,
+}
+
+struct TreeEntry {
+    path: PathBuf,
+    relative: PathBuf,
+    is_dir: bool,
+    size: u64,
+    /// Device and inode of a regular file with several links (None otherwise)
+    inode: Option<(u64, u64)>,
+}
+
+const VCS_DIRS: [&str; 5] = [".git", ".hg", ".svn", ".bzr", "CVS"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum LinkHandling {
+    /// Pack symlinks as symlinks
+    Store,
+    /// Pack what symlinks point to (--dereference)
+    Follow,
+    /// Leave symlinks out (--skip-symlinks)
+    Skip,
+}
+
+// What a directory walk leaves out
+#[derive(Clone)]
+struct WalkOptions {
+    exclude: Vec<glob::Pattern>,
+    gitignore: bool,
+    exclude_vcs: bool,
+    links: LinkHandling,
+    one_file_system: bool,
+    larger_than: Option<u64>,
+    smaller_than: Option<u64>,
+    newer_than: Option<std::time::SystemTime>,
+    older_than: Option<std::time::SystemTime>,
+    skip_unreadable: bool,
+    special_files: bool,
+}
+
+impl WalkOptions {
+    fn new(args: &PackArgs) -> Result<Self> {
+        let exclude = args
+            .exclude
+            .iter()
+            .map(|g| glob::Pattern::new(g).with_context(|| format!("Invalid glob '{}'", g)))
+            .collect::<Result<Vec<_>>>()?;
+        let newer_than = match &args.newer_than_file {
+            Some(file) => Some(fs::metadata(file).and_then(|m| m.modified()).with_context(
+                || {
+                    format!(
+                        "Failed to read the modification time of '{}'",
+                        file.display()
+                    )
+                },
+            )?),
+            None => args.newer_than,
+        };
+        if args.one_file_system && !cfg!(unix) {
+            eprintln!("Warning: --one-file-system has no effect on this platform");
+        }
+        Ok(Self {
+            exclude,
+            gitignore: args.gitignore,
+            exclude_vcs: args.exclude_vcs,
+            links: match (args.dereference, args.skip_symlinks) {
+                (true, _) => LinkHandling::Follow,
+                (_, true) => LinkHandling::Skip,
+                _ => LinkHandling::Store,
+            },
+            one_file_system: args.one_file_system,
+            larger_than: args.exclude_larger_than,
+            smaller_than: args.exclude_smaller_than,
+            newer_than,
+            older_than: args.older_than,
+            skip_unreadable: args.skip_unreadable,
+            special_files: args.special_files,
+        })
+    }
+
+    // `target` matches that name at any depth, `src/*.rs` the path inside the input
+    fn excludes(&self, relative: &Path) -> bool {
+        let name = relative.file_name().unwrap_or_default();
+        if self.exclude_vcs && VCS_DIRS.iter().any(|vcs| name == *vcs) {
+            return true;
+        }
+        self.exclude
+            .iter()
+            .any(|g| g.matches_path(Path::new(name)) || g.matches_path(relative))
+    }
+
+    fn filters_time(&self) -> bool {
+        self.newer_than.is_some() || self.older_than.is_some()
+    }
+
+    // Directories always stay so that the files kept below them have parents
+    fn excludes_time(&self, metadata: &fs::Metadata) -> bool {
+        let Ok(modified) = metadata.modified() else {
+            return false;
+        };
+        !metadata.is_dir()
+            && (self.newer_than.is_some_and(|cutoff| modified <= cutoff)
+                || self.older_than.is_some_and(|cutoff| modified >= cutoff))
+    }
+
+    // Size filters only ever apply to regular files
+    fn excludes_size(&self, metadata: &fs::Metadata) -> bool {
+        let size = metadata.len();
+        metadata.is_file()
+            && (self.larger_than.is_some_and(|max| size > max)
+                || self.smaller_than.is_some_and(|min| size < min))
+    }
+}
+
+// Pack Input Collection
+fn collect_pack_inputs(paths: &[PathBuf], walk: &WalkOptions) -> Result<Vec<PackInput>> {
+    let mut inputs: Vec<PackInput> = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = normalize_input_path(path);
+        let metadata = fs::metadata(&path)
+            .with_context(|| format!("Failed to read metadata of '{}'", path.display()))?;
+
+        let mut tree = TreeWalk::new(walk, &metadata);
+        if metadata.is_dir() {
+            if walk.gitignore {
+                tree.walk_gitignored(&path)?;
+            } else {
+                tree.walk(&path, Path::new(""))?;
+            }
+        }
+
+        inputs.push(PackInput {
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() {
+                tree.entries.iter().map(|e| e.size).sum()
+            } else {
+                metadata.len()
+            },
+            name: archive_base_name(&path),
+            path,
+            recursive: true,
+            entries: tree.entries,
+            skips: tree.skips,
+        });
+    }
+    Ok(inputs)
+}
+
+// Deepest directory nesting a walk descends into (--dereference can loop forever)
+const MAX_WALK_DEPTH: usize = 256;
+
+// Unreadable Paths (path and the error that hit it)
+type UnreadableList = Vec<(PathBuf, String)>;
+
+// What a directory walk left out, for the pack summary
+#[derive(Default)]
+struct WalkSkips {
+    excluded: u64,
+    /// Symlinks left out by --skip-symlinks
+    symlinks: Vec<PathBuf>,
+    /// Symlinks --dereference could not follow (broken or looping)
+    unfollowed: Vec<PathBuf>,
+    /// Directories on another filesystem left out by --one-file-system
+    mounts: Vec<PathBuf>,
+    /// Files left out by --exclude-larger-than/--exclude-smaller-than
+    by_size: Vec<PathBuf>,
+    /// Files outside and inside the --newer-than/--older-than window
+    by_time: u64,
+    in_time: u64,
+    /// Paths --skip-unreadable left out, with the error that hit them
+    unreadable: UnreadableList,
+    /// Sockets, which tar cannot hold
+    sockets: Vec<PathBuf>,
+    /// FIFOs and device nodes left out without --special-files
+    special: Vec<PathBuf>,
+}
+
+impl WalkSkips {
+    fn absorb(&mut self, other: &WalkSkips) {
+        self.excluded += other.excluded;
+        self.symlinks.extend(other.symlinks.iter().cloned());
+        self.unfollowed.extend(other.unfollowed.iter().cloned());
+        self.mounts.extend(other.mounts.iter().cloned());
+        self.by_size.extend(other.by_size.iter().cloned());
+        self.by_time += other.by_time;
+        self.in_time += other.in_time;
+        self.unreadable.extend(other.unreadable.iter().cloned());
+        self.sockets.extend(other.sockets.iter().cloned());
+        self.special.extend(other.special.iter().cloned());
+    }
+}
+
+// Directory Walk State (entries in tar order plus what was left out)
+struct TreeWalk<'a> {
+    options: &'a WalkOptions,
+    entries: Vec<TreeEntry>,
+    skips: WalkSkips,
+    /// Canonical paths of the directories being walked, to catch symlink loops
+    ancestors: Vec<PathBuf>,
+    /// Filesystem of the input root when --one-file-system is set
+    root_device: Option<u64>,
+}
+
+impl<'a> TreeWalk<'a> {
+    fn new(options: &'a WalkOptions, root: &fs::Metadata) -> Self {
+        Self {
+            options,
+            entries: Vec::new(),
+            skips: WalkSkips::default(),
+            ancestors: Vec::new(),
+            root_device: device_id(root).filter(|_| options.one_file_system),
+        }
+    }
+
+    // Sorted, parents before children
+    fn walk(&mut self, dir: &Path, relative: &Path) -> Result<()> {
+        if self.ancestors.len() >= MAX_WALK_DEPTH {
+            return Err(anyhow!(
+                "'{}' is nested more than {} directories deep",
+                dir.display(),
+                MAX_WALK_DEPTH
+            ));
+        }
+        // Listed before the entry goes in, so an unreadable directory is skipped whole
+        let listing = fs::read_dir(dir).and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<std::io::Result<Vec<_>>>()
+        });
+        let mut children = match listing {
+            Ok(children) => children,
+            Err(e) if self.options.skip_unreadable => {
+                self.skips
+                    .unreadable
+                    .push((dir.to_path_buf(), e.to_string()));
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(
+                    anyhow!(e).context(format!("Failed to read directory '{}'", dir.display()))
+                )
+            }
+        };
+        children.sort();
+        self.entries.push(TreeEntry {
+            path: dir.to_path_buf(),
+            relative: relative.to_path_buf(),
+            is_dir: true,
+            size: 0,
+            inode: None,
+        });
+
+        self.ancestors
+            .push(fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf()));
+        for path in children {
+            let child = relative.join(path.file_name().unwrap_or_default());
+            // Checked before anything is read, so an excluded directory is never entered
+            if self.options.excludes(&child) {
+                self.skips.excluded += 1;
+                continue;
+            }
+            let Some(metadata) = self.entry_metadata(&path)? else {
+                continue;
+            };
+            if metadata.is_dir() && crosses_device(self.root_device, &metadata) {
+                self.skips.mounts.push(path);
+            } else if self.options.excludes_size(&metadata) {
+                self.skips.by_size.push(path);
+            } else if self.options.excludes_time(&metadata) {
+                self.skips.by_time += 1;
+            } else if metadata.is_dir() {
+                self.walk(&path, &child)?;
+            } else if self.admits(&path, &metadata) {
+                self.skips.in_time += self.options.filters_time() as u64;
+                self.entries.push(TreeEntry {
+                    path,
+                    relative: child,
+                    is_dir: false,
+                    size: if metadata.is_file() {
+                        metadata.len()
+                    } else {
+                        0
+                    },
+                    inode: link_identity(&metadata),
+                });
+            }
+        }
+        self.ancestors.pop();
+        Ok(())
+    }
+
+    // Gitignore-aware walk (same order and entries as `walk`, minus ignored paths)
+    fn walk_gitignored(&mut self, dir: &Path) -> Result<()> {
+        let matched = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let mounts = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (options, root, counter) = (self.options.clone(), dir.to_path_buf(), matched.clone());
+        let (root_device, crossed) = (self.root_device, mounts.clone());
+        let following = self.options.links == LinkHandling::Follow;
+        let walker = gitignore_walker(dir)
+            .follow_links(following)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(move |entry| {
+                let relative = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+                if entry.depth() == 0 {
+                    return true;
+                }
+                if options.excludes(relative) {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    return false;
+                }
+                let other_device = entry
+                    .metadata()
+                    .is_ok_and(|m| m.is_dir() && crosses_device(root_device, &m));
+                if other_device {
+                    if let Ok(mut crossed) = crossed.lock() {
+                        crossed.push(entry.path().to_path_buf());
+                    }
+                }
+                !other_device
+            })
+            .build();
+
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => match unfollowable_link(&e).filter(|_| following) {
+                    Some(link) => {
+                        self.skips.unfollowed.push(link);
+                        continue;
+                    }
+                    None if self.options.skip_unreadable => {
+                        let path = match &e {
+                            ignore::Error::WithPath { path, .. } => path.clone(),
+                            _ => dir.to_path_buf(),
+                        };
+                        self.skips.unreadable.push((path, e.to_string()));
+                        continue;
+                    }
+                    None => {
+                        return Err(
+                            anyhow!(e).context(format!("Failed to walk '{}'", dir.display()))
+                        )
+                    }
+                },
+            };
+            let path = entry.into_path();
+            let Some(metadata) = self.entry_metadata(&path)? else {
+                continue;
+            };
+            if self.options.excludes_size(&metadata) {
+                self.skips.by_size.push(path);
+                continue;
+            }
+            if self.options.excludes_time(&metadata) {
+                self.skips.by_time += 1;
+                continue;
+            }
+            if !self.admits(&path, &metadata) {
+                continue;
+            }
+            if !metadata.is_dir() {
+                self.skips.in_time += self.options.filters_time() as u64;
+            }
+            self.entries.push(TreeEntry {
+                relative: path.strip_prefix(dir).unwrap_or(&path).to_path_buf(),
+                is_dir: metadata.is_dir(),
+                size: if metadata.is_file() {
+                    metadata.len()
+                } else {
+                    0
+                },
+                inode: link_identity(&metadata),
+                path,
+            });
+        }
+        self.skips.excluded += matched.load(std::sync::atomic::Ordering::Relaxed);
+        if let Ok(mut mounts) = mounts.lock() {
+            self.skips.mounts.append(&mut mounts);
+        }
+        Ok(())
+    }
+
+    // Sockets never go in, FIFOs and device nodes only with --special-files
+    fn admits(&mut self, path: &Path, metadata: &fs::Metadata) -> bool {
+        if is_socket(metadata) {
+            self.skips.sockets.push(path.to_path_buf());
+        } else if is_special_file(metadata) && !self.options.special_files {
+            self.skips.special.push(path.to_path_buf());
+        } else {
+            return true;
+        }
+        false
+    }
+
+    // Metadata an entry is packed with, or None when its symlink is left out
+    fn entry_metadata(&mut self, path: &Path) -> Result<Option<fs::Metadata>> {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if self.options.skip_unreadable => {
+                self.skips
+                    .unreadable
+                    .push((path.to_path_buf(), e.to_string()));
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(
+                    anyhow!(e).context(format!("Failed to read metadata of '{}'", path.display()))
+                )
+            }
+        };
+        if !metadata.is_symlink() {
+            return Ok(Some(metadata));
+        }
+        match self.options.links {
+            LinkHandling::Store => Ok(Some(metadata)),
+            LinkHandling::Skip => {
+                self.skips.symlinks.push(path.to_path_buf());
+                Ok(None)
+            }
+            LinkHandling::Follow => {
+                let Ok(target) = fs::metadata(path) else {
+                    self.skips.unfollowed.push(path.to_path_buf());
+                    return Ok(None);
+                };
+                let looping = target.is_dir()
+                    && fs::canonicalize(path).is_ok_and(|c| self.ancestors.contains(&c));
+                if looping {
+                    self.skips.unfollowed.push(path.to_path_buf());
+                    return Ok(None);
+                }
+                Ok(Some(target))
+            }
+        }
+    }
+}
+
+// Special File Helpers (FIFOs and device nodes are tar entries of their own, sockets are not)
+fn is_socket(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileTypeExt::is_socket(&metadata.file_type())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+fn is_special_file(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        let file_type = metadata.file_type();
+        file_type.is_fifo() || file_type.is_char_device() || file_type.is_block_device()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+// Hard Link Identity (device and inode of files linked more than once; Unix only)
+fn link_identity(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+// Device Helpers (st_dev on Unix; no filesystem boundaries are known elsewhere)
+fn device_id(metadata: &fs::Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+fn crosses_device(root_device: Option<u64>, metadata: &fs::Metadata) -> bool {
+    root_device.is_some_and(|root| device_id(metadata) != Some(root))
+}
+
+// Gitignore Walker Helper (only git's rules; hidden files are packed like any other)
+fn gitignore_walker(dir: &Path) -> ignore::WalkBuilder {
+    let mut builder = ignore::WalkBuilder::new(dir);
+    builder
+        .standard_filters(false)
+        .git_ignore(true)
+        .git_exclude(true)
+        .git_global(true)
+        .parents(true);
+    builder
+}
+
+// Walker Error Helper (the symlink behind a loop or a dangling target)
+fn unfollowable_link(err: &ignore::Error) -> Option<PathBuf> {
+    match err {
+        ignore::Error::Loop { child, .. } => Some(child.clone()),
+        // Dangling targets surface as a NotFound I/O error somewhere below the path
+        ignore::Error::WithPath { path, err } => unfollowable_link(err).map(|link| match link {
+            link if link.as_os_str().is_empty() => path.clone(),
+            link => link,
+        }),
+        ignore::Error::WithDepth { err, .. } => unfollowable_link(err),
+        ignore::Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound => Some(PathBuf::new()),
+        _ => None,
+    }
+}
+
+// Stdin Input (stored name comes from --name or the --output file name)
+fn stdin_pack_input(name: Option<&str>, output: Option<&Path>) -> Result<PackInput> {
+    let name = match (name, output) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(output)) if !output.is_dir() => archive_stem(output),
+        _ => {
+            return Err(anyhow!(
+                "Packing stdin requires --name <NAME> or --output <FILE>"
+            ))
+        }
+    };
+    validate_output_name(&name)?;
+
+    Ok(PackInput {
+        path: PathBuf::from("-"),
+        name,
+        is_dir: false,
+        size: 0,
+        recursive: false,
+        entries: Vec::new(),
+        skips: WalkSkips::default(),
+    })
+}
+
+// Manifest Input Collection (--files-from)
+fn collect_manifest_inputs(
+    manifest: &Path,
+    null_delimited: bool,
+    base_dir: &Path,
+    ignore_missing: bool,
+) -> Result<Vec<PackInput>> {
+    let mut data = Vec::new();
+    if manifest == Path::new("-") {
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("Failed to read file list from stdin")?;
+    } else {
+        File::open(manifest)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .with_context(|| format!("Failed to read file list '{}'", manifest.display()))?;
+    }
+
+    let delimiter = if null_delimited { b'\0' } else { b'\n' };
+    let mut inputs = Vec::new();
+    let mut missing = 0usize;
+
+    for raw in data.split(|&b| b == delimiter) {
+        let raw = match raw {
+            [rest @ .., b'\r'] if !null_delimited => rest,
+            _ => raw,
+        };
+        if raw.is_empty() {
+            continue;
+        }
+        let listed = path_from_bytes(raw);
+
+        let relative = if listed.is_absolute() {
+            listed
+                .strip_prefix(base_dir)
+                .ok()
+                .map(Path::to_path_buf)
+                .or_else(|| {
+                    let canonical = fs::canonicalize(base_dir).ok()?;
+                    listed.strip_prefix(canonical).ok().map(Path::to_path_buf)
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "'{}' is outside the base directory '{}'",
+                        listed.display(),
+                        base_dir.display()
+                    )
+                })?
+        } else {
+            listed.clone()
+        };
+
+        let mut name_parts = Vec::new();
+        for component in relative.components() {
+            match component {
+                std::path::Component::Normal(part) => name_parts.push(part.to_string_lossy()),
+                std::path::Component::CurDir => {}
+                _ => return Err(anyhow!("'{}' escapes the base directory", listed.display())),
+            }
+        }
+        // `find .` lists the base directory itself, which has no entry name
+        if name_parts.is_empty() {
+            continue;
+        }
+
+        let path = base_dir.join(&relative);
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && ignore_missing => {
+                eprintln!("Skipping missing path: {}", path.display());
+                missing += 1;
+                continue;
+            }
+            Err(e) => {
+                return Err(anyhow!(e).context(format!(
+                    "Failed to read metadata of '{}' (use --ignore-missing to skip)",
+                    path.display()
+                )))
+            }
+        };
+
+        inputs.push(PackInput {
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_file() {
+                metadata.len()
+            } else {
+                0
+            },
+            name: name_parts.join("/"),
+            path,
+            recursive: false,
+            entries: Vec::new(),
+            skips: WalkSkips::default(),
+        });
+    }
+
+    if missing > 0 {
+        eprintln!("Skipped {} missing path(s) from the file list", missing);
+    }
+    Ok(inputs)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+// Duplicate Entry Name Check
+fn check_duplicate_names(inputs: &[PackInput]) -> Result<()> {
+    let mut seen: std::collections::HashMap<&str, &Path> = std::collections::HashMap::new();
+    for input in inputs {
+        if let Some(other) = seen.insert(&input.name, &input.path) {
+            return Err(anyhow!(
+                "'{}' and '{}' would both be stored as '{}'",
+                other.display(),
+                input.path.display(),
+                input.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Entry Counting (files and directories the tar stream will contain)
+fn count_entries(inputs: &[PackInput], contents_only: bool) -> (u64, u64) {
+    let (mut files, mut dirs) = (0, 0);
+    for input in inputs {
+        if input.is_dir && input.recursive {
+            for entry in &input.entries {
+                if contents_only && entry.relative.as_os_str().is_empty() {
+                    continue;
+                }
+                if entry.is_dir {
+                    dirs += 1;
+                } else {
+                    files += 1;
+                }
+            }
+        } else if input.is_dir {
+            dirs += 1;
+        } else {
+            files += 1;
+        }
+    }
+    (files, dirs)
+}
+
+// Top-level Member Names (first path component of each entry, in order)
+fn top_level_members(inputs: &[PackInput]) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    inputs
+        .iter()
+        .map(|i| i.name.split('/').next().unwrap_or_default().to_string())
+        .filter(|name| seen.insert(name.clone()))
+        .collect()
+}
+
+// Codec Options (--store, --preset, --dict and the checks that need the final codec)
+fn resolve_codec_args(args: &mut PackArgs) -> Result<()> {
+    if args.store {
+        args.compression = Compression::None;
+    }
+    args.compression.check_available()?;
+    args.cipher.check_available()?;
+    if let Some(preset) = args.preset {
+        if args.compression != Compression::Zstd {
+            return Err(anyhow!("--preset only applies to zstd compression"));
+        }
+        let (level, long) = preset.settings();
+        args.level = args.level.or(Some(level));
+        if !args.low_memory {
+            args.long = args.long.or(long);
+        }
+    }
+    if args.long.is_some() && args.compression != Compression::Zstd {
+        return Err(anyhow!("--long only applies to zstd compression"));
+    }
+    if let Some(path) = &args.dict {
+        if args.compression != Compression::Zstd {
+            return Err(anyhow!("--dict only applies to zstd compression"));
+        }
+        args.dictionary = Some(
+            fs::read(path)
+                .with_context(|| format!("Failed to read dictionary '{}'", path.display()))?,
+        );
+    }
+    if args.compression == Compression::Xz && !(0..=9).contains(&args.level()) {
+        return Err(anyhow!("xz levels run from 0 to 9 (got {})", args.level()));
+    }
+    if args.index.is_some() && args.compression != Compression::Zstd {
+        return Err(anyhow!("--index only applies to zstd compression"));
+    }
+    if args.index == Some(0) {
+        return Err(anyhow!("--index needs a span of at least one byte"));
+    }
+    Ok(())
+}
+
+// Pack Function
+fn pack(mut args: PackArgs) -> Result<()> {
+    if args.reproducible {
+        args.source_date_epoch = source_date_epoch()?;
+    }
+    if args.reproducible && args.threads > 1 {
+        eprintln!("Warning: --reproducible compresses on one thread; --threads is ignored");
+    }
+    if args.low_memory && args.threads > 1 {
+        eprintln!("Warning: --low-memory works on one thread; --threads is ignored");
+    }
+    let from_stdin = args.inputs.iter().any(|i| i == Path::new("-"));
+    if from_stdin && (args.inputs.len() > 1 || args.files_from.is_some() || args.each) {
+        return Err(anyhow!("Stdin ('-') must be the only input"));
+    }
+    check_xattrs_support(args.xattrs)?;
+    check_acls_support(args.acls);
+    resolve_codec_args(&mut args)?;
+    if args.smart_compress && args.compression != Compression::Zstd {
+        return Err(anyhow!("--smart-compress only applies to zstd compression"));
+    }
+    if args.adaptive && args.compression != Compression::Zstd {
+        return Err(anyhow!("--adaptive only applies to zstd compression"));
+    }
+    if let Some(limit) = args.memory_limit {
+        fit_memory_limit(&mut args, limit)?;
+    }
+    if args.tar_format == TarFormat::Ustar {
+        if args.xattrs || args.acls {
+            return Err(anyhow!(
+                "--xattrs and --acls are stored as PAX records, which --tar-format ustar cannot hold"
+            ));
+        }
+        if args.sparse {
+            return Err(anyhow!(
+                "Sparse entries are a GNU extension; use --tar-format gnu or pax with --sparse"
+            ));
+        }
+    }
+    if from_stdin && args.wipe {
+        return Err(anyhow!("--wipe cannot be used when packing stdin"));
+    }
+    if args.name.is_some() && !from_stdin {
+        return Err(anyhow!("--name is only used when packing stdin ('-')"));
+    }
+
+    let name_suffix = match &args.timestamp {
+        Some(format) => Some(format_timestamp(format, args.utc)?),
+        None => None,
+    };
+    let comment = read_comment(&args)?;
+    if args.each {
+        return pack_each(&args, name_suffix.as_deref(), &comment);
+    }
+
+    let walk = WalkOptions::new(&args)?;
+    let mut inputs = if from_stdin {
+        vec![stdin_pack_input(
+            args.name.as_deref(),
+            args.output.as_deref(),
+        )?]
+    } else {
+        collect_pack_inputs(&expand_input_globs(&args.inputs)?, &walk)?
+    };
+    if let Some(manifest) = &args.files_from {
+        let base_dir = args.base_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+        inputs.extend(collect_manifest_inputs(
+            manifest,
+            args.null,
+            &base_dir,
+            args.ignore_missing,
+        )?);
+        if inputs.is_empty() {
+            return Err(anyhow!("The file list did not name any paths to pack"));
+        }
+    }
+    check_duplicate_names(&inputs)?;
+    let is_multi = inputs.len() > 1 || args.files_from.is_some();
+    if args.contents_only && (is_multi || !inputs[0].is_dir) {
+        return Err(anyhow!("--contents-only requires a single directory input"));
+    }
+
+    let output = args.output.as_deref();
+    let output_path = if is_multi {
+        match output {
+            Some(path) if !path.is_dir() => path.to_path_buf(),
+            _ => {
+                return Err(anyhow!(
+                    "Packing several inputs requires --output <FILE> to name the archive"
+                ))
+            }
+        }
+    } else if from_stdin {
+        derive_output_path(Path::new(&inputs[0].name), output, name_suffix.as_deref())
+    } else {
+        derive_output_path(&inputs[0].path, output, name_suffix.as_deref())
+    };
+    if name_suffix.is_some() && output.is_some_and(|o| !o.is_dir()) {
+        eprintln!("Warning: --timestamp has no effect when --output names a file");
+    }
+
+    for input in inputs.iter().filter(|_| !from_stdin) {
+        check_pack_input(input, &output_path, args.force)?;
+    }
+
+    // Fail before the (slow) password prompt rather than after it
+    if output_path.exists() && !args.overwrite {
+        return Err(anyhow!(
+            "'{}' already exists (use --overwrite to replace it)",
+            output_path.display()
+        ));
+    }
+
+    let credentials = pack_credentials(&args)?;
+
+    let total_size: u64 = inputs.iter().map(|i| i.size).sum();
+    let mut header = if is_multi {
+        RstfHeader {
+            is_dir: true,
+            original_name: archive_base_name(&output_path.with_extension("")),
+            original_size: total_size,
+            members: top_level_members(&inputs),
+            comment,
+            ..RstfHeader::stamped()
+        }
+    } else {
+        RstfHeader {
+            is_dir: inputs[0].is_dir,
+            original_name: inputs[0].name.clone(),
+            original_size: total_size,
+            contents_only: args.contents_only,
+            comment,
+            ..RstfHeader::stamped()
+        }
+    };
+    if header.is_dir {
+        (header.file_count, header.dir_count) = count_entries(&inputs, header.contents_only);
+    } else if !from_stdin {
+        record_file_metadata(&mut header, &inputs[0].path);
+        if args.sparse {
+            header.extents = sparse_extents(&inputs[0].path);
+        }
+    }
+    let single_file = (!header.is_dir && !from_stdin).then(|| inputs[0].path.as_path());
+    record_codec(&mut header, &args, single_file);
+    if args.reproducible {
+        make_reproducible(&mut header, &args);
+    } else if args.numeric_owner {
+        strip_owner_names(&mut header);
+    }
+
+    match &args.files_from {
+        _ if args.quiet || args.json => {}
+        Some(manifest) => println!(
+            "Packing {} path(s) listed in {}...",
+            inputs.len(),
+            manifest.display()
+        ),
+        None if from_stdin => println!("Packing stdin as {}...", inputs[0].name),
+        None => {
+            for input in &inputs {
+                println!("Packing {}...", input.path.display());
+            }
+        }
+    }
+    if header.is_dir && !args.quiet && !args.json {
+        println!(
+            "Found {} file(s) and {} directory(ies), {} in total",
+            HumanCount(header.file_count),
+            HumanCount(header.dir_count),
+            HumanBytes(total_size)
+        );
+    }
+    let written = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let pb = if from_stdin {
+        // The payload size is unknown up front, so show a byte counter instead
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(
+            ProgressStyle::default_spinner()
+                .with_key("written", written_key(written.clone()))
+                .template(
+                    "{spinner:.green} [{elapsed_precise}] {bytes} read, {written} ({bytes_per_sec})",
+                )?,
+        );
+        pb
+    } else {
+        let pb = ProgressBar::new(total_size);
+        pb.set_style(ProgressStyle::default_bar()
+            .with_key("written", written_key(written.clone()))
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} read, {written} ({bytes_per_sec}, {eta})")?
+            .progress_chars("#>-"));
+        pb
+    };
+    if args.quiet {
+        pb.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+    } else if !std::io::stderr().is_terminal() {
+        report_progress_lines(&pb, describe_bytes);
+    }
+    let progress = (is_multi && !args.quiet).then(MultiProgress::new);
+    let pb = match &progress {
+        Some(multi) => multi.add(pb),
+        None => pb,
+    };
+
+    if args.verbose > 0 && args.compression == Compression::Zstd {
+        eprintln!("Compressing with {} thread(s)", compression_threads(&args));
+    }
+
+    let job = PackJob {
+        inputs,
+        header,
+        output_path,
+        from_stdin,
+        written,
+        progress,
+    };
+    let outcome = write_archive(&job, &credentials, &args, &pb)?;
+    pb.finish_with_message(if job.header.is_dir {
+        "Directory packed"
+    } else {
+        "File packed"
+    });
+    if args.json {
+        print_pack_json(&job.output_path, &outcome)?;
+    } else if !args.quiet {
+        println!("Archive written to {}", job.output_path.display());
+        if args.verify {
+            println!("Verified: {}", outcome.digest.digest_string());
+        }
+        let label = match job.header.codec() {
+            Ok(Compression::None) => "Stored",
+            _ => "Compressed",
+        };
+        println!("{}: {}", label, format_pack_summary(&outcome));
+        if let Some(classes) = format_class_sizes(&outcome, &args) {
+            println!("By type: {}", classes);
+        }
+    }
+    let mut skips = WalkSkips::default();
+    for input in &job.inputs {
+        skips.absorb(&input.skips);
+    }
+    skips.unreadable.extend(outcome.unreadable);
+    print_walk_skips(&skips, &args);
+
+    if args.wipe {
+        for input in &job.inputs {
+            wipe_input(input)?;
+        }
+    }
+
+    exit_if_skipped(&skips);
+    Ok(())
+}
+
+// Comment Loading (--comment / --comment-file)
+fn read_comment(args: &PackArgs) -> Result<String> {
+    let comment = match (&args.comment, &args.comment_file) {
+        (Some(text), _) => text.clone(),
+        (None, Some(path)) => {
+            let bytes = fs::read(path)
+                .with_context(|| format!("Failed to read comment file '{}'", path.display()))?;
+            String::from_utf8(bytes)
+                .map_err(|_| anyhow!("Comment file '{}' is not valid UTF-8", path.display()))?
+        }
+        (None, None) => return Ok(String::new()),
+    };
+    if comment.len() > MAX_COMMENT_LEN {
+        return Err(anyhow!(
+            "Comment is {} long; the limit is {}",
+            HumanBytes(comment.len() as u64),
+            HumanBytes(MAX_COMMENT_LEN as u64)
+        ));
+    }
+    Ok(comment)
+}
+
+// Pack Input Checks
+fn check_pack_input(input: &PackInput, output_path: &Path, force: bool) -> Result<()> {
+    check_pack_paths(&input.path, output_path, input.is_dir && input.recursive)?;
+
+    if is_rstf_path(&input.path) && !force {
+        return Err(anyhow!(
+            "'{}' is already an .rstf archive (use --force to encrypt it again)",
+            input.path.display()
+        ));
+    }
+    Ok(())
+}
+
+struct PackJob {
+    inputs: Vec<PackInput>,
+    header: RstfHeader,
+    output_path: PathBuf,
+    from_stdin: bool,
+    /// Encrypted bytes that have reached the output file, for the progress line
+    written: std::sync::Arc<std::sync::atomic::AtomicU64>,
+    /// Display the overall bar belongs to when each input gets a bar of its own
+    progress: Option<MultiProgress>,
+}
+
+// Progress Key `{written}` (`402 MiB written (28%)` against the bytes read so far)
+fn written_key(
+    written: std::sync::Arc<std::sync::atomic::AtomicU64>,
+) -> impl Fn(&indicatif::ProgressState, &mut dyn std::fmt::Write) + Clone + Send + Sync {
+    move |state, w| {
+        let written = written.load(std::sync::atomic::Ordering::Relaxed);
+        let _ = match state.pos() {
+            0 => write!(w, "{} written", HumanBytes(written)),
+            read => write!(
+                w,
+                "{} written ({:.0}%)",
+                HumanBytes(written),
+                written as f64 * 100.0 / read as f64
+            ),
+        };
+    }
+}
+
+// Archive Writer (salt, nonce, encrypted header, compressed payload)
+fn write_archive(
+    job: &PackJob,
+    credentials: &Credentials,
+    args: &PackArgs,
+    pb: &ProgressBar,
+) -> Result<PackOutcome> {
+    write_archive_from(job, credentials, args, pb, None)
+}
+
+// Archive Writer (the payload comes from `source` instead of the job's inputs when given)
+fn write_archive_from(
+    job: &PackJob,
+    credentials: &Credentials,
+    args: &PackArgs,
+    pb: &ProgressBar,
+    source: Option<&mut dyn Read>,
+) -> Result<PackOutcome> {
+    let salt: [u8; 16] = rand::thread_rng().gen();
+    let kdf = KdfParams::for_pack(args);
+    let key = ArchiveKey {
+        // --no-encrypt archives have no secret; their chunk checksums take no key
+        key: if args.no_encrypt {
+            [0u8; 32]
+        } else {
+            credentials.derive_key(&salt, &kdf)?
+        },
+        salt,
+        kdf,
+    };
+    // Timed from here so the summary throughput leaves out key derivation
+    let started = std::time::Instant::now();
+    let output_file = create_output_file(&job.output_path, args.overwrite, "--overwrite")?;
+    let result = write_archive_contents(job, output_file, &key, args, pb, source);
+    if result.is_err() {
+        // Never leave a half-written archive behind
+        let _ = fs::remove_file(&job.output_path);
+    }
+    let WrittenPayload {
+        input_size,
+        bypassed,
+        class_sizes,
+        unreadable,
+        digest,
+    } = result?;
+    let elapsed = started.elapsed();
+    if args.verify {
+        verify_archive(
+            &job.output_path,
+            &key.key,
+            &digest,
+            args.dictionary.as_deref(),
+            pb,
+        )
+        .map_err(|e| {
+            e.context(format!(
+                "Verification of '{}' failed; the archive should not be trusted",
+                job.output_path.display()
+            ))
+        })?;
+    }
+
+    let archive_size = fs::metadata(&job.output_path)
+        .context("Failed to read archive metadata")?
+        .len();
+    Ok(PackOutcome {
+        input_size,
+        archive_size,
+        elapsed,
+        bypassed,
+        class_sizes,
+        unreadable,
+        digest,
+    })
+}
+
+// Pack Credentials Helper (--no-encrypt asks for nothing, but says what it means once)
+fn pack_credentials(args: &PackArgs) -> Result<Credentials> {
+    if !args.no_encrypt {
+        return Credentials::read(args.keyfile.as_deref(), args.quiet);
+    }
+    eprintln!("Warning: --no-encrypt writes an archive anyone can read; it is checked for damage but not protected");
+    Ok(Credentials::none())
+}
+
+// Key material of an archive being written (the KDF inputs go into its prologue)
+struct ArchiveKey {
+    salt: [u8; 16],
+    kdf: KdfParams,
+    key: [u8; 32],
+}
+
+// What write_archive_contents put into the payload
+struct WrittenPayload {
+    input_size: u64,
+    /// File bytes --smart-compress stored without compressing them
+    bypassed: u64,
+    /// File bytes per --adaptive class, in FileClass::ALL order
+    class_sizes: Option<[u64; 3]>,
+    unreadable: UnreadableList,
+    digest: Trailer,
+}
+
+// What writing one archive produced
+struct PackOutcome {
+    /// Bytes read from the inputs (file data only, not tar headers)
+    input_size: u64,
+    /// Size of the finished archive file
+    archive_size: u64,
+    /// Time spent writing the archive, without key derivation or --verify
+    elapsed: std::time::Duration,
+    /// Input bytes --smart-compress stored uncompressed
+    bypassed: u64,
+    /// Input bytes per --adaptive class, in FileClass::ALL order
+    class_sizes: Option<[u64; 3]>,
+    /// Digest of the payload as it was written
+    digest: Trailer,
+    /// Entries --skip-unreadable left out while reading them
+    unreadable: UnreadableList,
+}
+
+// Compressed Payload Size (archive minus prologue, chunk tags, header and footer)
+fn compressed_payload_size(archive_size: u64, header: &RstfHeader) -> u64 {
+    let tag = TAG_SIZE as u64;
+    let archive_size = if header.format_version >= 12 {
+        archive_size.saturating_sub(ChecksumFooter::LEN as u64)
+    } else {
+        archive_size
+    };
+    let sealed_chunk = (header.chunk_size + TAG_SIZE) as u64;
+    let header_len = header.encoded_len as u64;
+    let (trailer_len, version) = (header.trailer_len, header.format_version);
+    let prologue = prologue_len(version, header.cipher);
+    if version >= 2 {
+        let header_chunks = (4 + tag) + (header_len + tag);
+        let trailer_chunk = if trailer_len > 0 {
+            trailer_len as u64 + tag
+        } else {
+            0
+        };
+        let payload = archive_size.saturating_sub(prologue + header_chunks + trailer_chunk);
+        payload.saturating_sub(tag * payload.div_ceil(sealed_chunk))
+    } else {
+        let ciphertext = archive_size.saturating_sub(prologue);
+        let chunks = ciphertext.div_ceil(sealed_chunk);
+        ciphertext.saturating_sub(tag * chunks + 4 + header_len)
+    }
+}
+
+// Pack Summary (`4.31 GiB → 1.02 GiB (23.7%) in 2m14s, 32.9 MiB/s`)
+fn format_pack_summary(outcome: &PackOutcome) -> String {
+    let summary = format!(
+        "{} in {}, {}/s",
+        format_ratio(outcome.input_size, outcome.archive_size),
+        format_elapsed(outcome.elapsed),
+        HumanBytes(throughput(outcome))
+    );
+    if outcome.bypassed > 0 {
+        format!(
+            "{} ({} bypassed compression)",
+            summary,
+            HumanBytes(outcome.bypassed)
+        )
+    } else {
+        summary
+    }
+}
+
+// Adaptive Summary (`text 1.20 MiB at level 19, other 310 KiB at level 3, compressed 5.02 MiB stored`)
+fn format_class_sizes(outcome: &PackOutcome, args: &PackArgs) -> Option<String> {
+    let sizes = outcome.class_sizes?;
+    let classes: Vec<String> = FileClass::ALL
+        .iter()
+        .zip(sizes)
+        .map(|(class, size)| match class.level(args) {
+            Some(level) => format!("{} {} at level {}", class.name(), HumanBytes(size), level),
+            None => format!("{} {} stored", class.name(), HumanBytes(size)),
+        })
+        .collect();
+    Some(classes.join(", "))
+}
+
+// Throughput Helper (input bytes per second of wall time)
+fn throughput(outcome: &PackOutcome) -> u64 {
+    let secs = outcome.elapsed.as_secs_f64();
+    if secs > 0.0 {
+        (outcome.input_size as f64 / secs) as u64
+    } else {
+        0
+    }
+}
+
+// Elapsed Time Helper (`0.4s`, `2m14s`, `1h02m09s`)
+fn format_elapsed(elapsed: std::time::Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..=59 => format!("{:.1}s", elapsed.as_secs_f64()),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60),
+    }
+}
+
+// JSON Pack Summary (--json)
+fn print_pack_json(archive: &Path, outcome: &PackOutcome) -> Result<()> {
+    #[derive(Serialize)]
+    struct JsonSummary {
+        archive: String,
+        input_size: u64,
+        archive_size: u64,
+        elapsed_secs: f64,
+        bytes_per_sec: u64,
+        digest: String,
+        bypassed_size: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        class_sizes: Option<std::collections::BTreeMap<&'static str, u64>>,
+    }
+
+    let summary = JsonSummary {
+        archive: archive.display().to_string(),
+        input_size: outcome.input_size,
+        archive_size: outcome.archive_size,
+        elapsed_secs: outcome.elapsed.as_secs_f64(),
+        bytes_per_sec: throughput(outcome),
+        digest: outcome.digest.digest_string(),
+        bypassed_size: outcome.bypassed,
+        class_sizes: outcome.class_sizes.map(|sizes| {
+            FileClass::ALL
+                .iter()
+                .map(|class| class.name())
+                .zip(sizes)
+                .collect()
+        }),
+    };
+    println!("{}", serde_json::to_string(&summary)?);
+    Ok(())
+}
+
+// Compression Summary (`1.2 GiB → 310 MiB (25.8%)`)
+fn format_ratio(original: u64, compressed: u64) -> String {
+    if original == 0 {
+        return format!("{} → {}", HumanBytes(original), HumanBytes(compressed));
+    }
+    format!(
+        "{} → {} ({:.1}%)",
+        HumanBytes(original),
+        HumanBytes(compressed),
+        compressed as f64 * 100.0 / original as f64
+    )
+}
+
+fn write_archive_contents(
+    job: &PackJob,
+    output_file: File,
+    key: &ArchiveKey,
+    args: &PackArgs,
+    pb: &ProgressBar,
+    source: Option<&mut dyn Read>,
+) -> Result<WrittenPayload> {
+    let output_file = CountingWriter {
+        inner: output_file,
+        count: job.written.clone(),
+    };
+    // Everything up to the checksum footer is hashed on its way to the file
+    let mut writer = HashingWriter::new(BufWriter::with_capacity(CHUNK_SIZE, output_file));
+
+    let prologue = Prologue {
+        version: FORMAT_VERSION,
+        flags: if args.no_encrypt { FLAG_PLAINTEXT } else { 0 },
+        kdf: key.kdf,
+        chunk_size: args.chunk_size,
+        cipher: args.cipher,
+        salt: key.salt,
+        nonce: {
+            let mut nonce = vec![0u8; args.cipher.nonce_len()];
+            rand::thread_rng().fill(&mut nonce[..]);
+            nonce
+        },
+        commitment: Some(key_commitment(&key.key)),
+    };
+    writer.write_all(&prologue.to_bytes())?;
+
+    let header_bytes = job.header.to_bytes()?;
+    let header_len = header_bytes.len() as u32;
+
+    let cipher = ChunkCipher::new(&prologue, &key.key)?;
+    let mut crypto_writer = EncryptedWriter::new(writer, cipher, args.chunk_size, prologue.aad());
+
+    // Length and header get chunks of their own so payload chunks never carry header bytes
+    crypto_writer.write_chunk(&header_len.to_le_bytes())?;
+    crypto_writer.write_chunk(&header_bytes)?;
+
+    let payload_writer = PayloadEncoder::new(crypto_writer, &job.header, args)?;
+    // The digest covers the uncompressed payload (raw file bytes or tar stream)
+    let mut payload_writer = HashingWriter::new(payload_writer);
+
+    let mut input_size = 0;
+    let mut bypassed = 0;
+    let mut class_sizes = args.adaptive.then_some([0u64; 3]);
+    let mut unreadable = Vec::new();
+    // Payload offset of each tar entry, for the --index chunk index
+    let mut entry_offsets = Vec::new();
+    // Archive name of the first occurrence of each multiply-linked file
+    let mut linked: std::collections::HashMap<(u64, u64), PathBuf> =
+        std::collections::HashMap::new();
+    if let Some(source) = source {
+        // A repacked payload (tar stream included) is copied as it decodes
+        input_size = std::io::copy(source, &mut payload_writer)
+            .context("Failed to read the payload of the old archive")?;
+    } else if job.header.is_dir {
+        let input_style = ProgressStyle::default_bar()
+            .template("  [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}) {msg}")?
+            .progress_chars("#>-");
+        let mut tar_builder = tar::Builder::new(&mut payload_writer);
+        // --sparse entries are written by append_entry, which fits their headers too
+        tar_builder.sparse(false);
+        for input in &job.inputs {
+            // Several inputs each get a bar of their own above the overall one
+            let input_bar = job.progress.as_ref().map(|multi| {
+                let bar = multi.insert_before(pb, ProgressBar::new(input.size));
+                bar.set_style(input_style.clone());
+                bar.set_message(input.name.clone());
+                bar
+            });
+            let reading = input_bar.as_ref().unwrap_or(pb);
+            let input_start = pb.position();
+            if input.is_dir && input.recursive {
+                let mut entries: Vec<&TreeEntry> = input.entries.iter().collect();
+                if args.adaptive {
+                    // Directories keep their walk order ahead of the files, which
+                    // follow one type after another so each level runs as one frame
+                    entries
+                        .sort_by_key(|entry| (!entry.is_dir).then(|| FileClass::of(&entry.path)));
+                }
+                for entry in entries {
+                    // Contents-only archives store entries relative to the directory root
+                    let name = if job.header.contents_only {
+                        entry.relative.clone()
+                    } else {
+                        Path::new(&input.name).join(&entry.relative)
+                    };
+                    if name.as_os_str().is_empty() {
+                        continue;
+                    }
+                    let first_link = entry
+                        .inode
+                        .filter(|_| !args.no_hard_links)
+                        .and_then(|inode| linked.get(&inode));
+                    // A symlinked input directory is still stored as a directory
+                    let follow = entry.is_dir || args.dereference;
+                    let started = std::time::Instant::now();
+                    let position = reading.position();
+                    // Links carry no data, so only first occurrences are probed
+                    let class =
+                        (args.adaptive && !entry.is_dir).then(|| FileClass::of(&entry.path));
+                    let store = job.header.segmented
+                        && first_link.is_none()
+                        && !entry.is_dir
+                        && (class == Some(FileClass::Compressed)
+                            || args.smart_compress && looks_incompressible(&entry.path, args));
+                    select_codec(&mut tar_builder.get_mut().inner, store, class, args)?;
+                    let offset = tar_builder.get_mut().count;
+                    let appended = if let Some(first) = first_link {
+                        path_metadata(&entry.path, follow).and_then(|metadata| {
+                            let source = EntrySource::LinkTo(first);
+                            append_entry(
+                                &mut tar_builder,
+                                &entry.path,
+                                &name,
+                                &metadata,
+                                source,
+                                args,
+                                reading,
+                            )
+                        })
+                    } else if args.skip_unreadable && !entry.is_dir {
+                        append_readable(&mut tar_builder, &entry.path, &name, args, reading)?
+                    } else {
+                        path_metadata(&entry.path, follow).and_then(|metadata| {
+                            let source = EntrySource::Disk;
+                            append_entry(
+                                &mut tar_builder,
+                                &entry.path,
+                                &name,
+                                &metadata,
+                                source,
+                                args,
+                                reading,
+                            )
+                        })
+                    };
+                    match appended {
+                        Ok(()) => {
+                            entry_offsets.push((name.to_string_lossy().to_string(), offset));
+                            input_size += entry.size;
+                            if store {
+                                bypassed += entry.size;
+                            }
+                            if let (Some(sizes), Some(class)) = (&mut class_sizes, class) {
+                                sizes[class as usize] += entry.size;
+                            }
+                            print_verbose_entry(reading, args.verbose, &name, entry.size, started);
+                            if let Some(inode) = entry.inode.filter(|_| !args.no_hard_links) {
+                                linked.entry(inode).or_insert(name);
+                            }
+                        }
+                        Err(e) if args.skip_unreadable => {
+                            unreadable.push((entry.path.clone(), e.to_string()))
+                        }
+                        Err(e) => {
+                            return Err(anyhow!(e)
+                                .context(format!("Failed to pack '{}'", entry.path.display())))
+                        }
+                    }
+                    // File data moves the bar as it is read; land exactly on the walked size
+                    reading.set_position(position + entry.size);
+                    if input_bar.is_some() {
+                        pb.set_position(input_start + reading.position());
+                    }
+                }
+            } else {
+                // Inputs named on the command line are always followed
+                let started = std::time::Instant::now();
+                let position = reading.position();
+                let class = (args.adaptive && !input.is_dir).then(|| FileClass::of(&input.path));
+                let store = job.header.segmented
+                    && !input.is_dir
+                    && (class == Some(FileClass::Compressed)
+                        || args.smart_compress && looks_incompressible(&input.path, args));
+                select_codec(&mut tar_builder.get_mut().inner, store, class, args)?;
+                entry_offsets.push((input.name.clone(), tar_builder.get_mut().count));
+                path_metadata(&input.path, true)
+                    .and_then(|metadata| {
+                        let name = Path::new(&input.name);
+                        append_entry(
+                            &mut tar_builder,
+                            &input.path,
+                            name,
+                            &metadata,
+                            EntrySource::Disk,
+                            args,
+                            reading,
+                        )
+                    })
+                    .with_context(|| format!("Failed to pack '{}'", input.path.display()))?;
+                let name = Path::new(&input.name);
+                print_verbose_entry(reading, args.verbose, name, input.size, started);
+                input_size += input.size;
+                if store {
+                    bypassed += input.size;
+                }
+                if let (Some(sizes), Some(class)) = (&mut class_sizes, class) {
+                    sizes[class as usize] += input.size;
+                }
+                reading.set_position(position + input.size);
+            }
+            if let Some(bar) = input_bar {
+                pb.set_position(input_start + input.size);
+                bar.finish_and_clear();
+            }
+        }
+        tar_builder.finish()?;
+    } else if job.from_stdin {
+        let mut input_with_pb = pb.wrap_read(std::io::stdin().lock());
+        input_size = std::io::copy(&mut input_with_pb, &mut payload_writer)
+            .context("Failed to read stdin")?;
+    } else if let Some(extents) = &job.header.extents {
+        let mut input_file = File::open(&job.inputs[0].path)?;
+        for &(offset, len) in extents {
+            input_file.seek(SeekFrom::Start(offset))?;
+            let copied = std::io::copy(&mut (&mut input_file).take(len), &mut payload_writer)?;
+            if copied != len {
+                return Err(anyhow!(
+                    "'{}' shrank while it was being packed",
+                    job.inputs[0].path.display()
+                ));
+            }
+            pb.set_position(offset + len);
+        }
+        pb.set_position(job.header.original_size);
+        input_size = job.header.original_size;
+    } else {
+        let input_file = File::open(&job.inputs[0].path)?;
+        let mut input_with_pb = pb.wrap_read(input_file);
+        input_size = std::io::copy(&mut input_with_pb, &mut payload_writer)?;
+    }
+
+    if (args.smart_compress || args.adaptive)
+        && !job.header.is_dir
+        && job.header.codec()? == Compression::None
+    {
+        // A single file judged incompressible was stored whole
+        bypassed = input_size;
+    }
+    if let Some(sizes) = &mut class_sizes {
+        if !job.header.is_dir && !job.from_stdin {
+            sizes[FileClass::of(&job.inputs[0].path) as usize] = input_size;
+        }
+    }
+
+    let (payload_writer, trailer) = payload_writer.finish();
+    let index = payload_writer.index_frames().map(|frames| ChunkIndex {
+        frames,
+        entries: entry_offsets,
+        ..Default::default()
+    });
+    let mut crypto_writer = payload_writer.finish()?;
+    let mut trailer_bytes = trailer.to_bytes();
+    if let Some(index) = index {
+        // The index continues the payload stream; the trailer says how much of it is index
+        let index_bytes = index.to_bytes();
+        crypto_writer.write_all(&index_bytes)?;
+        trailer_bytes.extend_from_slice(&(index_bytes.len() as u64).to_le_bytes());
+    } else if job.header.padded {
+        trailer_bytes.extend_from_slice(&0u64.to_le_bytes());
+    }
+    if job.header.padded {
+        // Zeros up to the padded length, sealed like the rest so they cannot be told apart
+        let stream_len = crypto_writer.stream_len();
+        let padding = padme_len(stream_len) - stream_len;
+        std::io::copy(&mut std::io::repeat(0).take(padding), &mut crypto_writer)?;
+        trailer_bytes.extend_from_slice(&padding.to_le_bytes());
+    }
+    crypto_writer.write_trailer(&trailer_bytes)?;
+    let written = crypto_writer.get_mut();
+    let footer = ChecksumFooter::blake3(*written.hasher.finalize().as_bytes());
+    written.inner.write_all(&footer.to_bytes())?;
+    written.inner.flush()?;
+    Ok(WrittenPayload {
+        input_size,
+        bypassed,
+        class_sizes,
+        unreadable,
+        digest: trailer,
+    })
+}
+
+// Archive Verification (decrypts the finished archive with the packing key and re-hashes it)
+fn verify_archive(
+    path: &Path,
+    key: &[u8; 32],
+    expected: &Trailer,
+    dictionary: Option<&[u8]>,
+    pb: &ProgressBar,
+) -> Result<()> {
+    let (mut header, crypto_reader) =
+        open_archive_with(path, OpenArchiveOptions::default(), |_| Ok(*key))?;
+    if header.dictionary.is_none() {
+        header.dictionary = dictionary.map(<[u8]>::to_vec);
+    }
+    if header.trailer.as_ref() != Some(expected) {
+        return Err(anyhow!(
+            "The stored digest does not match the packed payload"
+        ));
+    }
+
+    // The bar restarts over the whole payload (tar headers included)
+    pb.reset();
+    pb.set_length(expected.payload_size);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:30.cyan/blue}] {bytes}/{total_bytes} verified ({bytes_per_sec}) {msg}")?
+            .progress_chars("#>-"),
+    );
+    let mut hasher = HashingWriter::new(std::io::sink());
+    let mut payload_reader = pb.wrap_read(PayloadDecoder::new(crypto_reader, &header)?);
+    std::io::copy(&mut payload_reader, &mut hasher).context("Failed to decrypt payload")?;
+    let (_, computed) = hasher.finish();
+    if computed != *expected {
+        return Err(anyhow!(
+            "Digest mismatch: packed {} ({} bytes), read back {} ({} bytes)",
+            expected.digest_string(),
+            expected.payload_size,
+            computed.digest_string(),
+            computed.payload_size
+        ));
+    }
+    Ok(())
+}
+
+// Codec Settings (what the decoder needs to know about the payload compression)
+fn record_codec(header: &mut RstfHeader, args: &PackArgs, single_file: Option<&Path>) {
+    let class = single_file.filter(|_| args.adaptive).map(FileClass::of);
+    if args.smart_compress || args.adaptive {
+        let incompressible = |path: &Path| {
+            class == Some(FileClass::Compressed)
+                || args.smart_compress && looks_incompressible(path, args)
+        };
+        if single_file.is_some_and(incompressible) {
+            header.compression = Compression::None.id();
+            return;
+        }
+        header.segmented = header.is_dir;
+    }
+    header.compression = args.compression.id();
+    header.window_log = args.long;
+    header.level = match args.compression {
+        Compression::Zstd => class
+            .and_then(|class| class.level(args))
+            .or(Some(args.level())),
+        Compression::Xz => Some(args.level()),
+        Compression::Lz4 | Compression::None => None,
+    };
+    if args.adaptive && header.is_dir {
+        header.class_levels = Some(
+            FileClass::ALL
+                .iter()
+                .map(|class| (class.name().to_string(), class.level(args)))
+                .collect(),
+        );
+    }
+    header.dict_id = args.dictionary.as_deref().map(dictionary_id);
+    if args.embed_dict {
+        header.dictionary = args.dictionary.clone();
+    }
+    // The trailer grows by the index length, and padded ones by the padding length too
+    header.index_span = args.index;
+    header.padded = args.pad;
+    if args.pad {
+        header.trailer_len = Trailer::PADDED_LEN as u32;
+    } else if args.index.is_some() {
+        header.trailer_len = Trailer::INDEXED_LEN as u32;
+    }
+}
+
+// --smart-compress probes this much of each file, and leaves smaller ones to zstd
+const SMART_SAMPLE_SIZE: u64 = 64 * 1024;
+const SMART_MIN_SIZE: u64 = 4 * 1024;
+
+// Compressibility Probe (trial zstd pass over the start of a regular file)
+fn looks_incompressible(path: &Path, args: &PackArgs) -> bool {
+    match path_metadata(path, args.dereference) {
+        Ok(metadata) if metadata.is_file() && metadata.len() >= SMART_MIN_SIZE => {}
+        _ => return false,
+    }
+    let mut sample = Vec::new();
+    let read =
+        File::open(path).and_then(|file| file.take(SMART_SAMPLE_SIZE).read_to_end(&mut sample));
+    // Unreadable files are left to the normal path, which reports them
+    if read.is_err() || sample.is_empty() {
+        return false;
+    }
+    match zstd::bulk::compress(&sample, 1) {
+        Ok(compressed) => {
+            compressed.len() as u64 * 100 >= sample.len() as u64 * args.smart_threshold as u64
+        }
+        Err(_) => false,
+    }
+}
+
+// --adaptive level for text, unless --level asks for more
+const ADAPTIVE_TEXT_LEVEL: i32 = 19;
+
+// Extensions --adaptive compresses hard
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "rst", "csv", "tsv", "json", "jsonl", "xml", "html", "htm", "css", "scss", "js",
+    "mjs", "ts", "jsx", "tsx", "vue", "rs", "c", "h", "cc", "cpp", "hpp", "cs", "go", "java", "kt",
+    "swift", "py", "rb", "php", "pl", "lua", "sh", "bash", "zsh", "ps1", "sql", "toml", "yaml",
+    "yml", "ini", "cfg", "conf", "lock", "log", "tex", "svg", "po",
+];
+
+// Extensions --adaptive stores (compressed media and archives, including zip-based documents)
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "avif", "mp3", "m4a", "aac", "ogg", "opus",
+    "flac", "mp4", "m4v", "mkv", "webm", "mov", "avi", "zip", "gz", "tgz", "bz2", "xz", "zst",
+    "lz4", "7z", "rar", "jar", "apk", "deb", "rpm", "woff", "woff2", "docx", "xlsx", "pptx", "odt",
+    "ods", "epub", "rstf",
+];
+
+// File Types of --adaptive (declaration order is the order files are packed in)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum FileClass {
+    Text,
+    Other,
+    Compressed,
+}
+
+impl FileClass {
+    const ALL: [FileClass; 3] = [FileClass::Text, FileClass::Other, FileClass::Compressed];
+
+    fn of(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some(e) if TEXT_EXTENSIONS.contains(&e) => FileClass::Text,
+            Some(e) if COMPRESSED_EXTENSIONS.contains(&e) => FileClass::Compressed,
+            _ => FileClass::Other,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FileClass::Text => "text",
+            FileClass::Other => "other",
+            FileClass::Compressed => "compressed",
+        }
+    }
+
+    /// zstd level for files of this type (None: stored)
+    fn level(self, args: &PackArgs) -> Option<i32> {
+        match self {
+            FileClass::Text => Some(max(args.level(), ADAPTIVE_TEXT_LEVEL)),
+            FileClass::Other => Some(args.level()),
+            FileClass::Compressed => None,
+        }
+    }
+}
+
+// Entry Codec (stored run or zstd level for the next tar entry of a segmented payload)
+fn select_codec<W: Write>(
+    encoder: &mut PayloadEncoder<W>,
+    store: bool,
+    class: Option<FileClass>,
+    args: &PackArgs,
+) -> Result<()> {
+    encoder.set_storing(store)?;
+    if let Some(level) = class.and_then(|class| class.level(args)).filter(|_| !store) {
+        encoder.set_level(level, args)?;
+    }
+    Ok(())
+}
+
+// zstd dictionaries start with magic 0xEC30A437 and a little-endian u32 ID
+const ZSTD_DICT_MAGIC: [u8; 4] = [0x37, 0xA4, 0x30, 0xEC];
+
+// Dictionary ID Helper (0 for raw-content dictionaries, which carry none)
+fn dictionary_id(dictionary: &[u8]) -> u32 {
+    match dictionary.get(..8) {
+        Some(start) if start[..4] == ZSTD_DICT_MAGIC => {
+            u32::from_le_bytes([start[4], start[5], start[6], start[7]])
+        }
+        _ => 0,
+    }
+}
+
+// Dictionary Loading (reader-side --dict, checked against the ID the archive needs)
+fn attach_dictionary(header: &mut RstfHeader, path: Option<&Path>) -> Result<()> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    let Some(needed) = header.dict_id else {
+        eprintln!(
+            "Warning: '{}' was packed without a dictionary; --dict is ignored",
+            header.original_name
+        );
+        return Ok(());
+    };
+    if header.dictionary.is_some() {
+        // An embedded dictionary is authenticated with the header, so it wins
+        return Ok(());
+    }
+    let dictionary = fs::read(path)
+        .with_context(|| format!("Failed to read dictionary '{}'", path.display()))?;
+    let found = dictionary_id(&dictionary);
+    if found != needed {
+        return Err(anyhow!(
+            "'{}' is dictionary {}, but the archive was packed with dictionary {}",
+            path.display(),
+            found,
+            needed
+        ));
+    }
+    header.dictionary = Some(dictionary);
+    Ok(())
+}
+
+// SOURCE_DATE_EPOCH Helper (unset or empty means mtimes are kept as they are)
+fn source_date_epoch() -> Result<Option<u64>> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) if !value.trim().is_empty() => value.trim().parse().map(Some).map_err(|_| {
+            anyhow!(
+                "SOURCE_DATE_EPOCH must be a Unix timestamp, got '{}'",
+                value
+            )
+        }),
+        _ => Ok(None),
+    }
+}
+
+// Reproducible Header (no creation time, owner or mtime newer than SOURCE_DATE_EPOCH)
+fn make_reproducible(header: &mut RstfHeader, args: &PackArgs) {
+    header.created_at = args.source_date_epoch.unwrap_or_default();
+    header.owner = None;
+    if let (Some(mtime), Some(epoch)) = (header.mtime, args.source_date_epoch) {
+        header.mtime = Some(mtime.min(epoch as i64));
+    }
+}
+
+// Numeric Owner Helper (the ids stay, the names are left out under --numeric-owner)
+fn strip_owner_names(header: &mut RstfHeader) {
+    if let Some(owner) = header.owner.as_mut() {
+        owner.user.clear();
+        owner.group.clear();
+    }
+}
+
+// Tar Owner Names (uname/gname, which set_metadata leaves empty; a name too long for
+// the 32-byte fields is left out and unpack falls back to the ids)
+#[cfg(unix)]
+fn set_owner_names(header: &mut tar::Header, metadata: &fs::Metadata) {
+    use std::os::unix::fs::MetadataExt;
+    thread_local! {
+        // Most trees have a handful of owners, so each is looked up once
+        static NAMES: std::cell::RefCell<std::collections::HashMap<(bool, u32), String>> = Default::default();
+    }
+    let lookup = |is_group: bool, id: u32| {
+        NAMES.with_borrow_mut(|names| {
+            names
+                .entry((is_group, id))
+                .or_insert_with(|| {
+                    if is_group {
+                        group_name(id)
+                    } else {
+                        user_name(id)
+                    }
+                    .unwrap_or_default()
+                })
+                .clone()
+        })
+    };
+    let _ = header.set_username(&lookup(false, metadata.uid()));
+    let _ = header.set_groupname(&lookup(true, metadata.gid()));
+}
+
+// Reproducible Tar Header (ids zeroed and the mtime clamped under --reproducible)
+fn normalize_header(header: &mut tar::Header, args: &PackArgs) {
+    if !args.reproducible {
+        return;
+    }
+    header.set_uid(0);
+    header.set_gid(0);
+    let _ = header.set_username("");
+    let _ = header.set_groupname("");
+    if let Some(epoch) = args.source_date_epoch {
+        header.set_mtime(header.mtime().unwrap_or_default().min(epoch));
+    }
+    header.set_cksum();
+}
+
+// What a tar entry holds besides the metadata of its path
+enum EntrySource<'a> {
+    /// Read from the path itself
+    Disk,
+    /// Contents already read by --skip-unreadable
+    Loaded(&'a [u8]),
+    /// A hard link to the archive name of the first occurrence
+    LinkTo(&'a Path),
+}
+
+fn path_metadata(path: &Path, follow: bool) -> std::io::Result<fs::Metadata> {
+    if follow {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    }
+}
+
+// Tar Entry Append (headers for --tar-format; everything that can fail is done before writing)
+fn append_entry<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    name: &Path,
+    metadata: &fs::Metadata,
+    source: EntrySource,
+    args: &PackArgs,
+    pb: &ProgressBar,
+) -> std::io::Result<()> {
+    if is_socket(metadata) {
+        return Err(std::io::Error::other("sockets cannot be archived"));
+    }
+    let mut records = pax_attributes(path, args)?;
+    let from_disk = matches!(source, EntrySource::Disk) && metadata.is_file();
+    let extents = (from_disk && args.sparse && !args.reproducible)
+        .then(|| sparse_extents(path))
+        .flatten();
+
+    // Holes only have a GNU sparse entry to go in, whatever the rest uses
+    let mut header = match args.tar_format {
+        TarFormat::Gnu => tar::Header::new_gnu(),
+        _ if extents.is_some() => tar::Header::new_gnu(),
+        TarFormat::Ustar | TarFormat::Pax => tar::Header::new_ustar(),
+    };
+    header.set_metadata(metadata);
+    #[cfg(unix)]
+    if !args.numeric_owner && !args.reproducible {
+        set_owner_names(&mut header, metadata);
+    }
+    let data: Box<dyn Read + '_> = match source {
+        EntrySource::Loaded(data) => {
+            header.set_size(data.len() as u64);
+            Box::new(data)
+        }
+        EntrySource::Disk if metadata.is_file() => match &extents {
+            Some(extents) => {
+                let map = set_sparse_map(&mut header, extents, metadata.len());
+                let stored: u64 = extents.iter().map(|&(_, len)| len).sum();
+                // The holes count as packed once the entry is out
+                pb.inc(metadata.len() - stored);
+                let data = ShrinkPadding {
+                    inner: ExtentReader {
+                        file: File::open(path)?,
+                        extents: extents.iter().copied().collect(),
+                    },
+                    remaining: stored,
+                    path,
+                    shrunk: false,
+                };
+                Box::new(std::io::Cursor::new(map).chain(pb.wrap_read(data)))
+            }
+            None => Box::new(pb.wrap_read(ShrinkPadding {
+                inner: File::open(path)?.take(metadata.len()),
+                remaining: metadata.len(),
+                path,
+                shrunk: false,
+            })),
+        },
+        _ => {
+            header.set_size(0);
+            Box::new(std::io::empty())
+        }
+    };
+    let link = match source {
+        EntrySource::LinkTo(first) => {
+            header.set_entry_type(tar::EntryType::Link);
+            Some(first.to_path_buf())
+        }
+        _ if metadata.file_type().is_symlink() => Some(fs::read_link(path)?),
+        _ => None,
+    };
+    // set_metadata leaves the device numbers at zero
+    #[cfg(unix)]
+    if is_special_file(metadata) && link.is_none() {
+        use std::os::unix::fs::MetadataExt;
+        let device = metadata.rdev() as libc::dev_t;
+        header.set_device_major(libc::major(device) as u32)?;
+        header.set_device_minor(libc::minor(device) as u32)?;
+    }
+    normalize_header(&mut header, args);
+    let long_names = fit_tar_header(
+        &mut header,
+        name,
+        link.as_deref(),
+        args.tar_format,
+        &mut records,
+    )?;
+    header.set_cksum();
+
+    builder.append_pax_extensions(records.iter().map(|(k, v)| (k.as_str(), v.as_slice())))?;
+    for (kind, bytes) in long_names {
+        builder.append(
+            &gnu_long_name_header(kind, bytes.len()),
+            bytes.chain(&[0u8][..]),
+        )?;
+    }
+    builder.append(&header, data)
+}
+
+// Shrink Padding (zeros up to the size in the header when a file shrinks while it is
+// packed, like GNU tar; the entries after it would be misaligned otherwise)
+struct ShrinkPadding<'a, R: Read> {
+    inner: R,
+    remaining: u64,
+    path: &'a Path,
+    shrunk: bool,
+}
+
+impl<R: Read> Read for ShrinkPadding<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let want = min(buf.len() as u64, self.remaining) as usize;
+        if want == 0 {
+            return Ok(0);
+        }
+        let mut n = if self.shrunk {
+            0
+        } else {
+            self.inner.read(&mut buf[..want])?
+        };
+        if n == 0 {
+            if !self.shrunk {
+                eprintln!(
+                    "Warning: '{}' shrank by {} bytes while being packed; padded with zeros",
+                    self.path.display(),
+                    self.remaining
+                );
+                self.shrunk = true;
+            }
+            buf[..want].fill(0);
+            n = want;
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+// GNU Sparse Map (the first four extents go in the header and the rest in extension
+// blocks, returned to be written ahead of the data; a trailing hole ends the map with
+// an empty extent at the file size)
+fn set_sparse_map(header: &mut tar::Header, extents: &[(u64, u64)], size: u64) -> Vec<u8> {
+    let mut map = extents.to_vec();
+    if map.last().is_none_or(|&(offset, len)| offset + len < size) {
+        map.push((size, 0));
+    }
+    header.set_entry_type(tar::EntryType::GNUSparse);
+    header.set_size(extents.iter().map(|&(_, len)| len).sum());
+    let gnu = header
+        .as_gnu_mut()
+        .expect("sparse entries have GNU headers");
+    gnu.set_real_size(size);
+    let mut rest = map.iter();
+    for (slot, &(offset, len)) in gnu.sparse.iter_mut().zip(rest.by_ref()) {
+        slot.set_offset(offset);
+        slot.set_length(len);
+    }
+    let mut rest = rest.as_slice().chunks(21).peekable();
+    gnu.set_is_extended(rest.peek().is_some());
+    let mut blocks = Vec::new();
+    while let Some(chunk) = rest.next() {
+        let mut block = tar::GnuExtSparseHeader::new();
+        for (slot, &(offset, len)) in block.sparse.iter_mut().zip(chunk) {
+            slot.set_offset(offset);
+            slot.set_length(len);
+        }
+        block.set_is_extended(rest.peek().is_some());
+        blocks.extend_from_slice(block.as_bytes());
+    }
+    blocks
+}
+
+// Extent Reader (the data regions of a sparse file back to back, as a GNU sparse entry
+// stores them; it ends early if the file shrinks)
+struct ExtentReader {
+    file: File,
+    extents: std::collections::VecDeque<(u64, u64)>,
+}
+
+impl Read for ExtentReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some(&(offset, len)) = self.extents.front() {
+            if len == 0 {
+                self.extents.pop_front();
+                continue;
+            }
+            self.file.seek(SeekFrom::Start(offset))?;
+            let want = min(buf.len() as u64, len) as usize;
+            let n = self.file.read(&mut buf[..want])?;
+            if n > 0 {
+                self.extents[0] = (offset + n as u64, len - n as u64);
+            }
+            return Ok(n);
+        }
+        Ok(0)
+    }
+}
+
+// Largest values the octal ustar fields hold (11 digits for size and mtime, 7 for ids)
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+const USTAR_MAX_ID: u64 = 0o7777777;
+
+// Header Fitting (path, link and numbers as --tar-format allows; GNU long names are returned)
+fn fit_tar_header(
+    header: &mut tar::Header,
+    name: &Path,
+    link: Option<&Path>,
+    format: TarFormat,
+    records: &mut Vec<(String, Vec<u8>)>,
+) -> std::io::Result<Vec<(tar::EntryType, Vec<u8>)>> {
+    let mut long_names = Vec::new();
+    if let Err(e) = header.set_path(name) {
+        let bytes = path_bytes(name)?;
+        if bytes.len() <= 100 {
+            return Err(e);
+        }
+        match format {
+            TarFormat::Ustar => {
+                return Err(std::io::Error::other(format!(
+                    "its archive path is {} bytes, more than ustar holds (100, or 255 split at a '/'); use --tar-format pax",
+                    bytes.len()
+                )))
+            }
+            TarFormat::Gnu => long_names.push((tar::EntryType::GNULongName, bytes.clone())),
+            TarFormat::Pax => records.push(("path".to_string(), bytes.clone())),
+        }
+        // Readers that skip the extension still see the start of the name
+        if let Some(ustar) = header.as_ustar_mut() {
+            ustar.prefix = [0; 155];
+        }
+        truncate_into(&mut header.as_old_mut().name, &bytes);
+    }
+    if let Some(link) = link {
+        if let Err(e) = header.set_link_name(link) {
+            let bytes = path_bytes(link)?;
+            if bytes.len() <= 100 {
+                return Err(e);
+            }
+            match format {
+                TarFormat::Ustar => {
+                    return Err(std::io::Error::other(format!(
+                        "its link target is {} bytes, more than ustar's 100; use --tar-format pax",
+                        bytes.len()
+                    )))
+                }
+                TarFormat::Gnu => long_names.push((tar::EntryType::GNULongLink, bytes.clone())),
+                TarFormat::Pax => records.push(("linkpath".to_string(), bytes.clone())),
+            }
+            truncate_into(&mut header.as_old_mut().linkname, &bytes);
+        }
+    }
+    // GNU headers fall back to base-256 numbers; ustar has only octal digits
+    if format != TarFormat::Gnu {
+        let numbers = [
+            ("size", header.size()?, USTAR_MAX_SIZE),
+            ("mtime", header.mtime()?, USTAR_MAX_SIZE),
+            ("uid", header.uid()?, USTAR_MAX_ID),
+            ("gid", header.gid()?, USTAR_MAX_ID),
+        ];
+        for (key, value, max) in numbers.into_iter().filter(|(_, value, max)| value > max) {
+            if format == TarFormat::Ustar {
+                return Err(std::io::Error::other(format!(
+                    "its {} ({}) is larger than ustar holds (at most {}); use --tar-format pax",
+                    key, value, max
+                )));
+            }
+            records.push((key.to_string(), value.to_string().into_bytes()));
+        }
+    }
+    Ok(long_names)
+}
+
+fn truncate_into(field: &mut [u8], bytes: &[u8]) {
+    let len = min(field.len(), bytes.len());
+    field.fill(0);
+    field[..len].copy_from_slice(&bytes[..len]);
+}
+
+fn path_bytes(path: &Path) -> std::io::Result<Vec<u8>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(path.as_os_str().as_bytes().to_vec())
+    }
+    #[cfg(not(unix))]
+    {
+        path.to_str()
+            .map(|p| p.replace('\\', "/").into_bytes())
+            .ok_or_else(|| std::io::Error::other("path is not valid Unicode"))
+    }
+}
+
+// GNU Long Name Header (the `././@LongLink` entry GNU tar puts before a long path or link)
+fn gnu_long_name_header(kind: tar::EntryType, len: usize) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    let name = b"././@LongLink";
+    header.as_old_mut().name[..name.len()].copy_from_slice(name);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    // The name is NUL-terminated, like GNU tar writes it
+    header.set_size(len as u64 + 1);
+    header.set_entry_type(kind);
+    header.set_cksum();
+    header
+}
+
+// Files --skip-unreadable reads whole before storing; larger ones are read through
+// once to check them, then streamed
+const SKIPPABLE_READ_LIMIT: u64 = 16 * 1024 * 1024;
+const LOW_MEMORY_READ_LIMIT: u64 = 1024 * 1024;
+
+// Skippable Append (the inner error means nothing was written for the entry)
+fn append_readable<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    name: &Path,
+    args: &PackArgs,
+    pb: &ProgressBar,
+) -> Result<std::io::Result<()>> {
+    let metadata = match path_metadata(path, args.dereference) {
+        Ok(metadata) => metadata,
+        Err(e) => return Ok(Err(e)),
+    };
+    if !metadata.is_file() {
+        // Links and special files fail before anything is written for them
+        return Ok(append_entry(
+            builder,
+            path,
+            name,
+            &metadata,
+            EntrySource::Disk,
+            args,
+            pb,
+        ));
+    }
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Ok(Err(e)),
+    };
+    let limit = if args.low_memory {
+        LOW_MEMORY_READ_LIMIT
+    } else {
+        SKIPPABLE_READ_LIMIT
+    };
+    if metadata.len() > limit {
+        // Its header is out before the data, so the file is read once before that;
+        // only a failure that appears between the two reads cannot be undone
+        if let Err(e) = std::io::copy(&mut file, &mut std::io::sink()) {
+            return Ok(Err(e));
+        }
+        append_entry(builder, path, name, &metadata, EntrySource::Disk, args, pb).with_context(
+            || {
+                format!(
+                    "Failed to pack '{}' (it was readable on the first pass but only partly on the second, so it cannot be skipped)",
+                    path.display()
+                )
+            },
+        )?;
+        return Ok(Ok(()));
+    }
+    let mut data = Vec::with_capacity(metadata.len() as usize);
+    if let Err(e) = pb.wrap_read(&mut file).read_to_end(&mut data) {
+        return Ok(Err(e));
+    }
+    Ok(append_entry(
+        builder,
+        path,
+        name,
+        &metadata,
+        EntrySource::Loaded(&data),
+        args,
+        pb,
+    ))
+}
+
+// Extended attributes travel as SCHILY.xattr.<name> PAX records, like GNU tar and bsdtar
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+fn check_xattrs_support(requested: bool) -> Result<()> {
+    if requested && !cfg!(all(unix, feature = "xattrs")) {
+        return Err(anyhow!(
+            "--xattrs needs a Unix build with the `xattrs` feature (cargo build --features xattrs)"
+        ));
+    }
+    Ok(())
+}
+
+// POSIX ACLs travel as their raw system.posix_acl_* xattr values under RSTF.acl.* records
+const PAX_ACL_RECORDS: [(&str, &str); 2] = [
+    ("RSTF.acl.access", "system.posix_acl_access"),
+    ("RSTF.acl.default", "system.posix_acl_default"),
+];
+
+fn check_acls_support(requested: bool) {
+    if requested && !cfg!(all(target_os = "linux", feature = "acls")) {
+        eprintln!(
+            "Warning: --acls needs a Linux build with the `acls` feature (cargo build --features acls); ACLs are ignored"
+        );
+    }
+}
+
+// Attribute Records Helper (xattrs and ACLs for the PAX header of an entry)
+fn pax_attributes(path: &Path, args: &PackArgs) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    let mut records = xattr_records(path, args)?;
+    records.extend(acl_records(path, args)?);
+    // Listing order is up to the filesystem
+    records.sort();
+    Ok(records)
+}
+
+// Xattr Records Helper (empty without --xattrs)
+#[cfg(all(unix, feature = "xattrs"))]
+fn xattr_records(path: &Path, args: &PackArgs) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    if !args.xattrs {
+        return Ok(Vec::new());
+    }
+    let names = match if args.dereference {
+        xattr::list_deref(path)
+    } else {
+        xattr::list(path)
+    } {
+        Ok(names) => names,
+        // A filesystem without xattr support simply has none to store
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut records = Vec::new();
+    for name in names {
+        let Some(key) = name.to_str() else {
+            eprintln!(
+                "Warning: skipped extended attribute with a non-UTF-8 name on {}",
+                path.display()
+            );
+            continue;
+        };
+        // ACLs are only stored when asked for with --acls
+        if PAX_ACL_RECORDS.iter().any(|(_, acl)| *acl == key) {
+            continue;
+        }
+        let value = if args.dereference {
+            xattr::get_deref(path, &name)?
+        } else {
+            xattr::get(path, &name)?
+        };
+        if let Some(value) = value {
+            records.push((format!("{}{}", PAX_XATTR_PREFIX, key), value));
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(not(all(unix, feature = "xattrs")))]
+fn xattr_records(_path: &Path, _args: &PackArgs) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+// ACL Records Helper (empty without --acls)
+#[cfg(all(target_os = "linux", feature = "acls"))]
+fn acl_records(path: &Path, args: &PackArgs) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    if !args.acls {
+        return Ok(Vec::new());
+    }
+    let mut records = Vec::new();
+    for (key, name) in PAX_ACL_RECORDS {
+        let value = match if args.dereference {
+            xattr::get_deref(path, name)
+        } else {
+            xattr::get(path, name)
+        } {
+            Ok(value) => value,
+            // Symlinks and filesystems without ACL support have none
+            Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => None,
+            Err(e) => return Err(e),
+        };
+        if let Some(value) = value {
+            records.push((key.to_string(), value));
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(not(all(target_os = "linux", feature = "acls")))]
+fn acl_records(_path: &Path, _args: &PackArgs) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+// Compression Threads (--threads clamped to the cores there are)
+fn compression_threads(args: &PackArgs) -> u32 {
+    // Batch mode already runs one archive per core; reproducible output stays single-threaded
+    if args.each || args.reproducible || args.low_memory {
+        return 1;
+    }
+    let available = num_cpus::get();
+    match args.threads {
+        0 => available as u32,
+        n => n.min(available) as u32,
+    }
+}
+
+// zstd window of --low-memory packs (1 MiB)
+const LOW_MEMORY_WINDOW_LOG: u32 = 20;
+
+// What rstf itself needs besides the codec and the KDF (buffers, tar, progress bars)
+const BASE_MEMORY: u64 = 12 << 20;
+
+// zstd (window, chain, hash) logs per level for inputs over 256 KiB, from zstd's
+// clevels.h; row 0 is the one negative levels use
+const ZSTD_LEVEL_LOGS: [(u32, u32, u32); 23] = [
+    (19, 12, 13),
+    (19, 13, 14),
+    (20, 15, 16),
+    (21, 16, 17),
+    (21, 18, 18),
+    (21, 18, 19),
+    (21, 18, 19),
+    (21, 19, 20),
+    (21, 19, 20),
+    (22, 20, 21),
+    (22, 21, 22),
+    (22, 21, 22),
+    (22, 22, 23),
+    (22, 22, 22),
+    (22, 22, 23),
+    (22, 23, 23),
+    (22, 22, 22),
+    (23, 23, 22),
+    (23, 23, 22),
+    (23, 24, 22),
+    (25, 25, 23),
+    (26, 26, 24),
+    (27, 27, 25),
+];
+
+// xz encoder memory per preset 0-9, in MiB (as `xz -vv` reports it)
+const XZ_PRESET_MEMORY: [u64; 10] = [3, 9, 17, 32, 48, 94, 94, 186, 370, 674];
+
+// zstd decoder memory besides the window (two blocks and the context)
+const ZSTD_DECODER_OVERHEAD: u64 = 1 << 20;
+
+// Memory Budget (--memory-limit minus what rstf needs anyway)
+fn memory_budget(limit: u64) -> Result<u64> {
+    limit
+        .checked_sub(BASE_MEMORY)
+        .filter(|&budget| budget > 0)
+        .ok_or_else(|| {
+            anyhow!(
+                "--memory-limit {} is too small; rstf needs about {} besides compression",
+                HumanBytes(limit),
+                HumanBytes(BASE_MEMORY)
+            )
+        })
+}
+
+// KDF Memory Check (Argon2 runs before compression, so it only has to fit on its own)
+fn check_kdf_memory(kdf: &KdfParams, limit: u64) -> Result<()> {
+    let needed = kdf.m_cost as u64 * 1024;
+    let budget = memory_budget(limit)?;
+    if needed > budget {
+        return Err(anyhow!(
+            "Argon2 key derivation needs {}, but --memory-limit {} leaves {} for it",
+            HumanBytes(needed),
+            HumanBytes(limit),
+            HumanBytes(budget)
+        ));
+    }
+    Ok(())
+}
+
+// zstd Parameter Logs (window, chain and hash for a level, after --long and --low-memory)
+fn zstd_logs(args: &PackArgs, level: i32) -> (u32, u32, u32) {
+    let row = match level {
+        ..=-1 => 0,
+        // Level 0 means zstd's default
+        0 => 3,
+        level => level.min(22) as usize,
+    };
+    let (mut window_log, mut chain_log, mut hash_log) = ZSTD_LEVEL_LOGS[row];
+    if let Some(long) = args.long {
+        window_log = long;
+    }
+    if args.low_memory && level > 2 {
+        window_log = window_log.min(LOW_MEMORY_WINDOW_LOG);
+        if level > 9 {
+            chain_log = chain_log.min(LOW_MEMORY_WINDOW_LOG);
+            hash_log = hash_log.min(LOW_MEMORY_WINDOW_LOG);
+        }
+    }
+    (window_log, chain_log, hash_log)
+}
+
+// Compression Memory Estimate (one encoder at `level` with `threads` zstd workers)
+fn compression_memory(args: &PackArgs, level: i32, threads: usize) -> u64 {
+    match args.compression {
+        Compression::Zstd => {
+            let (window_log, chain_log, hash_log) = zstd_logs(args, level);
+            let window = 1u64 << window_log;
+            // Fast levels have no chain table; the optimal parsers (16+) add their own state
+            let mut tables = 4u64 << hash_log;
+            if level >= 3 {
+                tables += 4 << chain_log;
+            }
+            if level >= 16 {
+                tables += 1 << 20;
+            }
+            if args.long.is_some() {
+                tables += window / 16;
+            }
+            tables += args.dictionary.as_ref().map_or(0, |d| d.len() as u64);
+            if threads > 1 {
+                // Every worker has tables of its own and holds a job of input and output
+                let job = (window * 4).clamp(1 << 20, 512 << 20);
+                window + threads as u64 * (tables + 2 * job)
+            } else {
+                window + tables + (256 << 10)
+            }
+        }
+        Compression::Xz => XZ_PRESET_MEMORY[level.clamp(0, 9) as usize] << 20,
+        Compression::Lz4 => 1 << 20,
+        Compression::None => 0,
+    }
+}
+
+// Memory Limit Fitting (lowers automatic threads, then an automatic level, until the
+// estimate fits; settings given explicitly are kept and reported when they do not)
+fn fit_memory_limit(args: &mut PackArgs, limit: u64) -> Result<()> {
+    let budget = memory_budget(limit)?;
+    let kdf = KdfParams::for_pack(args);
+    check_kdf_memory(&kdf, limit).map_err(|e| match args.low_memory {
+        true => e,
+        false => anyhow!("{} (--low-memory lowers it to 9 MiB)", e),
+    })?;
+
+    let cores = num_cpus::get();
+    // --each runs one single-threaded encoder per archive written at once
+    let mut threads = if args.each {
+        match args.threads {
+            _ if args.low_memory => 1,
+            0 => cores,
+            n => n.min(cores),
+        }
+    } else {
+        compression_threads(args) as usize
+    };
+    let mut level = args.level();
+    let explicit_threads = args.threads != 0;
+    let explicit_level = args.level.is_some() || args.compression == Compression::Lz4;
+    let min_level = match args.compression {
+        Compression::Zstd => 1,
+        _ => 0,
+    };
+    let estimate = |level: i32, threads: usize| {
+        // --adaptive packs text at a higher level than the rest
+        let level = if args.adaptive {
+            max(level, ADAPTIVE_TEXT_LEVEL)
+        } else {
+            level
+        };
+        if args.each {
+            threads as u64 * compression_memory(args, level, 1)
+        } else {
+            compression_memory(args, level, threads)
+        }
+    };
+    while estimate(level, threads) > budget {
+        if threads > 1 && !explicit_threads {
+            threads -= 1;
+        } else if level > min_level && !explicit_level && !args.adaptive {
+            level -= 1;
+        } else {
+            return Err(anyhow!(
+                "{} level {} with {} thread(s){} needs about {}, more than --memory-limit {} allows; lower --level, --threads or --long, or raise the limit",
+                args.compression.name(),
+                level,
+                threads,
+                if args.adaptive { " (level 19 for text with --adaptive)" } else { "" },
+                HumanBytes(BASE_MEMORY + estimate(level, threads)),
+                HumanBytes(limit)
+            ));
+        }
+    }
+    let needed = BASE_MEMORY + estimate(level, threads);
+    if !explicit_threads {
+        args.threads = threads;
+    }
+    if !explicit_level {
+        args.level = Some(level);
+    }
+    if args.verbose > 0 {
+        eprintln!(
+            "Memory limit {}: {} level {} with {} thread(s), about {} while compressing and {} while deriving the key",
+            HumanBytes(limit),
+            args.compression.name(),
+            level,
+            threads,
+            HumanBytes(needed),
+            HumanBytes(BASE_MEMORY + kdf.m_cost as u64 * 1024)
+        );
+    }
+    Ok(())
+}
+
+// Decoder Window Limit (largest zstd window log that fits unpack --memory-limit)
+fn decoder_window_log(limit: u64) -> Result<u32> {
+    let budget = memory_budget(limit)?;
+    (10..=MAX_WINDOW_LOG)
+        .rev()
+        .find(|&window_log| (1u64 << window_log) + ZSTD_DECODER_OVERHEAD <= budget)
+        .ok_or_else(|| {
+            anyhow!(
+                "--memory-limit {} leaves no room for a zstd window (at least {} is needed)",
+                HumanBytes(limit),
+                HumanBytes(BASE_MEMORY + (1 << 10) + ZSTD_DECODER_OVERHEAD)
+            )
+        })
+}
+
+// zstd Encoder Setup (dictionary, --long, --low-memory and --threads at the given level)
+fn zstd_encoder<W: Write>(
+    inner: W,
+    args: &PackArgs,
+    level: i32,
+) -> Result<ZstdEncoder<'static, W>> {
+    let mut encoder = match &args.dictionary {
+        Some(dictionary) => ZstdEncoder::with_dictionary(inner, level, dictionary)?,
+        None => ZstdEncoder::new(inner, level)?,
+    };
+    if let Some(window_log) = args.long {
+        encoder.long_distance_matching(true)?;
+        encoder.window_log(window_log)?;
+    }
+    // Levels above 2 default to windows of 2 MiB and more, which unpacking needs too;
+    // above 9 the match-finder tables alone run from tens to hundreds of MiB
+    if args.low_memory && level > 2 {
+        encoder.window_log(LOW_MEMORY_WINDOW_LOG)?;
+        if level > 9 {
+            encoder.set_parameter(zstd::stream::raw::CParameter::HashLog(
+                LOW_MEMORY_WINDOW_LOG,
+            ))?;
+            encoder.set_parameter(zstd::stream::raw::CParameter::ChainLog(
+                LOW_MEMORY_WINDOW_LOG,
+            ))?;
+        }
+    }
+    let threads = compression_threads(args);
+    if threads > 1 {
+        if let Err(e) = encoder.multithread(threads) {
+            eprintln!(
+                "Warning: zstd multithreading is unavailable ({}), compressing on one thread",
+                e
+            );
+        }
+    }
+    Ok(encoder)
+}
+
+// Segment tags of a --smart-compress payload. Each segment is the tag and a u32 LE
+// length; stored segments add the u64 LE payload offset their bytes start at. The
+// zstd segments concatenate into one zstd frame, so files on both sides of a
+// stored run still share a compression window; --adaptive starts a new frame
+// whenever the level changes
+const SEGMENT_ZSTD: u8 = 0;
+const SEGMENT_STORED: u8 = 1;
+
+// Smart Compression Encoder (zstd stream with stored runs spliced in between)
+struct SmartEncoder<W: Write> {
+    inner: W,
+    /// Compressed bytes collect here until a segment's worth is ready
+    zstd: ZstdEncoder<'static, Vec<u8>>,
+    /// Payload bytes taken so far, compressed or stored
+    position: u64,
+    /// Stored bytes not yet written out, and the payload offset they start at
+    stored: Vec<u8>,
+    stored_at: u64,
+    storing: bool,
+    /// Level of the zstd frame being written
+    level: i32,
+}
+
+impl<W: Write> SmartEncoder<W> {
+    fn set_level(&mut self, level: i32, args: &PackArgs) -> Result<()> {
+        if level == self.level {
+            return Ok(());
+        }
+        let next = zstd_encoder(Vec::new(), args, level)?;
+        let compressed = std::mem::replace(&mut self.zstd, next).finish()?;
+        write_segment(&mut self.inner, SEGMENT_ZSTD, &compressed)?;
+        self.level = level;
+        Ok(())
+    }
+
+    fn set_storing(&mut self, storing: bool) -> std::io::Result<()> {
+        if storing == self.storing {
+            return Ok(());
+        }
+        if storing {
+            // Everything before the stored run has to be decodable on its own
+            self.zstd.flush()?;
+            self.write_zstd_segment()?;
+        } else {
+            self.write_stored_segment()?;
+        }
+        self.storing = storing;
+        Ok(())
+    }
+
+    fn write_zstd_segment(&mut self) -> std::io::Result<()> {
+        let compressed = std::mem::take(self.zstd.get_mut());
+        write_segment(&mut self.inner, SEGMENT_ZSTD, &compressed)
+    }
+
+    fn write_stored_segment(&mut self) -> std::io::Result<()> {
+        if self.stored.is_empty() {
+            return Ok(());
+        }
+        let mut segment = Vec::with_capacity(8 + self.stored.len());
+        segment.extend_from_slice(&self.stored_at.to_le_bytes());
+        segment.append(&mut self.stored);
+        write_segment(&mut self.inner, SEGMENT_STORED, &segment)
+    }
+
+    fn finish(mut self) -> std::io::Result<W> {
+        self.write_stored_segment()?;
+        let SmartEncoder {
+            mut inner, zstd, ..
+        } = self;
+        let compressed = zstd.finish()?;
+        write_segment(&mut inner, SEGMENT_ZSTD, &compressed)?;
+        Ok(inner)
+    }
+}
+
+// Segment Writer (tag, u32 LE length, data; empty segments are left out)
+fn write_segment<W: Write>(inner: &mut W, tag: u8, data: &[u8]) -> std::io::Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    inner.write_all(&[tag])?;
+    inner.write_all(&(data.len() as u32).to_le_bytes())?;
+    inner.write_all(data)
+}
+
+// Write Trait for SmartEncoder
+impl<W: Write> Write for SmartEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = if self.storing {
+            if self.stored.is_empty() {
+                self.stored_at = self.position;
+            }
+            let take = min(buf.len(), CHUNK_SIZE - self.stored.len());
+            self.stored.extend_from_slice(&buf[..take]);
+            if self.stored.len() == CHUNK_SIZE {
+                self.write_stored_segment()?;
+            }
+            take
+        } else {
+            let written = self.zstd.write(buf)?;
+            if self.zstd.get_ref().len() >= CHUNK_SIZE {
+                self.write_zstd_segment()?;
+            }
+            written
+        };
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Indexed zstd Encoder (--index): a new, independent frame every span of payload, each
+// recorded with the compressed offset it starts at
+struct IndexedEncoder<W: Write> {
+    /// Encoder of the current frame (only missing after a failed frame change)
+    zstd: Option<ZstdEncoder<'static, CountingWriter<W>>>,
+    /// Compressed bytes written so far
+    compressed: std::sync::Arc<std::sync::atomic::AtomicU64>,
+    /// Settings every frame's encoder is built with
+    args: PackArgs,
+    level: i32,
+    span: u64,
+    /// Payload bytes in the current frame, and in all of them
+    in_frame: u64,
+    position: u64,
+    frames: Vec<(u64, u64)>,
+}
+
+impl<W: Write> IndexedEncoder<W> {
+    fn zstd(&mut self) -> std::io::Result<&mut ZstdEncoder<'static, CountingWriter<W>>> {
+        self.zstd
+            .as_mut()
+            .ok_or_else(|| std::io::Error::other("zstd frame could not be restarted"))
+    }
+
+    /// Closes the current frame and starts the next one where the payload now is
+    fn next_frame(&mut self) -> std::io::Result<()> {
+        let finished = self
+            .zstd
+            .take()
+            .ok_or_else(|| std::io::Error::other("zstd frame could not be restarted"))?;
+        let counted = finished.finish()?;
+        self.zstd =
+            Some(zstd_encoder(counted, &self.args, self.level).map_err(std::io::Error::other)?);
+        self.frames.push((
+            self.position,
+            self.compressed.load(std::sync::atomic::Ordering::Relaxed),
+        ));
+        self.in_frame = 0;
+        Ok(())
+    }
+
+    fn finish(self) -> std::io::Result<W> {
+        match self.zstd {
+            Some(zstd) => Ok(zstd.finish()?.inner),
+            None => Err(std::io::Error::other("zstd frame could not be restarted")),
+        }
+    }
+}
+
+// Write Trait for IndexedEncoder
+impl<W: Write> Write for IndexedEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Frames start lazily, so the payload never ends with an empty one
+        if self.in_frame >= self.span {
+            self.next_frame()?;
+        }
+        let room = min(self.span - self.in_frame, buf.len() as u64) as usize;
+        let written = self.zstd()?.write(&buf[..room])?;
+        self.in_frame += written as u64;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.zstd()?.flush()
+    }
+}
+
+// Payload Encoder (--compression codec between the payload and the encrypting writer)
+enum PayloadEncoder<W: Write> {
+    Zstd(ZstdEncoder<'static, W>),
+    Smart(SmartEncoder<W>),
+    Indexed(Box<IndexedEncoder<W>>),
+    #[cfg(feature = "lz4")]
+    Lz4(lz4_flex::frame::FrameEncoder<W>),
+    #[cfg(feature = "xz")]
+    Xz(xz2::write::XzEncoder<W>),
+    Stored(W),
+}
+
+impl<W: Write> PayloadEncoder<W> {
+    /// Encoder for the codec the header records, which --smart-compress may
+    /// have switched to none
+    fn new(inner: W, header: &RstfHeader, args: &PackArgs) -> Result<Self> {
+        let level = header.level.unwrap_or_else(|| args.level());
+        Ok(match header.codec()? {
+            Compression::Zstd if header.segmented => PayloadEncoder::Smart(SmartEncoder {
+                inner,
+                zstd: zstd_encoder(Vec::new(), args, level)?,
+                position: 0,
+                stored: Vec::new(),
+                stored_at: 0,
+                storing: false,
+                level,
+            }),
+            Compression::Zstd if header.index_span.is_some() => {
+                let compressed = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+                let counted = CountingWriter {
+                    inner,
+                    count: compressed.clone(),
+                };
+                PayloadEncoder::Indexed(Box::new(IndexedEncoder {
+                    zstd: Some(zstd_encoder(counted, args, level)?),
+                    compressed,
+                    args: args.clone(),
+                    level,
+                    span: header.index_span.unwrap_or(u64::MAX),
+                    in_frame: 0,
+                    position: 0,
+                    frames: vec![(0, 0)],
+                }))
+            }
+            Compression::Zstd => PayloadEncoder::Zstd(zstd_encoder(inner, args, level)?),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => PayloadEncoder::Lz4(lz4_flex::frame::FrameEncoder::new(inner)),
+            #[cfg(feature = "xz")]
+            Compression::Xz => {
+                PayloadEncoder::Xz(xz2::write::XzEncoder::new(inner, args.level() as u32))
+            }
+            Compression::None => PayloadEncoder::Stored(inner),
+            #[cfg(not(all(feature = "lz4", feature = "xz")))]
+            codec => return Err(codec.check_available().unwrap_err()),
+        })
+    }
+
+    /// Routes the following writes around zstd (only segmented payloads can)
+    fn set_storing(&mut self, storing: bool) -> std::io::Result<()> {
+        match self {
+            PayloadEncoder::Smart(encoder) => encoder.set_storing(storing),
+            _ => Ok(()),
+        }
+    }
+
+    /// Continues at another zstd level (only segmented payloads can)
+    fn set_level(&mut self, level: i32, args: &PackArgs) -> Result<()> {
+        match self {
+            PayloadEncoder::Smart(encoder) => encoder.set_level(level, args),
+            _ => Ok(()),
+        }
+    }
+
+    /// Frames an indexed encoder started so far (None for the other encoders)
+    fn index_frames(&self) -> Option<Vec<(u64, u64)>> {
+        match self {
+            PayloadEncoder::Indexed(encoder) => Some(encoder.frames.clone()),
+            _ => None,
+        }
+    }
+
+    fn finish(self) -> std::io::Result<W> {
+        match self {
+            PayloadEncoder::Zstd(encoder) => encoder.finish(),
+            PayloadEncoder::Smart(encoder) => encoder.finish(),
+            PayloadEncoder::Indexed(encoder) => encoder.finish(),
+            #[cfg(feature = "lz4")]
+            PayloadEncoder::Lz4(encoder) => encoder.finish().map_err(std::io::Error::other),
+            #[cfg(feature = "xz")]
+            PayloadEncoder::Xz(encoder) => encoder.finish(),
+            PayloadEncoder::Stored(inner) => Ok(inner),
+        }
+    }
+}
+
+// Write Trait for PayloadEncoder
+impl<W: Write> Write for PayloadEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            PayloadEncoder::Zstd(encoder) => encoder.write(buf),
+            PayloadEncoder::Smart(encoder) => encoder.write(buf),
+            PayloadEncoder::Indexed(encoder) => encoder.write(buf),
+            #[cfg(feature = "lz4")]
+            PayloadEncoder::Lz4(encoder) => encoder.write(buf),
+            #[cfg(feature = "xz")]
+            PayloadEncoder::Xz(encoder) => encoder.write(buf),
+            PayloadEncoder::Stored(inner) => inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            PayloadEncoder::Zstd(encoder) => encoder.flush(),
+            PayloadEncoder::Smart(encoder) => encoder.flush(),
+            PayloadEncoder::Indexed(encoder) => encoder.flush(),
+            #[cfg(feature = "lz4")]
+            PayloadEncoder::Lz4(encoder) => encoder.flush(),
+            #[cfg(feature = "xz")]
+            PayloadEncoder::Xz(encoder) => encoder.flush(),
+            PayloadEncoder::Stored(inner) => inner.flush(),
+        }
+    }
+}
+
+// Payload Decoder (codec named in the header; missing features fail instead of misreading)
+enum PayloadDecoder<R: Read> {
+    Zstd(ZstdDecoder<'static, std::io::BufReader<R>>),
+    Smart(SmartDecoder<R>),
+    #[cfg(feature = "lz4")]
+    Lz4(lz4_flex::frame::FrameDecoder<R>),
+    #[cfg(feature = "xz")]
+    Xz(xz2::read::XzDecoder<R>),
+    Stored(R),
+}
+
+impl<R: Read> PayloadDecoder<R> {
+    fn new(inner: R, header: &RstfHeader) -> Result<Self> {
+        let codec = header.codec()?;
+        codec
+            .check_available()
+            .context("Cannot decompress this archive")?;
+        Ok(match codec {
+            Compression::Zstd if header.segmented => {
+                let mut decoder = match zstd_dictionary(header)? {
+                    Some(dictionary) => zstd::stream::raw::Decoder::with_dictionary(dictionary)?,
+                    None => zstd::stream::raw::Decoder::new()?,
+                };
+                if let Some(window_log) = zstd_window_log(header)? {
+                    decoder
+                        .set_parameter(zstd::stream::raw::DParameter::WindowLogMax(window_log))?;
+                }
+                PayloadDecoder::Smart(SmartDecoder {
+                    inner,
+                    zstd: decoder,
+                    input: Vec::new(),
+                    consumed: 0,
+                    draining: false,
+                    position: 0,
+                    stored: None,
+                })
+            }
+            Compression::Zstd => {
+                let mut decoder = match zstd_dictionary(header)? {
+                    Some(dictionary) => {
+                        ZstdDecoder::with_dictionary(std::io::BufReader::new(inner), dictionary)?
+                    }
+                    None => ZstdDecoder::new(inner)?,
+                };
+                if let Some(window_log) = zstd_window_log(header)? {
+                    decoder.window_log_max(window_log)?;
+                }
+                PayloadDecoder::Zstd(decoder)
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => PayloadDecoder::Lz4(lz4_flex::frame::FrameDecoder::new(inner)),
+            #[cfg(feature = "xz")]
+            Compression::Xz => PayloadDecoder::Xz(xz2::read::XzDecoder::new(inner)),
+            Compression::None => PayloadDecoder::Stored(inner),
+            #[cfg(not(all(feature = "lz4", feature = "xz")))]
+            _ => unreachable!("check_available rejects codecs missing from this build"),
+        })
+    }
+}
+
+// Read Trait for PayloadDecoder
+impl<R: Read> Read for PayloadDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let decoded = match self {
+            PayloadDecoder::Zstd(decoder) => decoder.read(buf),
+            PayloadDecoder::Smart(decoder) => decoder.read(buf),
+            #[cfg(feature = "lz4")]
+            PayloadDecoder::Lz4(decoder) => decoder.read(buf),
+            #[cfg(feature = "xz")]
+            PayloadDecoder::Xz(decoder) => decoder.read(buf),
+            PayloadDecoder::Stored(inner) => return inner.read(buf),
+        };
+        // Chunks that authenticated but do not decompress are damage as well
+        decoded.map_err(|e| match ArchiveError::of_io(&e) {
+            Some(_) => e,
+            None => ArchiveError::Corrupted(format!("payload does not decompress: {}", e))
+                .into_io(std::io::ErrorKind::InvalidData),
+        })
+    }
+}
+
+impl<R: Read> PayloadDecoder<R> {
+    /// The stream beneath, with whatever the decoder had read ahead dropped
+    fn into_inner(self) -> R {
+        match self {
+            PayloadDecoder::Zstd(decoder) => decoder.finish().into_inner(),
+            PayloadDecoder::Smart(decoder) => decoder.inner,
+            #[cfg(feature = "lz4")]
+            PayloadDecoder::Lz4(decoder) => decoder.into_inner(),
+            #[cfg(feature = "xz")]
+            PayloadDecoder::Xz(decoder) => decoder.into_inner(),
+            PayloadDecoder::Stored(inner) => inner,
+        }
+    }
+}
+
+// Salvage Decoder (unpack --salvage of a compressed payload): what decodes after a damaged
+// chunk is read is replaced by zeros up to the first indexed frame past the damage, where
+// a fresh decoder takes over, so the payload keeps its length; without a chunk index
+// there is no frame to resume at and decoding ends at the damage
+struct SalvageDecoder {
+    decoder: Option<PayloadDecoder<ArchiveReader>>,
+    header: RstfHeader,
+    damage: DamageLog,
+    /// Damage entries already dealt with
+    seen: usize,
+    /// Payload offset of the next byte handed out
+    position: u64,
+    /// Zeros are handed out up to this offset, where the resumed frame starts
+    resume_at: u64,
+}
+
+impl SalvageDecoder {
+    fn new(
+        decoder: PayloadDecoder<ArchiveReader>,
+        header: &RstfHeader,
+        damage: &DamageLog,
+    ) -> Self {
+        SalvageDecoder {
+            decoder: Some(decoder),
+            header: header.clone(),
+            damage: damage.clone(),
+            // A damaged trailer is logged before any payload is read
+            seen: damage_count(damage),
+            position: 0,
+            resume_at: 0,
+        }
+    }
+
+    /// Restarts decoding at the first frame that begins after every chunk logged so far
+    fn resume(&mut self) -> std::io::Result<()> {
+        let mut reader = match self.decoder.take() {
+            Some(decoder) => decoder.into_inner(),
+            None => return Err(std::io::Error::other("payload decoder is gone")),
+        };
+        let chunk_size = self.header.chunk_size as u64;
+        loop {
+            let damaged = self
+                .damage
+                .lock()
+                .map_err(|_| std::io::Error::other("damage log is poisoned"))?
+                .clone();
+            let Some(last) = damaged[self.seen..].iter().map(|d| d.chunk).max() else {
+                break;
+            };
+            self.seen = damaged.len();
+            // Stream chunks 0 and 1 hold the header, so payload chunk n is stream chunk n + 2
+            let damage_end = (last as u64).saturating_sub(1) * chunk_size;
+            let Some(index) = self.header.index.as_ref() else {
+                return Err(ArchiveError::Corrupted(format!(
+                    "the compressed payload cannot be decoded past damaged chunk {}; only archives packed with --index or --compression none are salvaged beyond their first damaged chunk",
+                    last
+                ))
+                .into_io(std::io::ErrorKind::InvalidData));
+            };
+            let Some(&(offset, packed)) = index
+                .frames
+                .iter()
+                .find(|&&(_, packed)| packed >= damage_end)
+            else {
+                return Err(ArchiveError::Corrupted(format!(
+                    "no compressed frame starts after damaged chunk {}, so the rest of the payload is lost",
+                    last
+                ))
+                .into_io(std::io::ErrorKind::InvalidData));
+            };
+            if offset < self.position {
+                return Err(std::io::Error::other("chunk index frames are out of order"));
+            }
+            // Opening the chunk the frame starts in can log more damage, hence the loop
+            reader.seek_payload(index, packed, index.packed_len)?;
+            self.resume_at = offset;
+        }
+        self.decoder =
+            Some(PayloadDecoder::new(reader, &self.header).map_err(std::io::Error::other)?);
+        Ok(())
+    }
+}
+
+impl Read for SalvageDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.position < self.resume_at {
+                let n = min(buf.len() as u64, self.resume_at - self.position) as usize;
+                buf[..n].fill(0);
+                self.position += n as u64;
+                return Ok(n);
+            }
+            let Some(decoder) = self.decoder.as_mut() else {
+                return Ok(0);
+            };
+            let result = decoder.read(buf);
+            if damage_count(&self.damage) == self.seen {
+                let n = result?;
+                self.position += n as u64;
+                return Ok(n);
+            }
+            // This read went through a damaged chunk, so none of it is trusted
+            let log_position = self.position;
+            self.resume()?;
+            eprintln!(
+                "Warning: payload bytes {} to {} decode from damaged chunks and read as zeros",
+                log_position, self.resume_at
+            );
+        }
+    }
+}
+
+fn damage_count(damage: &DamageLog) -> usize {
+    damage.lock().map(|d| d.len()).unwrap_or_default()
+}
+
+// zstd Dictionary Lookup (embedded or --dict; an archive needing one fails without it)
+fn zstd_dictionary(header: &RstfHeader) -> Result<Option<&[u8]>> {
+    match (&header.dictionary, header.dict_id) {
+        (Some(dictionary), _) => Ok(Some(dictionary)),
+        (None, Some(id)) => Err(anyhow!(
+            "Archive was compressed with zstd dictionary {}; pass it with --dict <FILE>",
+            id
+        )),
+        (None, None) => Ok(None),
+    }
+}
+
+// zstd Window Check (windows past the default 2^27 limit are refused unless allowed)
+fn zstd_window_log(header: &RstfHeader) -> Result<Option<u32>> {
+    match (header.window_log, header.window_log_limit) {
+        (Some(window_log), _) if window_log > MAX_WINDOW_LOG => Err(anyhow!(
+            "Archive needs a 2^{} byte zstd window (limit is 2^{})",
+            window_log,
+            MAX_WINDOW_LOG
+        )),
+        (Some(window_log), Some(limit)) if window_log > limit => Err(anyhow!(
+            "Archive needs a {} zstd window, but --memory-limit allows at most {}",
+            HumanBytes(1 << window_log),
+            HumanBytes(1 << limit)
+        )),
+        (window_log, limit) => Ok(window_log.or(limit)),
+    }
+}
+
+// Smart Compression Decoder (SmartEncoder segments back into one payload stream)
+struct SmartDecoder<R: Read> {
+    inner: R,
+    zstd: zstd::stream::raw::Decoder<'static>,
+    /// Compressed bytes from zstd segments, decoded up to `consumed`
+    input: Vec<u8>,
+    consumed: usize,
+    /// The last run filled its output, so zstd may still hold decoded bytes
+    draining: bool,
+    /// Payload bytes produced so far
+    position: u64,
+    /// Stored segment read ahead: the payload offset it starts at and the bytes left
+    stored: Option<(u64, u64)>,
+}
+
+impl<R: Read> SmartDecoder<R> {
+    /// Reads the next segment header; None at the end of the payload
+    fn next_segment(&mut self) -> std::io::Result<Option<(u8, u32)>> {
+        let mut tag = [0u8; 1];
+        loop {
+            match self.inner.read(&mut tag) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let mut len = [0u8; 4];
+        self.inner.read_exact(&mut len)?;
+        Ok(Some((tag[0], u32::from_le_bytes(len))))
+    }
+}
+
+fn corrupt_segments(reason: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Smart-compressed payload is corrupt: {}", reason),
+    )
+}
+
+// Read Trait for SmartDecoder
+impl<R: Read> Read for SmartDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use zstd::stream::raw::{InBuffer, Operation, OutBuffer};
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // zstd output runs up to where the next stored segment starts
+            let wanted = match self.stored {
+                Some((offset, left)) if self.position >= offset => {
+                    let take = min(buf.len() as u64, left) as usize;
+                    self.inner.read_exact(&mut buf[..take])?;
+                    self.position += take as u64;
+                    self.stored = (left > take as u64).then_some((offset, left - take as u64));
+                    return Ok(take);
+                }
+                Some((offset, _)) => min(buf.len() as u64, offset - self.position) as usize,
+                None => buf.len(),
+            };
+            // zstd gives up after repeated runs that make no progress, so it is
+            // only run with input left or output it may still be holding
+            if self.consumed < self.input.len() || self.draining {
+                let mut input = InBuffer::around(&self.input[self.consumed..]);
+                let mut output = OutBuffer::around(&mut buf[..wanted]);
+                self.zstd.run(&mut input, &mut output)?;
+                let (consumed, produced) = (input.pos(), output.pos());
+                self.consumed += consumed;
+                self.draining = produced == wanted;
+                if produced > 0 {
+                    self.position += produced as u64;
+                    return Ok(produced);
+                }
+                if consumed > 0 {
+                    continue;
+                }
+            }
+            if self.stored.is_some() {
+                return Err(corrupt_segments("zstd data ends before a stored segment"));
+            }
+            match self.next_segment()? {
+                None => return Ok(0),
+                Some((SEGMENT_ZSTD, len)) => {
+                    self.input.drain(..self.consumed);
+                    self.consumed = 0;
+                    let start = self.input.len();
+                    self.input.resize(start + len as usize, 0);
+                    self.inner.read_exact(&mut self.input[start..])?;
+                }
+                Some((SEGMENT_STORED, len)) => {
+                    let mut offset = [0u8; 8];
+                    self.inner.read_exact(&mut offset)?;
+                    let offset = u64::from_le_bytes(offset);
+                    if offset < self.position || len <= 8 {
+                        return Err(corrupt_segments("stored segment out of place"));
+                    }
+                    self.stored = Some((offset, len as u64 - 8));
+                }
+                Some((tag, _)) => {
+                    return Err(corrupt_segments(&format!("unknown segment tag {}", tag)))
+                }
+            }
+        }
+    }
+}
+
+// Hashing Writer (BLAKE3 of everything passed through, plus a byte count)
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: blake3::Hasher,
+    count: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: blake3::Hasher::new(),
+            count: 0,
+        }
+    }
+
+    fn finish(self) -> (W, Trailer) {
+        let trailer = Trailer {
+            digest: *self.hasher.finalize().as_bytes(),
+            payload_size: self.count,
+        };
+        (self.inner, trailer)
+    }
+}
+
+// Write Trait for HashingWriter
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Hashing Reader (the read side of HashingWriter, checked against the stored trailer)
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: blake3::Hasher,
+    count: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: blake3::Hasher::new(),
+            count: 0,
+        }
+    }
+
+    /// Hash what the extractor left unread (tar padding, skipped entries), then
+    /// compare with the trailer; archives from before format 2 have none to compare
+    fn verify(&mut self, stored: Option<&Trailer>) -> Result<()> {
+        let Some(stored) = stored else {
+            return Ok(());
+        };
+        std::io::copy(self, &mut std::io::sink()).context("Failed to decrypt payload")?;
+        let computed = Trailer {
+            digest: *self.hasher.finalize().as_bytes(),
+            payload_size: self.count,
+        };
+        if computed != *stored {
+            return Err(ArchiveError::Corrupted(format!(
+                "Digest mismatch: stored {} ({} bytes), extracted {} ({} bytes)",
+                stored.digest_string(),
+                stored.payload_size,
+                computed.digest_string(),
+                computed.payload_size
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// Read Trait for HashingReader
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+// Counting Writer (bytes passed through, readable from another thread)
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: std::sync::Arc<std::sync::atomic::AtomicU64>,
+}
+
+// Write Trait for CountingWriter
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count
+            .fetch_add(written as u64, std::sync::atomic::Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Seconds between progress lines when stderr cannot show a bar
+const PROGRESS_LINE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Line Progress (periodic stderr summaries until the bar finishes)
+fn report_progress_lines(pb: &ProgressBar, describe: fn(&ProgressBar) -> String) {
+    let pb = pb.clone();
+    std::thread::spawn(move || {
+        let mut last = std::time::Instant::now();
+        while !pb.is_finished() {
+            std::thread::sleep(std::time::Duration::from_millis(250));
+            if last.elapsed() >= PROGRESS_LINE_INTERVAL && !pb.is_finished() {
+                eprintln!("{}", describe(&pb));
+                last = std::time::Instant::now();
+            }
+        }
+    });
+}
+
+// Byte Progress Line (`Progress: 1.2 GiB of 4.0 GiB (30%)`)
+fn describe_bytes(pb: &ProgressBar) -> String {
+    let position = pb.position();
+    match pb.length() {
+        Some(len) if len > 0 => format!(
+            "Progress: {} of {} ({}%)",
+            HumanBytes(position),
+            HumanBytes(len),
+            position * 100 / len
+        ),
+        _ => format!("Progress: {}", HumanBytes(position)),
+    }
+}
+
+// Batch Pack (--each): one archive per input, one password prompt
+fn pack_each(args: &PackArgs, name_suffix: Option<&str>, comment: &str) -> Result<()> {
+    if args.files_from.is_some() {
+        return Err(anyhow!("--each cannot be combined with --files-from"));
+    }
+    let output_dir = match args.output.as_deref() {
+        Some(dir) if !dir.is_dir() => {
+            return Err(anyhow!(
+                "--output must be an existing directory in --each mode"
+            ))
+        }
+        other => other,
+    };
+
+    let walk = WalkOptions::new(args)?;
+    let mut paths = expand_input_globs(&args.inputs)?;
+    let mut skips = WalkSkips::default();
+    // A lone directory means "each item inside it"
+    if let [dir] = paths.as_slice() {
+        if dir.is_dir() {
+            let mut children = if walk.gitignore {
+                gitignore_walker(dir)
+                    .max_depth(Some(1))
+                    .build()
+                    .skip(1)
+                    .map(|entry| entry.map(ignore::DirEntry::into_path))
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .with_context(|| format!("Failed to read directory '{}'", dir.display()))?
+            } else {
+                fs::read_dir(dir)
+                    .with_context(|| format!("Failed to read directory '{}'", dir.display()))?
+                    .map(|entry| entry.map(|e| e.path()))
+                    .collect::<std::io::Result<Vec<_>>>()?
+            };
+            children.sort();
+            let before = children.len();
+            children
+                .retain(|child| !walk.excludes(Path::new(child.file_name().unwrap_or_default())));
+            skips.excluded = (before - children.len()) as u64;
+            paths = children;
+        }
+    }
+
+    let inputs = collect_pack_inputs(&paths, &walk)?;
+    if inputs.is_empty() {
+        return Err(anyhow!("Nothing to pack"));
+    }
+
+    let mut jobs = Vec::with_capacity(inputs.len());
+    let mut seen_outputs = std::collections::HashSet::new();
+    for input in inputs {
+        let output_path = derive_output_path(&input.path, output_dir, name_suffix);
+        check_pack_input(&input, &output_path, args.force)?;
+        if output_path.exists() && !args.overwrite {
+            return Err(anyhow!(
+                "'{}' already exists (use --overwrite to replace it)",
+                output_path.display()
+            ));
+        }
+        if !seen_outputs.insert(output_path.clone()) {
+            return Err(anyhow!(
+                "Several inputs would be written to '{}'",
+                output_path.display()
+            ));
+        }
+
+        let mut header = RstfHeader {
+            is_dir: input.is_dir,
+            original_name: input.name.clone(),
+            original_size: input.size,
+            comment: comment.to_string(),
+            ..RstfHeader::stamped()
+        };
+        let inputs = vec![input];
+        if header.is_dir {
+            (header.file_count, header.dir_count) = count_entries(&inputs, false);
+        } else {
+            record_file_metadata(&mut header, &inputs[0].path);
+            if args.sparse {
+                header.extents = sparse_extents(&inputs[0].path);
+            }
+        }
+        let single_file = (!header.is_dir).then(|| inputs[0].path.as_path());
+        record_codec(&mut header, args, single_file);
+        if args.reproducible {
+            make_reproducible(&mut header, args);
+        } else if args.numeric_owner {
+            strip_owner_names(&mut header);
+        }
+
+        jobs.push(PackJob {
+            header,
+            inputs,
+            output_path,
+            from_stdin: false,
+            written: Default::default(),
+            progress: None,
+        });
+    }
+
+    let credentials = pack_credentials(args)?;
+    if !args.quiet {
+        println!("Packing {} inputs into individual archives...", jobs.len());
+    }
+
+    let multi = MultiProgress::new();
+    if args.quiet {
+        multi.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+    }
+    let overall = multi.add(ProgressBar::new(jobs.len() as u64));
+    overall.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] {pos}/{len} archive(s) packed")?,
+    );
+    if !args.quiet && !std::io::stderr().is_terminal() {
+        report_progress_lines(&overall, |bar| {
+            format!(
+                "Progress: {} of {} archive(s) packed",
+                bar.position(),
+                bar.length().unwrap_or_default()
+            )
+        });
+    }
+    let style = ProgressStyle::default_bar()
+        .template(
+            "{spinner:.green} [{bar:30.cyan/blue}] {bytes}/{total_bytes}, {written} ({bytes_per_sec}) {msg}",
+        )?
+        .progress_chars("#>-");
+
+    // --threads caps how many archives are written at once
+    let workers = if args.low_memory {
+        1
+    } else {
+        args.threads.min(num_cpus::get())
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .build()
+        .context("Failed to start the worker threads")?;
+    if args.verbose > 0 {
+        eprintln!(
+            "Writing up to {} archive(s) at once",
+            pool.current_num_threads()
+        );
+    }
+    let results: Vec<Result<PackOutcome>> = pool.install(|| {
+        jobs.par_iter()
+            .map(|job| {
+                let pb = multi.insert_before(&overall, ProgressBar::new(job.header.original_size));
+                pb.set_style(
+                    style
+                        .clone()
+                        .with_key("written", written_key(job.written.clone())),
+                );
+                pb.set_message(job.header.original_name.clone());
+                let result = write_archive(job, &credentials, args, &pb);
+                pb.finish_and_clear();
+                overall.inc(1);
+                result
+            })
+            .collect()
+    });
+    overall.finish();
+
+    let mut failures = 0;
+    if !args.quiet {
+        println!();
+    }
+    for (job, result) in jobs.iter().zip(&results) {
+        match result {
+            Ok(_) if args.quiet => {}
+            Ok(outcome) => {
+                println!(
+                    "  ok      {}  {}{}",
+                    job.output_path.display(),
+                    format_pack_summary(outcome),
+                    if args.verify { "  verified" } else { "" }
+                );
+                if let Some(classes) = format_class_sizes(outcome, args) {
+                    println!("          by type: {}", classes);
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                println!("  FAILED  {}: {:#}", job.inputs[0].path.display(), e);
+            }
+        }
+    }
+    if !args.quiet {
+        println!("Packed {} of {} inputs", jobs.len() - failures, jobs.len());
+    }
+    for (job, result) in jobs.iter().zip(&results) {
+        skips.absorb(&job.inputs[0].skips);
+        if let Ok(outcome) = result {
+            skips.unreadable.extend(outcome.unreadable.iter().cloned());
+        }
+    }
+    print_walk_skips(&skips, args);
+
+    if args.wipe {
+        for (job, _) in jobs.iter().zip(&results).filter(|(_, r)| r.is_ok()) {
+            wipe_input(&job.inputs[0])?;
+        }
+    }
+
+    if failures > 0 {
+        return Err(anyhow!("{} input(s) failed to pack", failures));
+    }
+    exit_if_skipped(&skips);
+    Ok(())
+}
+
+// Walk Skips Report (pack summary lines, silent when nothing was left out)
+fn print_walk_skips(skips: &WalkSkips, args: &PackArgs) {
+    // Counts are status lines; only the warnings below survive --quiet and --json
+    let status = !args.quiet && !args.json;
+    if skips.excluded > 0 && status {
+        println!("Excluded {} path(s)", skips.excluded);
+    }
+    if !skips.by_size.is_empty() && status {
+        println!("Excluded {} file(s) by size", skips.by_size.len());
+        for path in skips.by_size.iter().filter(|_| args.verbose > 0) {
+            println!("  {}", path.display());
+        }
+    }
+    if skips.by_time + skips.in_time > 0 && status {
+        println!(
+            "Time filter: {} file(s) matched, {} skipped",
+            skips.in_time, skips.by_time
+        );
+    }
+    let lists = [
+        ("skipped symlink(s)", &skips.symlinks),
+        (
+            "broken or looping symlink(s) not followed",
+            &skips.unfollowed,
+        ),
+        ("socket(s) skipped (tar cannot store them)", &skips.sockets),
+        (
+            "FIFO(s) and device node(s) skipped (store them with --special-files)",
+            &skips.special,
+        ),
+    ];
+    for (what, paths) in lists.into_iter().filter(|(_, paths)| !paths.is_empty()) {
+        eprintln!("Warning: {} {}:", paths.len(), what);
+        for line in capped_path_list(paths) {
+            eprintln!("{}", line);
+        }
+    }
+    if !skips.mounts.is_empty() && status {
+        println!("Did not cross into {} mount point(s):", skips.mounts.len());
+        for line in capped_path_list(&skips.mounts) {
+            println!("{}", line);
+        }
+    }
+    if !skips.unreadable.is_empty() {
+        eprintln!(
+            "Warning: skipped {} unreadable path(s):",
+            skips.unreadable.len()
+        );
+        let width = skips
+            .unreadable
+            .iter()
+            .map(|(path, _)| path.display().to_string().chars().count())
+            .max()
+            .unwrap_or_default()
+            .min(60);
+        eprintln!("  {:<width$}  ERROR", "PATH");
+        for (path, error) in &skips.unreadable {
+            eprintln!("  {:<width$}  {}", path.display().to_string(), error);
+        }
+    }
+}
+
+// Exit status 9 tells scripts the archive is complete except for skipped paths
+// (2 is taken by clap for usage errors, 3 to 8 by salvage and the archive failures)
+const EXIT_SKIPPED: i32 = 9;
+
+fn exit_if_skipped(skips: &WalkSkips) {
+    if !skips.unreadable.is_empty() {
+        exit_with(EXIT_SKIPPED);
+    }
+}
+
+#[cfg(not(test))]
+fn exit_with(code: i32) -> ! {
+    std::process::exit(code)
+}
+
+// Test builds unwind with the exit status instead, so the test can read it back
+#[cfg(test)]
+fn exit_with(code: i32) -> ! {
+    std::panic::panic_any(tests::Exit(code))
+}
+
+// Verbose Entry Line (-v names the entry, -vv adds size and time; stderr, above the bar)
+fn print_verbose_entry(
+    pb: &ProgressBar,
+    verbosity: u8,
+    name: &Path,
+    size: u64,
+    started: std::time::Instant,
+) {
+    if verbosity == 0 {
+        return;
+    }
+    let line = if verbosity > 1 {
+        format!(
+            "{}  {}  {:.1?}",
+            name.display(),
+            HumanBytes(size),
+            started.elapsed()
+        )
+    } else {
+        name.display().to_string()
+    };
+    // A hidden bar drops println, so non-terminal runs print directly
+    if pb.is_hidden() {
+        eprintln!("{}", line);
+    } else {
+        pb.println(line);
+    }
+}
+
+// Path List Helper (indented lines, at most 20 paths and a count of the rest)
+fn capped_path_list(paths: &[PathBuf]) -> Vec<String> {
+    let mut lines: Vec<String> = paths
+        .iter()
+        .take(20)
+        .map(|p| format!("  {}", p.display()))
+        .collect();
+    if paths.len() > 20 {
+        lines.push(format!("  ... and {} more", paths.len() - 20));
+    }
+    lines
+}
+
+// Failure List Helper (a warning to stderr, capped at 20 lines; silent when empty)
+fn print_failures(heading: &str, failures: &[String]) {
+    if failures.is_empty() {
+        return;
+    }
+    eprintln!("Warning: {} {}:", failures.len(), heading);
+    for failure in failures.iter().take(20) {
+        eprintln!("  {}", failure);
+    }
+    if failures.len() > 20 {
+        eprintln!("  ... and {} more", failures.len() - 20);
+    }
+}
+
+// Wipe Prompt Helper
+fn wipe_input(input: &PackInput) -> Result<()> {
+    print!(
+        "\nDelete original file/folder '{}'? (y/N): ",
+        input.path.display()
+    );
+    std::io::stdout().flush()?;
+
+    let mut input_string = String::new();
+    std::io::stdin()
+        .read_line(&mut input_string)
+        .context("Failed to read input")?;
+
+    if input_string.trim().to_lowercase() == "y" {
+        if input.is_dir {
+            fs::remove_dir_all(&input.path).context("Failed to wipe directory")?;
+        } else {
+            fs::remove_file(&input.path).context("Failed to wipe file")?;
+        }
+        println!("Original data wiped.");
+    } else {
+        println!("Wipe cancelled. Original data preserved.");
+    }
+    Ok(())
+}
+
+// Output File Helper
+fn create_output_file(path: &Path, force: bool, force_flag: &str) -> Result<File> {
+    if force {
+        return File::create(path).context("Failed to create output file");
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::AlreadyExists {
+                anyhow!(
+                    "'{}' already exists (use {} to overwrite)",
+                    path.display(),
+                    force_flag
+                )
+            } else {
+                anyhow!(e).context("Failed to create output file")
+            }
+        })
+}
+
+// Output Name Validation Helper
+fn validate_output_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(std::path::Component::Normal(_)), None) if !name.contains(['/', '\\']) => Ok(()),
+        _ => Err(anyhow!(
+            "Invalid name '{}': must be a plain file name without path separators",
+            name
+        )),
+    }
+}
+
+// Stored Name Sanitizer (the header's name must not steer output outside the destination)
+fn sanitize_stored_name(name: &str) -> Result<String> {
+    if name.contains('\0') {
+        return Err(anyhow!(
+            "Archive's stored name contains a NUL byte (use --as <NAME> to choose one)"
+        ));
+    }
+    let base = name
+        .rsplit(['/', '\\'])
+        .find(|part| !part.is_empty())
+        .unwrap_or_default();
+    if base.is_empty() || base == "." || base == ".." {
+        return Err(anyhow!(
+            "Archive's stored name '{}' is not a usable file name (use --as <NAME> to choose one)",
+            name
+        ));
+    }
+
+    let mut sanitized = base.to_string();
+    if cfg!(windows) && is_reserved_device_name(base) {
+        sanitized.insert(0, '_');
+    }
+    if sanitized != name {
+        eprintln!(
+            "Warning: archive's stored name '{}' was reduced to '{}'",
+            name, sanitized
+        );
+    }
+    Ok(sanitized)
+}
+
+// Windows Device Name Check (`CON`, `nul.txt`, `COM1`, ...)
+fn is_reserved_device_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    let upper = stem.to_ascii_uppercase();
+    matches!(upper.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || (upper.len() == 4
+            && (upper.starts_with("COM") || upper.starts_with("LPT"))
+            && matches!(upper.as_bytes()[3], b'1'..=b'9'))
+}
+
+// Re-rooted Target Helper (swaps the first path component for `root_name`)
+fn rerooted_target(dest: &Path, root_name: &str, path: &Path) -> std::io::Result<PathBuf> {
+    let root = dest.join(root_name);
+    Ok(stripped_target(&root, path, 1)?.unwrap_or(root))
+}
+
+// Stripped Target Helper (None when nothing is left after dropping `strip` elements)
+fn stripped_target(dest: &Path, path: &Path, strip: usize) -> std::io::Result<Option<PathBuf>> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(part) => parts.push(part),
+            std::path::Component::CurDir => {}
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "unsafe path in archive: {} (absolute or containing '..', use --allow-unsafe-paths to extract it anyway)",
+                        path.display()
+                    ),
+                ))
+            }
+        }
+    }
+    if parts.len() <= strip {
+        return Ok(None);
+    }
+    Ok(Some(
+        parts[strip..]
+            .iter()
+            .fold(dest.to_path_buf(), |target, part| target.join(part)),
+    ))
+}
+
+// Include/Exclude Path Filter
+struct PathFilter {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl PathFilter {
+    fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let compile = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|g| glob::Pattern::new(g).with_context(|| format!("Invalid glob '{}'", g)))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    fn is_active(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty()
+    }
+
+    // A pattern matching a directory also covers everything beneath it
+    fn matches(&self, path: &str) -> bool {
+        let path = Path::new(path.trim_end_matches('/'));
+        let hits = |patterns: &[glob::Pattern]| {
+            path.ancestors()
+                .filter(|p| !p.as_os_str().is_empty())
+                .any(|p| patterns.iter().any(|g| g.matches_path(p)))
+        };
+        if hits(&self.exclude) {
+            return false;
+        }
+        self.include.is_empty() || hits(&self.include)
+    }
+}
+
+// Where and what a selective or dry-run extraction writes
+struct Extraction<'a> {
+    dest: &'a Path,
+    root_name: Option<&'a str>,
+    strip: usize,
+    wanted: &'a [String],
+    filter: &'a PathFilter,
+    dry_run: bool,
+    policy: ConflictPolicy,
+    suffix: &'a str,
+    allow_unsafe_paths: bool,
+    symlinks: SymlinkPolicy,
+    max_size: Option<u64>,
+    max_entries: Option<u64>,
+    keep_partial: bool,
+    preserve_mtimes: bool,
+    preserve_permissions: bool,
+    /// Zero blocks are stepped over, as damaged chunks read as zeros (unpack --salvage)
+    salvage: bool,
+    /// Stored mode bits that survive (see UnpackArgs::mode_mask)
+    mode_mask: u32,
+    numeric_owner: bool,
+    xattrs: bool,
+    acls: bool,
+    /// Bar that -v/--verbose entry lines are printed above
+    progress: &'a ProgressBar,
+    verbosity: u8,
+}
+
+impl Extraction<'_> {
+    /// Tar reader over `reader` set up for this extraction
+    fn tar<R: Read>(&self, reader: R) -> tar::Archive<R> {
+        let mut archive = tar::Archive::new(reader);
+        archive.set_overwrite(self.policy == ConflictPolicy::Overwrite);
+        archive.set_preserve_mtime(self.preserve_mtimes);
+        archive.set_preserve_permissions(self.preserve_permissions);
+        archive.set_ignore_zeros(self.salvage);
+        archive
+    }
+}
+
+// Tar Resync (unpack --salvage): when extraction fails after new damage was read, the tar
+// stream is picked up again at the next indexed entry, or else at the next block that
+// checks out as a ustar header
+struct TarResync<'a, R: Read> {
+    inner: R,
+    /// Payload offset of the next byte handed out
+    position: u64,
+    /// A header block found by scanning, handed out before `inner` continues
+    pending: Vec<u8>,
+    damage: Option<&'a DamageLog>,
+    /// Damage entries the last pass started with
+    seen: usize,
+    /// Payload offsets of the indexed entries, in stream order
+    entries: Vec<u64>,
+}
+
+impl<'a, R: Read> TarResync<'a, R> {
+    fn new(inner: R, damage: Option<&'a DamageLog>, index: Option<&ChunkIndex>) -> Self {
+        let mut entries: Vec<u64> = index
+            .map(|index| index.entries.iter().map(|&(_, offset)| offset).collect())
+            .unwrap_or_default();
+        entries.sort_unstable();
+        TarResync {
+            inner,
+            position: 0,
+            pending: Vec::new(),
+            damage,
+            seen: damage.map(damage_count).unwrap_or_default(),
+            entries,
+        }
+    }
+
+    /// Moves on to the next entry after an error ended a pass; false when the error did
+    /// not follow new damage or no entry is left
+    fn resume(&mut self) -> std::io::Result<bool> {
+        let Some(logged) = self.damage.map(damage_count) else {
+            return Ok(false);
+        };
+        if logged == self.seen {
+            return Ok(false);
+        }
+        self.seen = logged;
+        let failed_at = self.position;
+        let found = match self.entries.iter().find(|&&offset| offset >= failed_at) {
+            Some(&next) => {
+                self.skip(next - failed_at)?;
+                true
+            }
+            None if self.entries.is_empty() => self.scan()?,
+            None => false,
+        };
+        if found {
+            eprintln!(
+                "Warning: the tar stream is damaged at payload offset {}; extraction resumes at offset {}",
+                failed_at, self.position
+            );
+        }
+        Ok(found)
+    }
+
+    fn skip(&mut self, len: u64) -> std::io::Result<()> {
+        std::io::copy(&mut self.by_ref().take(len), &mut std::io::sink())?;
+        Ok(())
+    }
+
+    /// Reads block by block up to one that is a header, which is handed out next
+    fn scan(&mut self) -> std::io::Result<bool> {
+        self.skip(self.position.next_multiple_of(512) - self.position)?;
+        let mut block = [0u8; 512];
+        loop {
+            match self.read_exact(&mut block) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            if is_tar_header(&block) {
+                self.pending = block.to_vec();
+                self.position -= 512;
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for TarResync<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = if self.pending.is_empty() {
+            self.inner.read(buf)?
+        } else {
+            let n = min(buf.len(), self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            n
+        };
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+// Tar Header Check (ustar or GNU magic and a matching checksum, which zeros and
+// compressed garbage do not have)
+fn is_tar_header(block: &[u8; 512]) -> bool {
+    let header = tar::Header::from_byte_slice(block);
+    if header.as_ustar().is_none() && header.as_gnu().is_none() {
+        return false;
+    }
+    // The checksum is taken with its own field read as spaces
+    let sum: u64 = block
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { b' ' } else { byte } as u64)
+        .sum();
+    header.cksum().is_ok_and(|stored| stored as u64 == sum)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SymlinkPolicy {
+    /// Extract symlinks unless they point outside the destination
+    Refuse,
+    Keep,
+    Skip,
+}
+
+#[derive(Default)]
+struct Selection {
+    written: u64,
+    total_size: u64,
+    conflicts: u64,
+    missing: Vec<String>,
+    stopped_early: bool,
+    /// Outputs written under a new name by --on-conflict rename
+    renamed: Vec<(PathBuf, PathBuf)>,
+    skipped_links: u64,
+    /// FIFOs and device nodes this process cannot create (devices need root)
+    skipped_special: u64,
+    /// Entries whose stored owner differs and could not be restored (not root)
+    unowned: u64,
+    /// Extended attributes the destination refused, as `path name: error`
+    xattr_failures: Vec<String>,
+    /// Entries whose stored ACLs the destination refused, as `path: error`
+    acl_failures: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Resolution {
+    Overwrite,
+    Skip,
+}
+
+// Collision Prompt (--interactive), remembers "all" and "skip all" answers
+#[derive(Default)]
+struct CollisionPrompt {
+    remembered: Option<Resolution>,
+    skipped: u64,
+}
+
+impl CollisionPrompt {
+    fn ask(&mut self, target: &Path) -> std::io::Result<Resolution> {
+        let resolution = match self.remembered {
+            Some(resolution) => resolution,
+            None => loop {
+                eprint!("overwrite {}? [y/N/a(ll)/s(kip all)/q] ", target.display());
+                std::io::stderr().flush()?;
+                let mut answer = String::new();
+                if std::io::stdin().read_line(&mut answer)? == 0 {
+                    break Resolution::Skip;
+                }
+                match answer.trim().to_lowercase().as_str() {
+                    "y" | "yes" => break Resolution::Overwrite,
+                    "" | "n" | "no" => break Resolution::Skip,
+                    "a" | "all" => {
+                        self.remembered = Some(Resolution::Overwrite);
+                        break Resolution::Overwrite;
+                    }
+                    "s" | "skip all" => {
+                        self.remembered = Some(Resolution::Skip);
+                        break Resolution::Skip;
+                    }
+                    "q" | "quit" => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::Interrupted,
+                            "extraction aborted",
+                        ))
+                    }
+                    _ => continue,
+                }
+            },
+        };
+        if resolution == Resolution::Skip {
+            self.skipped += 1;
+        }
+        Ok(resolution)
+    }
+}
+
+// Tar Extraction, removing whatever this run created if it fails (unless --keep-partial)
+fn extract_entries<R: Read>(
+    reader: &mut TarResync<R>,
+    plan: &Extraction,
+    prompt: Option<&mut CollisionPrompt>,
+) -> std::io::Result<Selection> {
+    let mut created = Vec::new();
+    let result = extract_entries_into(reader, plan, prompt, &mut created).and_then(|selection| {
+        // Tar stops at its end marker; read on so every chunk gets authenticated
+        if !selection.stopped_early {
+            std::io::copy(reader, &mut std::io::sink())?;
+        }
+        Ok(selection)
+    });
+    if result.is_err() && !created.is_empty() {
+        report_partial(&created, plan.keep_partial);
+    }
+    result
+}
+
+// Partial Output Report (removes the paths, or lists them with --keep-partial)
+fn report_partial(created: &[PathBuf], keep: bool) {
+    if !keep {
+        remove_created(created);
+        eprintln!(
+            "Removed {} path(s) written before the failure (use --keep-partial to keep them)",
+            created.len()
+        );
+        return;
+    }
+    eprintln!(
+        "Warning: extraction failed, these {} path(s) are incomplete or unverified:",
+        created.len()
+    );
+    for path in created.iter().take(20) {
+        eprintln!("  {}", path.display());
+    }
+    if created.len() > 20 {
+        eprintln!("  ... and {} more", created.len() - 20);
+    }
+}
+
+// Tar Extraction (every entry is placed and checked here, never by `Archive::unpack`)
+fn extract_entries_into<R: Read>(
+    reader: &mut TarResync<R>,
+    plan: &Extraction,
+    mut prompt: Option<&mut CollisionPrompt>,
+    created: &mut Vec<PathBuf>,
+) -> std::io::Result<Selection> {
+    let (dest, wanted) = (plan.dest, plan.wanted);
+    let mut pending: Vec<&String> = wanted.iter().collect();
+    let mut selection = Selection::default();
+    // A selected directory's contents can appear anywhere later in the stream
+    let mut subtree_selected = false;
+    if !plan.dry_run {
+        fs::create_dir_all(dest)?;
+    }
+    let dest_root = fs::canonicalize(dest).ok();
+    // Directory modes and mtimes are applied last: read-only directories can still
+    // be filled, and writing their contents would otherwise bump the mtimes again
+    let mut directories = Vec::new();
+    let mut directory_acls = Vec::new();
+    // Nothing is ever written below a symlink this extraction created
+    let mut extracted_links = std::collections::HashSet::new();
+
+    // Under --salvage, a pass that fails past newly damaged data is picked up again at
+    // the next entry further on
+    let mut entry_count: u64 = 0;
+    loop {
+        let mut pass = || -> std::io::Result<()> {
+            let mut archive = plan.tar(&mut *reader);
+            for entry in archive.entries()? {
+                let index = entry_count;
+                entry_count += 1;
+                let started = std::time::Instant::now();
+                let mut entry = entry?;
+                if plan.max_entries.is_some_and(|max| index >= max) {
+                    return Err(std::io::Error::other(format!(
+                "extraction stopped: archive holds more than {} entries (raise --max-entries if this is expected)",
+                plan.max_entries.unwrap_or_default()
+            )));
+                }
+                let raw_path = entry.path()?.into_owned();
+                let path = normalize_entry_path(&raw_path.to_string_lossy());
+                if !plan.filter.matches(&path) {
+                    continue;
+                }
+                if !wanted.is_empty() {
+                    let Some(selected) = wanted
+                        .iter()
+                        .find(|w| path == **w || path.starts_with(&format!("{}/", w)))
+                    else {
+                        continue;
+                    };
+                    if path != *selected || entry.header().entry_type().is_dir() {
+                        subtree_selected = true;
+                    }
+                    pending.retain(|w| *w != selected);
+                }
+
+                // Entries such as `./` that name the destination itself are skipped
+                let Some(target) = place_entry(plan, dest_root.as_deref(), &raw_path)? else {
+                    continue;
+                };
+                if let Some(link) = target.ancestors().find(|p| extracted_links.contains(*p)) {
+                    return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "unsafe path in archive: {} would be written through the symlink {} extracted before it",
+                    raw_path.display(),
+                    link.display()
+                ),
+            ));
+                }
+
+                let kind = entry.header().entry_type();
+                if is_special_entry(kind) && !can_create_special(kind) {
+                    selection.skipped_special += 1;
+                    continue;
+                }
+                if kind.is_symlink() {
+                    if plan.symlinks == SymlinkPolicy::Skip {
+                        selection.skipped_links += 1;
+                        continue;
+                    }
+                    let link = entry.link_name()?.unwrap_or_default().into_owned();
+                    if plan.symlinks == SymlinkPolicy::Refuse
+                        && !link_stays_inside(dest, &target, &link)
+                    {
+                        return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "unsafe symlink in archive: {} -> {} points outside the destination (use --keep-symlinks or --skip-symlinks)",
+                        raw_path.display(),
+                        link.display()
+                    ),
+                ));
+                    }
+                }
+
+                if let Some(prompt) = prompt.as_deref_mut() {
+                    let is_dir = entry.header().entry_type().is_dir();
+                    match fs::symlink_metadata(&target) {
+                        Ok(existing) if !(is_dir && existing.is_dir()) => {
+                            if prompt.ask(&target)? == Resolution::Skip {
+                                continue;
+                            }
+                            if !existing.is_dir() {
+                                fs::remove_file(&target)?;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                let size = entry.header().size()?;
+                if plan
+                    .max_size
+                    .is_some_and(|max| selection.total_size + size > max)
+                {
+                    return Err(std::io::Error::other(format!(
+                "extraction stopped: output would exceed {} (raise --max-extract-size if this is expected)",
+                HumanBytes(plan.max_size.unwrap_or_default())
+            )));
+                }
+
+                if plan.dry_run {
+                    let is_dir = entry.header().entry_type().is_dir();
+                    // An existing directory is only a conflict for entries that are not directories
+                    let exists =
+                        fs::symlink_metadata(&target).is_ok_and(|m| !(is_dir && m.is_dir()));
+                    print_dry_run_entry(&target, size, is_dir, exists, plan.policy);
+                    selection.written += 1;
+                    selection.total_size += size;
+                    selection.conflicts += exists as u64;
+                    continue;
+                }
+
+                created.extend(missing_ancestors(&target));
+                let renaming = plan.policy == ConflictPolicy::Rename && !kind.is_dir();
+                if !renaming && fs::symlink_metadata(&target).is_err() {
+                    created.push(target.clone());
+                }
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let owner = tar_owner(entry.header())?;
+                let (xattrs, acls) = if plan.xattrs || plan.acls {
+                    entry_attributes(&mut entry, plan)?
+                } else {
+                    Default::default()
+                };
+                if kind.is_dir() {
+                    fs::create_dir_all(&target)?;
+                    if !restore_owner(&target, &owner, plan.numeric_owner)? {
+                        selection.unowned += 1;
+                    }
+                    restore_xattrs(&target, &xattrs, &mut selection.xattr_failures);
+                    let header = entry.header();
+                    // The access ACL has to follow the chmod, which waits for the directory's contents
+                    if !acls.is_empty() {
+                        directory_acls.push((target.clone(), acls));
+                    }
+                    directories.push((target, header.mode()?, header.mtime()?));
+                } else if kind.is_hard_link() {
+                    // Link sources name other archive entries and are placed the same way
+                    let source = entry.link_name()?.unwrap_or_default().into_owned();
+                    let Some(mut source_path) = place_entry(plan, dest_root.as_deref(), &source)?
+                    else {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("invalid hard link in archive: {}", raw_path.display()),
+                        ));
+                    };
+                    if renaming {
+                        // A source that was written under a new name is linked there, not to
+                        // the file it made way for
+                        if let Some((_, written)) =
+                            selection.renamed.iter().find(|(w, _)| *w == source_path)
+                        {
+                            source_path = written.clone();
+                        }
+                        let ((), written_path) =
+                            place_renamed(&target, plan.suffix, |candidate| {
+                                fs::hard_link(&source_path, candidate)
+                            })?;
+                        created.push(written_path.clone());
+                        if written_path != target {
+                            selection.renamed.push((target, written_path));
+                        }
+                    } else {
+                        match fs::hard_link(&source_path, &target) {
+                            Err(e)
+                                if e.kind() == std::io::ErrorKind::AlreadyExists
+                                    && plan.policy == ConflictPolicy::Overwrite =>
+                            {
+                                fs::remove_file(&target)?;
+                                fs::hard_link(&source_path, &target)?;
+                            }
+                            result => result?,
+                        }
+                    }
+                } else if is_special_entry(kind) {
+                    let written_path = if renaming {
+                        let ((), written_path) =
+                            place_renamed(&target, plan.suffix, |candidate| {
+                                make_special(candidate, entry.header(), plan)
+                            })?;
+                        created.push(written_path.clone());
+                        written_path
+                    } else {
+                        make_special(&target, entry.header(), plan)?;
+                        target.clone()
+                    };
+                    if !restore_owner(&written_path, &owner, plan.numeric_owner)? {
+                        selection.unowned += 1;
+                    }
+                    restore_xattrs(&written_path, &xattrs, &mut selection.xattr_failures);
+                    if written_path != target {
+                        selection.renamed.push((target, written_path));
+                    }
+                } else if renaming && kind.is_symlink() {
+                    let link = entry.link_name()?.unwrap_or_default().into_owned();
+                    let written_path = symlink_renamed(&link, &target, plan.suffix)?;
+                    created.push(written_path.clone());
+                    if !restore_owner(&written_path, &owner, plan.numeric_owner)? {
+                        selection.unowned += 1;
+                    }
+                    restore_xattrs(&written_path, &xattrs, &mut selection.xattr_failures);
+                    extracted_links.insert(written_path.clone());
+                    if written_path != target {
+                        selection.renamed.push((target, written_path));
+                    }
+                } else if renaming {
+                    let (mut file, written_path) = create_renamed(&target, plan.suffix)?;
+                    created.push(written_path.clone());
+                    std::io::copy(&mut entry, &mut file)?;
+                    // chown clears setuid and setgid, so the mode goes on afterwards
+                    if !restore_owner(&written_path, &owner, plan.numeric_owner)? {
+                        selection.unowned += 1;
+                    }
+                    restore_xattrs(&written_path, &xattrs, &mut selection.xattr_failures);
+                    apply_entry_metadata(&file, entry.header(), plan)?;
+                    restore_acls(&written_path, &acls, &mut selection.acl_failures);
+                    if written_path != target {
+                        selection.renamed.push((target, written_path));
+                    }
+                } else {
+                    entry.unpack(&target)?;
+                    // After chown, which would drop security.capability again
+                    if !restore_owner(&target, &owner, plan.numeric_owner)? {
+                        selection.unowned += 1;
+                    }
+                    restore_xattrs(&target, &xattrs, &mut selection.xattr_failures);
+                    if kind.is_symlink() {
+                        extracted_links.insert(target);
+                    } else if kind.is_file() {
+                        // tar sets the stored bits as they are; narrow them to the umask or -p
+                        #[cfg(unix)]
+                        {
+                            use std::os::unix::fs::PermissionsExt;
+                            let mode = entry.header().mode()? & plan.mode_mask;
+                            fs::set_permissions(&target, fs::Permissions::from_mode(mode))?;
+                        }
+                        restore_acls(&target, &acls, &mut selection.acl_failures);
+                    }
+                }
+
+                selection.written += 1;
+                selection.total_size += size;
+                print_verbose_entry(
+                    plan.progress,
+                    plan.verbosity,
+                    Path::new(&path),
+                    size,
+                    started,
+                );
+
+                // Everything asked for has been written; skip decrypting the rest
+                if !wanted.is_empty() && pending.is_empty() && !subtree_selected {
+                    selection.stopped_early = true;
+                    break;
+                }
+            }
+            Ok(())
+        };
+        match pass() {
+            Err(_) if reader.resume()? => continue,
+            result => break result?,
+        }
+    }
+    apply_directory_metadata(&mut directories, plan)?;
+    for (path, acls) in &directory_acls {
+        restore_acls(path, acls, &mut selection.acl_failures);
+    }
+    selection.missing = pending.into_iter().cloned().collect();
+    Ok(selection)
+}
+
+// Special Entry Helpers (tar would otherwise write FIFOs and device nodes as empty files)
+fn is_special_entry(kind: tar::EntryType) -> bool {
+    kind.is_fifo() || kind.is_character_special() || kind.is_block_special()
+}
+
+fn can_create_special(kind: tar::EntryType) -> bool {
+    #[cfg(unix)]
+    {
+        kind.is_fifo() || unsafe { libc::geteuid() } == 0
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = kind;
+        false
+    }
+}
+
+#[cfg(unix)]
+fn make_special(path: &Path, header: &tar::Header, plan: &Extraction) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let kind = header.entry_type();
+    let (file_type, device) = if kind.is_fifo() {
+        (libc::S_IFIFO, 0)
+    } else {
+        let major = header.device_major()?.unwrap_or_default();
+        let minor = header.device_minor()?.unwrap_or_default();
+        let file_type = if kind.is_character_special() {
+            libc::S_IFCHR
+        } else {
+            libc::S_IFBLK
+        };
+        (file_type, libc::makedev(major as _, minor as _))
+    };
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mode = file_type | (header.mode()? & plan.mode_mask) as libc::mode_t;
+    let mknod = || match unsafe { libc::mknod(c_path.as_ptr(), mode, device) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    };
+    match mknod() {
+        Err(e)
+            if e.kind() == std::io::ErrorKind::AlreadyExists
+                && plan.policy == ConflictPolicy::Overwrite =>
+        {
+            fs::remove_file(path)?;
+            mknod()
+        }
+        result => result,
+    }
+}
+
+#[cfg(not(unix))]
+fn make_special(_path: &Path, _header: &tar::Header, _plan: &Extraction) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Missing Ancestors Helper (outermost first, so they can be removed in reverse)
+fn missing_ancestors(path: &Path) -> Vec<PathBuf> {
+    let mut missing: Vec<PathBuf> = path
+        .ancestors()
+        .skip(1)
+        .take_while(|p| !p.as_os_str().is_empty() && fs::symlink_metadata(p).is_err())
+        .map(Path::to_path_buf)
+        .collect();
+    missing.reverse();
+    missing
+}
+
+// Partial Output Cleanup (newest first; directories only go once they are empty)
+fn remove_created(created: &[PathBuf]) {
+    for path in created.iter().rev() {
+        let _ = match fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_dir() => fs::remove_dir(path),
+            Ok(_) => fs::remove_file(path),
+            Err(_) => continue,
+        };
+    }
+}
+
+// Deferred Directory Metadata (deepest first, like GNU tar)
+fn apply_directory_metadata(
+    directories: &mut [(PathBuf, u32, u64)],
+    plan: &Extraction,
+) -> std::io::Result<()> {
+    directories.sort_by_key(|(path, _, _)| std::cmp::Reverse(path.components().count()));
+    for (path, mode, mtime) in directories.iter() {
+        if plan.preserve_mtimes {
+            let mtime = filetime::FileTime::from_unix_time(*mtime as i64, 0);
+            filetime::set_file_mtime(path, mtime)?;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & plan.mode_mask))?;
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+    }
+    Ok(())
+}
+
+// Entry Placement Helper (destination path after --as/--strip-components, None to skip)
+fn place_entry(
+    plan: &Extraction,
+    dest_root: Option<&Path>,
+    raw_path: &Path,
+) -> std::io::Result<Option<PathBuf>> {
+    let is_unsafe = raw_path.components().any(|c| {
+        !matches!(
+            c,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    });
+    if is_unsafe && plan.allow_unsafe_paths {
+        return Ok(Some(plan.dest.join(raw_path)));
+    }
+
+    let placed = match plan.root_name {
+        _ if plan.strip > 0 => stripped_target(plan.dest, raw_path, plan.strip)?,
+        Some(name) => Some(rerooted_target(plan.dest, name, raw_path)?),
+        None => stripped_target(plan.dest, raw_path, 0)?,
+    };
+    if let (Some(target), Some(root)) = (&placed, dest_root) {
+        ensure_inside(root, target, raw_path)?;
+    }
+    Ok(placed)
+}
+
+// Symlink Containment Check (resolves `link` from the symlink's own directory, refusing `..`
+// steps that would go back up through another symlink)
+fn link_stays_inside(dest: &Path, target: &Path, link: &Path) -> bool {
+    let Ok(relative) = target.strip_prefix(dest) else {
+        return false;
+    };
+    let mut resolved: Vec<_> = relative
+        .parent()
+        .map(|p| p.components().collect())
+        .unwrap_or_default();
+    for component in link.components() {
+        match component {
+            std::path::Component::Normal(_) => resolved.push(component),
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                let current: PathBuf = resolved.iter().collect();
+                let through_link = fs::symlink_metadata(dest.join(&current))
+                    .is_ok_and(|m| m.file_type().is_symlink());
+                if through_link || resolved.pop().is_none() {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+// Containment Check (the deepest existing ancestor of `target` must resolve inside `root`)
+fn ensure_inside(root: &Path, target: &Path, raw_path: &Path) -> std::io::Result<()> {
+    let Some(existing) = target
+        .ancestors()
+        .skip(1)
+        .find(|p| fs::symlink_metadata(p).is_ok())
+    else {
+        return Ok(());
+    };
+    if fs::canonicalize(existing)?.starts_with(root) {
+        return Ok(());
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!(
+            "unsafe path in archive: {} resolves outside the destination (use --allow-unsafe-paths to extract it anyway)",
+            raw_path.display()
+        ),
+    ))
+}
+
+// Bounded Copy Helper (--max-extract-size for single-file archives)
+fn copy_limited<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    limit: Option<u64>,
+) -> Result<u64> {
+    let Some(limit) = limit else {
+        return Ok(std::io::copy(reader, writer)?);
+    };
+    let copied = std::io::copy(&mut reader.take(limit + 1), writer)?;
+    if copied > limit {
+        return Err(anyhow!(
+            "Extraction stopped: output would exceed {} (raise --max-extract-size if this is expected)",
+            HumanBytes(limit)
+        ));
+    }
+    Ok(copied)
+}
+
+// Collision-free File Creation (--on-conflict rename), retries `create_new` on numbered names
+fn create_renamed(path: &Path, suffix: &str) -> std::io::Result<(File, PathBuf)> {
+    place_renamed(path, suffix, |candidate| {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(candidate)
+    })
+}
+
+// Collision-free Symlink Creation (--on-conflict rename), the link version of create_renamed
+fn symlink_renamed(link: &Path, path: &Path, suffix: &str) -> std::io::Result<PathBuf> {
+    #[cfg(unix)]
+    {
+        place_renamed(path, suffix, |candidate| {
+            std::os::unix::fs::symlink(link, candidate)
+        })
+        .map(|((), written_path)| written_path)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (link, suffix);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("cannot create the symlink {} here", path.display()),
+        ))
+    }
+}
+
+// Numbered Name Helper (retries `create` until it no longer meets an existing path)
+fn place_renamed<T>(
+    path: &Path,
+    suffix: &str,
+    mut create: impl FnMut(&Path) -> std::io::Result<T>,
+) -> std::io::Result<(T, PathBuf)> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut candidate = path.to_path_buf();
+    for n in 1..=10_000u32 {
+        match create(&candidate) {
+            Ok(created) => return Ok((created, candidate)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let renamed = format!("{}{}", file_name, suffix.replace("{n}", &n.to_string()));
+                candidate = path.with_file_name(renamed);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        format!("no free name left for {}", path.display()),
+    ))
+}
+
+// Entry Metadata Helper (mode and mtime for files written outside of tar's unpack)
+fn apply_entry_metadata(
+    file: &File,
+    header: &tar::Header,
+    plan: &Extraction,
+) -> std::io::Result<()> {
+    if plan.preserve_mtimes {
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(header.mtime()?);
+        file.set_modified(mtime)?;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(header.mode()? & plan.mode_mask))?;
+    }
+    Ok(())
+}
+
+// Ownership Helper (root chowns to the stored owner; false when it could not be restored)
+fn restore_owner(path: &Path, owner: &FileOwner, numeric: bool) -> std::io::Result<bool> {
+    #[cfg(unix)]
+    {
+        let (uid, gid) = if numeric {
+            (owner.uid, owner.gid)
+        } else {
+            (
+                user_id(&owner.user).unwrap_or(owner.uid),
+                group_id(&owner.group).unwrap_or(owner.gid),
+            )
+        };
+        // SAFETY: geteuid and getegid have no preconditions and cannot fail
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        if euid != 0 {
+            return Ok(uid == euid && gid == egid);
+        }
+        std::os::unix::fs::lchown(path, Some(uid), Some(gid))?;
+        Ok(true)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, owner, numeric);
+        Ok(true)
+    }
+}
+
+// Stored Attributes (xattrs and ACLs as xattr name/value pairs, read before the entry's data)
+type XattrList = Vec<(String, Vec<u8>)>;
+
+fn entry_attributes<R: Read>(
+    entry: &mut tar::Entry<R>,
+    plan: &Extraction,
+) -> std::io::Result<(XattrList, XattrList)> {
+    let (mut xattrs, mut acls) = (Vec::new(), Vec::new());
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok((xattrs, acls));
+    };
+    for extension in extensions {
+        let extension = extension?;
+        let Ok(key) = extension.key() else {
+            continue;
+        };
+        let value = extension.value_bytes().to_vec();
+        if let Some(name) = key.strip_prefix(PAX_XATTR_PREFIX).filter(|_| plan.xattrs) {
+            xattrs.push((name.to_string(), value));
+        } else if let Some((_, name)) = PAX_ACL_RECORDS
+            .iter()
+            .find(|(record, _)| *record == key)
+            .filter(|_| plan.acls)
+        {
+            acls.push((name.to_string(), value));
+        }
+    }
+    Ok((xattrs, acls))
+}
+
+// Xattr Restore Helper (refusals are collected for one warning, never fatal)
+#[cfg(all(unix, feature = "xattrs"))]
+fn restore_xattrs(path: &Path, xattrs: &[(String, Vec<u8>)], failures: &mut Vec<String>) {
+    for (name, value) in xattrs {
+        if let Err(e) = xattr::set(path, name, value) {
+            failures.push(format!("{} {}: {}", path.display(), name, e));
+        }
+    }
+}
+
+#[cfg(not(all(unix, feature = "xattrs")))]
+fn restore_xattrs(_path: &Path, _xattrs: &[(String, Vec<u8>)], _failures: &mut Vec<String>) {}
+
+// ACL Restore Helper (one failure per entry; the extraction carries on)
+#[cfg(all(target_os = "linux", feature = "acls"))]
+fn restore_acls(path: &Path, acls: &[(String, Vec<u8>)], failures: &mut Vec<String>) {
+    for (name, value) in acls {
+        if let Err(e) = xattr::set(path, name, value) {
+            failures.push(format!("{}: {}", path.display(), e));
+            return;
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "acls")))]
+fn restore_acls(_path: &Path, _acls: &[(String, Vec<u8>)], _failures: &mut Vec<String>) {}
+
+// Tar Owner Helper (names are only present in archives written with them)
+fn tar_owner(header: &tar::Header) -> std::io::Result<FileOwner> {
+    let name = |name: std::result::Result<Option<&str>, _>| -> String {
+        name.ok().flatten().unwrap_or_default().to_string()
+    };
+    Ok(FileOwner {
+        uid: header.uid()? as u32,
+        gid: header.gid()? as u32,
+        user: name(header.username()),
+        group: name(header.groupname()),
+    })
+}
+
+// Single-File Metadata (owner, mode and mtime stored in the header)
+fn record_file_metadata(header: &mut RstfHeader, path: &Path) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    header.owner = file_owner(&metadata);
+    header.mode = Some(file_mode(&metadata));
+    header.mtime = metadata.modified().ok().map(|modified| {
+        match modified.duration_since(std::time::UNIX_EPOCH) {
+            Ok(after) => after.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        }
+    });
+}
+
+// Sparse Extents Helper (data regions from SEEK_DATA/SEEK_HOLE; None packs densely)
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn sparse_extents(path: &Path) -> Option<Vec<(u64, u64)>> {
+    #[cfg(target_os = "freebsd")]
+    use libc::lseek;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    use libc::lseek64 as lseek;
+    use std::os::unix::io::AsRawFd;
+
+    let file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    if size == 0 {
+        return None;
+    }
+    // ENXIO means no data at or past the offset; any other error means no hole support
+    let seek = |offset: u64, whence| match unsafe { lseek(file.as_raw_fd(), offset as _, whence) } {
+        -1 => match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::ENXIO) => Some(None),
+            _ => None,
+        },
+        position => Some(Some(position as u64)),
+    };
+    let mut extents = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let Some(start) = seek(offset, libc::SEEK_DATA)? else {
+            break;
+        };
+        let end = seek(start, libc::SEEK_HOLE)?.unwrap_or(size).min(size);
+        extents.push((start, end - start));
+        offset = end;
+    }
+    // A file without holes gains nothing from an extent list
+    if extents == [(0, size)] {
+        return None;
+    }
+    Some(extents)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn sparse_extents(_path: &Path) -> Option<Vec<(u64, u64)>> {
+    None
+}
+
+// Sparse Payload Reader (zeros for the holes of a --sparse single file, so it reads densely)
+struct HoleFiller<R: Read> {
+    inner: R,
+    /// None for dense payloads, which pass through untouched
+    extents: Option<std::collections::VecDeque<(u64, u64)>>,
+    size: u64,
+    position: u64,
+}
+
+impl<R: Read> HoleFiller<R> {
+    fn new(inner: R, header: &RstfHeader) -> Self {
+        HoleFiller {
+            inner,
+            extents: header
+                .extents
+                .as_ref()
+                .map(|extents| extents.iter().copied().collect()),
+            size: header.original_size,
+            position: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for HoleFiller<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(extents) = self.extents.as_mut() else {
+            return self.inner.read(buf);
+        };
+        let Some(&(offset, len)) = extents.front() else {
+            // Trailing hole up to the recorded size
+            let n = min(buf.len() as u64, self.size.saturating_sub(self.position)) as usize;
+            buf[..n].fill(0);
+            self.position += n as u64;
+            return Ok(n);
+        };
+        if self.position < offset {
+            let n = min(buf.len() as u64, offset - self.position) as usize;
+            buf[..n].fill(0);
+            self.position += n as u64;
+            return Ok(n);
+        }
+        let remaining = (offset + len).saturating_sub(self.position);
+        let want = min(buf.len() as u64, remaining) as usize;
+        let n = self.inner.read(&mut buf[..want])?;
+        if n == 0 && want > 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "payload ended inside a sparse data extent",
+            ));
+        }
+        if n as u64 == remaining {
+            extents.pop_front();
+        }
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+// Sparse Write Helper (seeks over holes and sets the final length, leaving them unallocated)
+fn write_sparse<R: Read>(
+    reader: &mut R,
+    file: &mut File,
+    header: &RstfHeader,
+    extents: &[(u64, u64)],
+    limit: Option<u64>,
+    pb: &ProgressBar,
+) -> Result<u64> {
+    if let Some(limit) = limit.filter(|&limit| header.original_size > limit) {
+        return Err(anyhow!(
+            "Extraction stopped: output would exceed {} (raise --max-extract-size if this is expected)",
+            HumanBytes(limit)
+        ));
+    }
+    for &(offset, len) in extents {
+        file.seek(SeekFrom::Start(offset))?;
+        if std::io::copy(&mut (&mut *reader).take(len), file)? != len {
+            return Err(anyhow!("Archive payload ended inside a sparse data extent"));
+        }
+        pb.set_position(offset + len);
+    }
+    file.set_len(header.original_size)?;
+    pb.set_position(header.original_size);
+    Ok(header.original_size)
+}
+
+// Input Owner Helper (ids and names of a file being packed)
+#[cfg(unix)]
+fn file_owner(metadata: &fs::Metadata) -> Option<FileOwner> {
+    use std::os::unix::fs::MetadataExt;
+    Some(FileOwner {
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        user: user_name(metadata.uid()).unwrap_or_default(),
+        group: group_name(metadata.gid()).unwrap_or_default(),
+    })
+}
+
+#[cfg(not(unix))]
+fn file_owner(_metadata: &fs::Metadata) -> Option<FileOwner> {
+    None
+}
+
+// Mode Helper (Unix permission bits; elsewhere only the read-only flag maps over)
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o7777
+    }
+    #[cfg(not(unix))]
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+// Restored File Metadata (single-file unpack; mode after chown, which clears setuid)
+fn apply_file_metadata(path: &Path, header: &RstfHeader, args: &UnpackArgs) -> Result<()> {
+    if let Some(mtime) = header.mtime.filter(|_| !args.no_preserve_mtimes) {
+        filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(mtime, 0))
+            .context("Failed to restore the modification time")?;
+    }
+    if let Some(mode) = header.mode {
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::Permissions::from_mode(mode & args.mode_mask())
+        };
+        #[cfg(not(unix))]
+        let permissions = {
+            let mut permissions = fs::metadata(path)?.permissions();
+            permissions.set_readonly(mode & 0o222 == 0);
+            permissions
+        };
+        fs::set_permissions(path, permissions).context("Failed to restore file permissions")?;
+    }
+    Ok(())
+}
+
+// User and Group Lookups (reentrant libc calls into the passwd and group databases)
+#[cfg(unix)]
+const LOOKUP_BUFFER_LEN: usize = 16 * 1024;
+
+#[cfg(unix)]
+fn user_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name)
+        .ok()
+        .filter(|n| !n.is_empty())?;
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    // SAFETY: passwd is plain data, and every pointer handed over outlives the call
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    (status == 0 && !result.is_null()).then_some(entry.pw_uid)
+}
+
+#[cfg(unix)]
+fn group_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name)
+        .ok()
+        .filter(|n| !n.is_empty())?;
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    // SAFETY: group is plain data, and every pointer handed over outlives the call
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    (status == 0 && !result.is_null()).then_some(entry.gr_gid)
+}
+
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    // SAFETY: passwd is plain data, and pw_name points into buffer on success
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(entry.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(unix)]
+fn group_name(gid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    // SAFETY: group is plain data, and gr_name points into buffer on success
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getgrgid_r(
+            gid,
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(entry.gr_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+// Renamed Outputs Report (one `old -> new` line per file, for scripts)
+fn print_renamed(renamed: &[(PathBuf, PathBuf)]) {
+    if renamed.is_empty() {
+        return;
+    }
+    println!("Renamed to avoid conflicts:");
+    for (wanted, written) in renamed {
+        println!("  {} -> {}", wanted.display(), written.display());
+    }
+}
+
+// Dry-run Entry Line
+fn print_dry_run_entry(
+    target: &Path,
+    size: u64,
+    is_dir: bool,
+    exists: bool,
+    policy: ConflictPolicy,
+) {
+    let target = std::path::absolute(target).unwrap_or(target.to_path_buf());
+    println!(
+        "{:>14}  {}{}{}",
+        size,
+        target.display(),
+        if is_dir { "/" } else { "" },
+        match (exists, policy) {
+            (false, _) => "",
+            (true, ConflictPolicy::Abort) => "  [exists]",
+            (true, ConflictPolicy::Overwrite) => "  [exists, would overwrite]",
+            (true, ConflictPolicy::Rename) => "  [exists, would be renamed]",
+        }
+    );
+}
+
+// Dry-run Summary
+fn print_dry_run_summary(selection: &Selection, output_path: &Path, policy: ConflictPolicy) {
+    let output_path = std::path::absolute(output_path).unwrap_or(output_path.to_path_buf());
+    println!(
+        "Dry run: {} entries, {} total would be written to {}",
+        selection.written,
+        HumanBytes(selection.total_size),
+        output_path.display()
+    );
+    if selection.conflicts > 0 {
+        let outcome = match policy {
+            ConflictPolicy::Abort => "already exist (extraction would fail without --force)",
+            ConflictPolicy::Overwrite => "already exist and would be overwritten",
+            ConflictPolicy::Rename => "already exist (files would be written under new names)",
+        };
+        println!("{} path(s) {}", selection.conflicts, outcome);
+    }
+}
+
+type ArchiveReader = DecryptedReader<std::io::Take<File>>;
+
+// Archive Opening Helper (prompts for credentials and decrypts the header)
+fn open_archive(
+    input_path: &Path,
+    credentials: &Credentials,
+) -> Result<(RstfHeader, ArchiveReader)> {
+    open_archive_with(input_path, OpenArchiveOptions::default(), |prologue| {
+        credentials.derive_key(&prologue.salt, &prologue.kdf)
+    })
+}
+
+// Archive Opening Options (damage unpack can be told to live with)
+#[derive(Default, Clone, Copy)]
+struct OpenArchiveOptions<'a> {
+    /// Skip data appended after the archive instead of refusing it
+    allow_trailing: bool,
+    /// Log payload chunks and a trailer that fail to authenticate here and carry on
+    /// (unpack --salvage)
+    salvage: Option<&'a DamageLog>,
+}
+
+// Archive Opening Helper (key supplied by the caller once the salt is known)
+fn open_archive_with(
+    input_path: &Path,
+    options: OpenArchiveOptions<'_>,
+    derive_key: impl FnOnce(&Prologue) -> Result<[u8; 32]>,
+) -> Result<(RstfHeader, ArchiveReader)> {
+    let mut input_file = File::open(input_path).context("Failed to open .rstf")?;
+    let mut archive_size = input_file
+        .metadata()
+        .context("Failed to read archive metadata")?
+        .len();
+    let prologue = Prologue::read(&mut input_file)?;
+    prologue.cipher.check_available()?;
+    let mut footer_missing = false;
+    if prologue.version >= 12 {
+        // The archive ends before its checksum footer; without one, the trailer
+        // search below reports whatever was cut or appended
+        let resume = input_file.stream_position()?;
+        match ChecksumFooter::find(&mut input_file, archive_size)? {
+            Some(footer) => archive_size -= footer.len,
+            None => footer_missing = true,
+        }
+        input_file.seek(SeekFrom::Start(resume))?;
+    }
+
+    let key = if prologue.is_plaintext() {
+        [0u8; 32]
+    } else {
+        derive_key(&prologue)?
+    };
+    prologue.check_key(&key)?;
+    let mut crypto_reader = decrypting_reader(input_file.take(u64::MAX), &key, &prologue)?;
+
+    let header_data = if prologue.version >= 2 {
+        let len_bytes = crypto_reader
+            .read_chunk(4 + TAG_SIZE)
+            .map_err(|e| header_chunk_error(e, &prologue))?;
+        let len_bytes: [u8; 4] = len_bytes
+            .try_into()
+            .map_err(|_| anyhow!("Malformed header length chunk"))?;
+        let header_len = u32::from_le_bytes(len_bytes) as usize;
+        crypto_reader
+            .read_chunk(header_len + TAG_SIZE)
+            .context("Failed to decrypt header")?
+    } else {
+        // Format 1 streams the header through the first payload chunk. Without the
+        // magic, random data and a wrong password look the same
+        let mut len_bytes = [0u8; 4];
+        crypto_reader.read_exact(&mut len_bytes).map_err(|_| {
+            ArchiveError::NotAnArchive(format!(
+                "Not an rstf archive: '{}' has no RSTF magic and does not open as a magic-less (format 1) archive with these credentials",
+                input_path.display()
+            ))
+        })?;
+        eprintln!(
+            "Warning: '{}' is a magic-less format 1 archive; support for these ends with the next release, so repack it (`rstf repack <archive> --replace`)",
+            input_path.display()
+        );
+        let header_len = u32::from_le_bytes(len_bytes) as usize;
+
+        let mut header_data = vec![0u8; header_len];
+        crypto_reader.read_exact(&mut header_data)?;
+        header_data
+    };
+    let mut header = RstfHeader::from_bytes(&header_data, prologue.version)?;
+    header.format_version = prologue.version;
+    header.cipher = prologue.cipher;
+    header.plaintext = prologue.is_plaintext();
+    header.chunk_size = prologue.chunk_size;
+    crypto_reader.salvage = options.salvage.cloned();
+
+    if header.trailer_len > 0 {
+        let sealed_trailer = (header.trailer_len as usize + TAG_SIZE) as u64;
+        let sealed_chunk = (prologue.chunk_size + TAG_SIZE) as u64;
+        let payload_start = prologue.len() + (4 + TAG_SIZE + header_data.len() + TAG_SIZE) as u64;
+        let mut payload_len = archive_size
+            .checked_sub(payload_start + sealed_trailer)
+            .ok_or_else(|| {
+                ArchiveError::Truncated("Archive is truncated (trailer is missing)".into())
+            })?;
+
+        let mut trailer_file = File::open(input_path).context("Failed to open .rstf")?;
+        let trailer_chunk = SealedTrailer {
+            cipher: ChunkCipher::new(&prologue, &key)?,
+            // Format 8 seals the trailer as the last chunk; before that it was an ordinary one
+            last: prologue.version >= 8,
+            aad: prologue.aad(),
+            payload_start,
+            sealed_chunk,
+            sealed_len: sealed_trailer,
+        };
+        let mut trailer = trailer_chunk.open(&mut trailer_file, payload_len)?;
+        if trailer.is_some() && footer_missing {
+            // Exactly the footer was cut off; the trailer still vouches for the payload
+            eprintln!(
+                "Warning: '{}' ends without its checksum footer; it was cut short, though not into the payload",
+                input_path.display()
+            );
+        }
+        if trailer.is_none() {
+            if let Some(chunks) =
+                ends_on_payload_chunk(&mut trailer_file, &trailer_chunk, archive_size)
+            {
+                return Err(ArchiveError::Truncated(format!(
+                    "Archive is truncated: it ends after payload chunk {}, before the trailer that closes it",
+                    chunks
+                ))
+                .into());
+            }
+            if let Some(end) = trailer_chunk.find_end(&mut trailer_file, archive_size)? {
+                let extra = archive_size - end;
+                if footer_missing && !options.allow_trailing {
+                    let mut tail = Vec::new();
+                    trailer_file.seek(SeekFrom::Start(end))?;
+                    (&mut trailer_file)
+                        .take(ChecksumFooter::LEN as u64)
+                        .read_to_end(&mut tail)?;
+                    if ChecksumFooter::is_cut_short(&tail) {
+                        return Err(ArchiveError::Truncated(format!(
+                            "Archive is truncated: only {} of the {} bytes of its checksum footer are left",
+                            extra,
+                            ChecksumFooter::LEN
+                        ))
+                        .into());
+                    }
+                }
+                if !options.allow_trailing {
+                    return Err(anyhow!(
+                        "Unexpected data after end of archive ({} extra bytes); --allow-trailing ignores it",
+                        extra
+                    ));
+                }
+                eprintln!(
+                    "Warning: ignoring {} bytes after the end of '{}'",
+                    extra,
+                    input_path.display()
+                );
+                payload_len = end - payload_start - sealed_trailer;
+                trailer = trailer_chunk.open(&mut trailer_file, payload_len)?;
+            }
+        }
+        let trailer_position = 2 + payload_len.div_ceil(sealed_chunk);
+        let Some(trailer) = trailer else {
+            if let Some(log) = options.salvage {
+                // Without the trailer the payload is taken to run up to the end of the file
+                eprintln!(
+                    "Warning: the trailer of '{}' is damaged; salvaging without its digest",
+                    input_path.display()
+                );
+                log.lock()
+                    .map_err(|_| anyhow!("Damage log is poisoned"))?
+                    .push(DamagedChunk {
+                        chunk: u32::try_from(trailer_position).unwrap_or(u32::MAX),
+                        file_offset: payload_start + payload_len,
+                        len: 0,
+                    });
+                crypto_reader.inner.set_limit(payload_len);
+                return Ok((header, crypto_reader));
+            }
+            if footer_missing {
+                // Nothing was appended (that is found above), so the end was cut off
+                return Err(ArchiveError::Truncated(format!(
+                    "archive is truncated: its checksum footer is missing and no trailer opens at chunk {} (ciphertext offset {})",
+                    trailer_position,
+                    HumanCount(payload_start + payload_len)
+                ))
+                .into());
+            }
+            return Err(ArchiveError::Corrupted(format!(
+                "authentication failed at trailer chunk {} (ciphertext offset {}); the archive is truncated or damaged",
+                trailer_position,
+                HumanCount(payload_start + payload_len)
+            ))
+            .into_io(std::io::ErrorKind::InvalidData)
+            .into());
+        };
+        // Stop the payload stream where the trailer chunk begins
+        crypto_reader.inner.set_limit(payload_len);
+        header.trailer = Some(Trailer::from_bytes(&trailer)?);
+
+        let index_len = Trailer::index_len(&trailer)?;
+        if (index_len > 0) != header.index_span.is_some() {
+            return Err(anyhow!(
+                "Archive header and trailer disagree about the chunk index"
+            ));
+        }
+        let padding_len = Trailer::padding_len(&trailer)?;
+        if padding_len > 0 && !header.padded {
+            return Err(anyhow!(
+                "Archive trailer records padding the header does not mention"
+            ));
+        }
+        // Every chunk but the last is full, so the plaintext length follows from the sealed one
+        let tags = payload_len.div_ceil(sealed_chunk) * TAG_SIZE as u64;
+        let stream_len = payload_len.saturating_sub(tags);
+        let packed_len = stream_len
+            .checked_sub(index_len.saturating_add(padding_len))
+            .ok_or_else(|| anyhow!("Chunk index and padding are longer than the payload stream"))?;
+        // The payload stream stops where the index or padding begins
+        if index_len > 0 || padding_len > 0 {
+            crypto_reader.remaining = packed_len;
+        }
+        if index_len > 0 {
+            let mut index = ChunkIndex {
+                payload_start,
+                ciphertext_len: payload_len,
+                packed_len,
+                ..Default::default()
+            };
+            let index_file = File::open(input_path).context("Failed to open .rstf")?;
+            let mut index_reader = decrypting_reader(index_file.take(0), &key, &prologue)?;
+            index_reader.seek_payload(&index, packed_len, packed_len + index_len)?;
+            let mut bytes = Vec::new();
+            index_reader
+                .read_to_end(&mut bytes)
+                .context("Failed to read the chunk index")?;
+            index = ChunkIndex {
+                payload_start,
+                ciphertext_len: payload_len,
+                packed_len,
+                ..ChunkIndex::from_bytes(&bytes)?
+            };
+            header.index = Some(index);
+        }
+    }
+
+    Ok((header, crypto_reader))
+}
+
+// Trailer Chunk Location (everything needed to open the sealed trailer wherever it sits)
+struct SealedTrailer {
+    cipher: ChunkCipher,
+    last: bool,
+    aad: Vec<u8>,
+    payload_start: u64,
+    sealed_chunk: u64,
+    sealed_len: u64,
+}
+
+impl SealedTrailer {
+    /// Trailer following `payload_len` bytes of payload ciphertext, if it authenticates there
+    fn open(&self, file: &mut File, payload_len: u64) -> Result<Option<Vec<u8>>> {
+        file.seek(SeekFrom::Start(self.payload_start + payload_len))?;
+        let mut sealed = vec![0u8; self.sealed_len as usize];
+        file.read_exact(&mut sealed)
+            .context("Failed to read archive trailer")?;
+        Ok(self.decrypt(payload_len, &sealed))
+    }
+
+    fn decrypt(&self, payload_len: u64, sealed: &[u8]) -> Option<Vec<u8>> {
+        // Header chunks are 0 and 1, payload chunks follow, then the trailer
+        let position = u32::try_from(2 + payload_len.div_ceil(self.sealed_chunk)).ok()?;
+        self.cipher
+            .decrypt(position, self.last, self.payload(sealed))
+    }
+
+    fn payload<'a>(&'a self, sealed: &'a [u8]) -> Payload<'a, 'a> {
+        Payload {
+            msg: sealed,
+            aad: &self.aad,
+        }
+    }
+
+    /// Where the archive really ends when more data follows it: walks the full
+    /// payload chunks (every one but the last is full), then looks for the trailer
+    /// behind the short final chunk. Reads the whole payload, so it is only used
+    /// once the trailer failed to open at the end of the file
+    fn find_end(&self, file: &mut File, archive_size: u64) -> Result<Option<u64>> {
+        file.seek(SeekFrom::Start(self.payload_start))?;
+        let mut reader = std::io::BufReader::new(file);
+        let full = self.sealed_chunk as usize;
+        let trailer_len = self.sealed_len as usize;
+        let mut window = vec![0u8; full + trailer_len];
+        let mut filled = read_fully(&mut reader, &mut window)?;
+        let mut chunk = 0u64;
+        loop {
+            let is_full_chunk = filled >= full
+                && u32::try_from(2 + chunk).is_ok_and(|position| {
+                    self.cipher
+                        .decrypt(position, false, self.payload(&window[..full]))
+                        .is_some()
+                });
+            if !is_full_chunk {
+                for final_len in TAG_SIZE..full {
+                    if final_len + trailer_len > filled {
+                        break;
+                    }
+                    let payload_len = chunk * self.sealed_chunk + final_len as u64;
+                    let sealed = &window[final_len..final_len + trailer_len];
+                    if self.decrypt(payload_len, sealed).is_some() {
+                        let end = self.payload_start + payload_len + self.sealed_len;
+                        return Ok((end < archive_size).then_some(end));
+                    }
+                }
+                return Ok(None);
+            }
+            // Keep what was read past this chunk and top the window up
+            window.copy_within(full..filled, 0);
+            filled -= full;
+            filled += read_fully(&mut reader, &mut window[filled..])?;
+            chunk += 1;
+        }
+    }
+}
+
+// Truncation Check (payload chunks the file holds when it ends exactly after one,
+// which is where a cut on a chunk boundary or just before the trailer leaves it)
+fn ends_on_payload_chunk(
+    file: &mut File,
+    trailer: &SealedTrailer,
+    archive_size: u64,
+) -> Option<u64> {
+    let ciphertext = archive_size.checked_sub(trailer.payload_start)?;
+    let chunks = ciphertext.div_ceil(trailer.sealed_chunk);
+    let last_len = match ciphertext % trailer.sealed_chunk {
+        0 => trailer.sealed_chunk,
+        short => short,
+    };
+    if chunks == 0 || last_len < TAG_SIZE as u64 {
+        return None;
+    }
+    let mut sealed = vec![0u8; last_len as usize];
+    file.seek(SeekFrom::Start(archive_size - last_len)).ok()?;
+    file.read_exact(&mut sealed).ok()?;
+    // Header chunks are 0 and 1
+    let position = u32::try_from(chunks + 1).ok()?;
+    trailer
+        .cipher
+        .decrypt(position, false, trailer.payload(&sealed))?;
+    Some(chunks)
+}
+
+// Clear Prologue (optional magic + version and KDF parameters, then salt and nonce)
+struct Prologue {
+    version: u8,
+    flags: u8,
+    kdf: KdfParams,
+    chunk_size: usize,
+    /// Always ChaCha20-Poly1305 before format 11
+    cipher: Cipher,
+    salt: [u8; 16],
+    /// STREAM nonce prefix, Cipher::nonce_len bytes
+    nonce: Vec<u8>,
+    /// Hash of the archive key (format 10 on), checked before anything is decrypted
+    commitment: Option<[u8; 32]>,
+}
+
+// Short Prologue Error (files that end inside the prologue are no archive at all)
+fn too_short(_: std::io::Error) -> ArchiveError {
+    ArchiveError::NotAnArchive("File is too short to be an .rstf archive".to_string())
+}
+
+impl Prologue {
+    fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut start = [0u8; 4];
+        reader.read_exact(&mut start).map_err(too_short)?;
+
+        let mut salt = [0u8; 16];
+        let mut kdf = KdfParams::standard();
+        let mut chunk_size = CHUNK_SIZE;
+        let mut flags = 0;
+        let mut cipher = Cipher::ChaCha20;
+        let version = if &start == MAGIC {
+            let mut version = [0u8; 1];
+            reader.read_exact(&mut version).map_err(too_short)?;
+            if version[0] > FORMAT_VERSION {
+                return Err(ArchiveError::UnsupportedVersion(format!(
+                    "Archive uses format {} and requires a newer rstf",
+                    version[0]
+                ))
+                .into());
+            }
+            if version[0] >= 5 {
+                let mut flag_byte = [0u8; 1];
+                reader.read_exact(&mut flag_byte).map_err(too_short)?;
+                flags = flag_byte[0];
+                check_prologue_flags(flags)?;
+            }
+            if version[0] >= 11 {
+                let mut cipher_id = [0u8; 1];
+                reader.read_exact(&mut cipher_id).map_err(too_short)?;
+                cipher = Cipher::from_id(cipher_id[0])?;
+            }
+            if version[0] >= 3 {
+                let mut params = [0u8; KdfParams::LEN];
+                reader.read_exact(&mut params).map_err(too_short)?;
+                kdf = KdfParams::from_bytes(&params);
+            }
+            if version[0] >= 4 {
+                let mut chunk_log = [0u8; 1];
+                reader.read_exact(&mut chunk_log).map_err(too_short)?;
+                chunk_size = chunk_size_from_log(chunk_log[0])?;
+            }
+            reader.read_exact(&mut salt).map_err(too_short)?;
+            version[0]
+        } else {
+            // Format 1 has no magic; those four bytes already belong to the salt
+            salt[..4].copy_from_slice(&start);
+            reader.read_exact(&mut salt[4..]).map_err(too_short)?;
+            1
+        };
+
+        let mut nonce = vec![0u8; cipher.nonce_len()];
+        reader.read_exact(&mut nonce).map_err(too_short)?;
+        let commitment = if version >= 10 {
+            let mut commitment = [0u8; 32];
+            reader.read_exact(&mut commitment).map_err(too_short)?;
+            Some(commitment)
+        } else {
+            None
+        };
+        Ok(Prologue {
+            version,
+            flags,
+            kdf,
+            chunk_size,
+            cipher,
+            salt,
+            nonce,
+            commitment,
+        })
+    }
+
+    /// Refuses a key other than the one the archive was written with. ChaCha20-Poly1305
+    /// does not commit to its key, so without this a chunk could be crafted to
+    /// authenticate under two passwords; it also rejects a wrong password up front
+    fn check_key(&self, key: &[u8; 32]) -> Result<()> {
+        match self.commitment {
+            Some(commitment) if commitment != key_commitment(key) => {
+                Err(ArchiveError::WrongCredentials(
+                    "Wrong password or keyfile (the key does not match the archive's key commitment)"
+                        .to_string(),
+                )
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        prologue_len(self.version, self.cipher)
+    }
+
+    /// Encoding of a format 5 or newer prologue
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[self.version, self.flags]);
+        if self.version >= 11 {
+            bytes.push(self.cipher.id());
+        }
+        bytes.extend_from_slice(&self.kdf.to_bytes());
+        bytes.push(self.chunk_size.trailing_zeros() as u8);
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&self.nonce);
+        if let Some(commitment) = &self.commitment {
+            bytes.extend_from_slice(commitment);
+        }
+        bytes
+    }
+
+    /// Associated data of every sealed chunk: the whole prologue from format 9 on, so
+    /// changing any clear byte breaks authentication, and nothing before that
+    fn aad(&self) -> Vec<u8> {
+        if self.version >= 9 {
+            self.to_bytes()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn is_plaintext(&self) -> bool {
+        self.flags & FLAG_PLAINTEXT != 0
+    }
+}
+
+// Archive Encryption Probe (false when the prologue cannot be read; opening reports why)
+fn is_plaintext_archive(path: &Path) -> bool {
+    File::open(path)
+        .ok()
+        .and_then(|mut file| Prologue::read(&mut file).ok())
+        .is_some_and(|prologue| prologue.is_plaintext())
+}
+
+// Prologue Flags Check (bits from a newer rstf change how the rest must be read)
+fn check_prologue_flags(flags: u8) -> Result<()> {
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(ArchiveError::UnsupportedVersion(format!(
+            "Archive uses prologue flags {:#04x} and requires a newer rstf",
+            flags & !KNOWN_FLAGS
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+// Prologue Length Helper (bytes before the first sealed chunk)
+fn prologue_len(version: u8, cipher: Cipher) -> u64 {
+    let magic = match version {
+        0 | 1 => 0,
+        2 => MAGIC.len() + 1,
+        3 => MAGIC.len() + 1 + KdfParams::LEN,
+        4 => MAGIC.len() + 1 + KdfParams::LEN + 1,
+        // Formats 6 to 9 only changed what is inside the sealed chunks
+        5..=9 => MAGIC.len() + 2 + KdfParams::LEN + 1,
+        // The key commitment follows the nonce
+        10 => MAGIC.len() + 2 + KdfParams::LEN + 1 + 32,
+        // Then a cipher id after the flags
+        _ => MAGIC.len() + 3 + KdfParams::LEN + 1 + 32,
+    };
+    (magic + 16 + cipher.nonce_len()) as u64
+}
+
+// Key Commitment (hash of the archive key, stored in the clear prologue)
+fn key_commitment(key: &[u8; 32]) -> [u8; 32] {
+    blake3::derive_key(KEY_COMMITMENT_CONTEXT, key)
+}
+
+// Chunk Size Helper (prologue byte to size, refusing sizes outside the format bounds)
+fn chunk_size_from_log(chunk_log: u8) -> Result<usize> {
+    if !(MIN_CHUNK_LOG..=MAX_CHUNK_LOG).contains(&chunk_log) {
+        return Err(anyhow!(
+            "Archive uses a chunk size of 2^{} bytes, outside the supported 2^{} to 2^{}",
+            chunk_log,
+            MIN_CHUNK_LOG,
+            MAX_CHUNK_LOG
+        ));
+    }
+    Ok(1 << chunk_log)
+}
+
+// Decryptor Setup Helper (reader positioned just after the nonce)
+fn decrypting_reader<R: Read>(
+    inner: R,
+    key: &[u8; 32],
+    prologue: &Prologue,
+) -> Result<DecryptedReader<R>> {
+    let cipher = ChunkCipher::new(prologue, key)?;
+    let mut reader = DecryptedReader::new(inner, cipher, prologue.chunk_size, prologue.aad());
+    reader.file_offset = prologue.len();
+    Ok(reader)
+}
+
+// Unpack Function
+fn unpack(args: UnpackArgs) -> Result<()> {
+    check_xattrs_support(args.xattrs)?;
+    check_acls_support(args.acls);
+    let archives = collect_archive_paths(&args.inputs)?;
+    let to_stdout = args.stdout || args.dest == Path::new("-");
+
+    if archives.len() > 1 && (to_stdout || args.rename.is_some()) {
+        return Err(anyhow!(
+            "--stdout and --as can only be used with a single archive"
+        ));
+    }
+    if archives.len() > 1 && args.salvage {
+        return Err(anyhow!("--salvage works on one archive at a time"));
+    }
+    if let Some(name) = &args.rename {
+        validate_output_name(name)?;
+    }
+    if args
+        .entry
+        .iter()
+        .any(|e| normalize_entry_path(e).is_empty())
+    {
+        return Err(anyhow!("--entry needs a path inside the archive"));
+    }
+    let filter = PathFilter::new(&args.include, &args.exclude)?;
+    if !args.conflict_suffix.contains("{n}") || args.conflict_suffix.contains(['/', '\\']) {
+        return Err(anyhow!(
+            "--conflict-suffix must contain {{n}} and no path separators"
+        ));
+    }
+
+    let credentials = Credentials::for_archives(&archives, args.keyfile.as_deref())?;
+
+    if args.sandbox {
+        let writable = if to_stdout || args.dry_run {
+            None
+        } else {
+            fs::create_dir_all(&args.dest).context("Failed to create destination directory")?;
+            Some(args.dest.as_path())
+        };
+        let mut readable: Vec<&Path> = archives.iter().map(PathBuf::as_path).collect();
+        readable.extend(args.keyfile.as_deref());
+        readable.extend(args.dict.as_deref());
+        enter_sandbox(writable, &readable)?;
+    }
+
+    if let [archive] = archives.as_slice() {
+        if args.salvage {
+            let damage = DamageLog::default();
+            let outcome = unpack_archive(archive, &credentials, &args, &filter, Some(&damage));
+            return finish_salvage(&damage, outcome);
+        }
+        unpack_archive(archive, &credentials, &args, &filter, None)
+            .map_err(|e| diagnose_damage(archive, e))?;
+        return remove_unpacked_archive(archive, &args);
+    }
+
+    // Try the shared credentials on every archive and report at the end
+    let mut failures = Vec::new();
+    for archive in &archives {
+        println!("\n== {} ==", archive.display());
+        match unpack_archive(archive, &credentials, &args, &filter, None)
+            .map_err(|e| diagnose_damage(archive, e))
+        {
+            Ok(()) => {
+                if let Err(e) = remove_unpacked_archive(archive, &args) {
+                    eprintln!("Warning: {:#}", e);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                failures.push((archive, e));
+            }
+        }
+    }
+
+    println!(
+        "\nUnpacked {} of {} archives",
+        archives.len() - failures.len(),
+        archives.len()
+    );
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    let (auth_failures, other_failures): (Vec<_>, Vec<_>) = failures.iter().partition(|(_, e)| {
+        matches!(
+            ArchiveError::classify(e),
+            Some(ArchiveError::WrongCredentials(_))
+        )
+    });
+    if !auth_failures.is_empty() {
+        println!("Wrong password or keyfile for (retry individually):");
+        for (archive, _) in &auth_failures {
+            println!("  {}", archive.display());
+        }
+    }
+    if !other_failures.is_empty() {
+        println!("Failed:");
+        for (archive, e) in &other_failures {
+            println!("  {}: {}", archive.display(), e);
+        }
+    }
+    // The exit status is that of the first failure, so a lone bad archive in a batch
+    // still reads as truncated, corrupted or a wrong password
+    let summary = format!("{} archive(s) failed to unpack", failures.len());
+    match ArchiveError::classify(&failures[0].1) {
+        Some(class) => Err(class.with_message(summary).into()),
+        None => Err(anyhow!(summary)),
+    }
+}
+
+// Exit status 3 tells scripts that unpack --salvage left damaged regions in the output
+const EXIT_SALVAGED: i32 = 3;
+
+// Salvage Report (unpack --salvage): an undamaged archive finishes as usual; otherwise the
+// chunks read as zeros are listed and the exit status marks the output incomplete, even if
+// extraction then failed, since that failure follows from the damage
+fn finish_salvage(damage: &DamageLog, outcome: Result<()>) -> Result<()> {
+    let damaged = damage.lock().map(|d| d.clone()).unwrap_or_default();
+    if damaged.is_empty() {
+        return outcome;
+    }
+    if let Err(e) = &outcome {
+        eprintln!("Extraction ended with an error: {:#}", e);
+    }
+    let lost: u64 = damaged.iter().map(|d| d.len).sum();
+    eprintln!(
+        "\nSalvaged: {} chunk(s) failed authentication, {} of the stream read as zeros",
+        damaged.len(),
+        HumanBytes(lost)
+    );
+    eprintln!("  {:>10}  {:>16}  {:>10}", "CHUNK", "FILE OFFSET", "BYTES");
+    for chunk in &damaged {
+        eprintln!(
+            "  {:>10}  {:>16}  {:>10}",
+            chunk.chunk, chunk.file_offset, chunk.len
+        );
+    }
+    eprintln!(
+        "The output is incomplete: files stored over these regions hold zeros or are missing"
+    );
+    std::process::exit(EXIT_SALVAGED);
+}
+
+// Extraction Sandbox (--sandbox): Landlock allows writes only below the destination and reads
+// only of the archives, seccomp refuses exec, network, mount and module syscalls
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+fn enter_sandbox(writable: Option<&Path>, readable: &[&Path]) -> Result<()> {
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr,
+        RulesetStatus, ABI,
+    };
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+
+    let abi = ABI::V5;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(readable, AccessFs::from_read(abi)))
+        })
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(writable, AccessFs::from_all(abi)))
+        })
+        .and_then(|ruleset| ruleset.restrict_self())
+        .context("Failed to set up the Landlock sandbox")?;
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => {}
+        RulesetStatus::NotEnforced => {
+            eprintln!("Warning: this kernel does not support Landlock, files are not confined")
+        }
+        _ => eprintln!("Warning: this kernel only partly supports Landlock, confinement is weaker"),
+    }
+
+    let blocked = [
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_writev,
+        libc::SYS_socket,
+        libc::SYS_connect,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_kexec_load,
+        libc::SYS_bpf,
+        libc::SYS_keyctl,
+    ];
+    // Syscall numbers are `c_long`, which is only `i64` on 64-bit targets
+    #[allow(clippy::useless_conversion)]
+    let filter = SeccompFilter::new(
+        blocked
+            .into_iter()
+            .map(|nr| (i64::from(nr), Vec::new()))
+            .collect(),
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        std::env::consts::ARCH
+            .try_into()
+            .context("Unsupported architecture for seccomp")?,
+    )
+    .context("Failed to build the seccomp filter")?;
+    let program: BpfProgram = filter
+        .try_into()
+        .context("Failed to compile the seccomp filter")?;
+    seccompiler::apply_filter(&program).context("Failed to install the seccomp filter")?;
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", feature = "sandbox")))]
+fn enter_sandbox(_writable: Option<&Path>, _readable: &[&Path]) -> Result<()> {
+    Err(anyhow!(
+        "--sandbox needs a Linux build with the `sandbox` feature (cargo build --features sandbox)"
+    ))
+}
+
+// Archive Removal (--remove-archive), only called after a complete extraction
+fn remove_unpacked_archive(archive: &Path, args: &UnpackArgs) -> Result<()> {
+    if !args.remove_archive {
+        return Ok(());
+    }
+
+    if !args.yes {
+        eprint!("Delete archive '{}'? (y/N): ", archive.display());
+        std::io::stderr().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin()
+            .read_line(&mut answer)
+            .context("Failed to read input")?;
+        if answer.trim().to_lowercase() != "y" {
+            eprintln!("Archive kept.");
+            return Ok(());
+        }
+    }
+
+    fs::remove_file(archive)
+        .with_context(|| format!("Failed to remove archive '{}'", archive.display()))?;
+    eprintln!("Removed archive {}", archive.display());
+    Ok(())
+}
+
+// Archive Stem Helper (`backup.rstf` -> `backup`)
+fn archive_stem(archive: &Path) -> String {
+    let stem = if is_rstf_path(archive) {
+        archive.with_extension("")
+    } else {
+        archive.to_path_buf()
+    };
+    archive_base_name(&stem)
+}
+
+// Archive Path Collection (directories expand to the .rstf files directly inside)
+fn collect_archive_paths(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut archives = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            archives.push(input.clone());
+            continue;
+        }
+        let mut found = fs::read_dir(input)
+            .with_context(|| format!("Failed to read directory '{}'", input.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file() && is_rstf_path(path))
+            .collect::<Vec<_>>();
+        if found.is_empty() {
+            return Err(anyhow!("No .rstf archives found in '{}'", input.display()));
+        }
+        found.sort();
+        archives.extend(found);
+    }
+    Ok(archives)
+}
+
+// Archive Errors (failures scripts can tell apart by exit status; anything else exits 1)
+#[derive(Debug)]
+enum ArchiveError {
+    /// The key is not the archive's: its key commitment or first header chunk disagrees
+    WrongCredentials(String),
+    /// The file ends before the stream's last chunk
+    Truncated(String),
+    /// A chunk after an intact start fails authentication, or the payload does not decode
+    Corrupted(String),
+    /// No RSTF magic, and nothing that opens as a magic-less archive either
+    NotAnArchive(String),
+    /// Written by a newer rstf (format, prologue flags, cipher, codec or header field)
+    UnsupportedVersion(String),
+}
+
+impl ArchiveError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            ArchiveError::WrongCredentials(_) => 4,
+            ArchiveError::Truncated(_) => 5,
+            ArchiveError::Corrupted(_) => 6,
+            ArchiveError::NotAnArchive(_) => 7,
+            ArchiveError::UnsupportedVersion(_) => 8,
+        }
+    }
+
+    /// The same class of failure, described by `message`
+    fn with_message(&self, message: String) -> ArchiveError {
+        match self {
+            ArchiveError::WrongCredentials(_) => ArchiveError::WrongCredentials(message),
+            ArchiveError::Truncated(_) => ArchiveError::Truncated(message),
+            ArchiveError::Corrupted(_) => ArchiveError::Corrupted(message),
+            ArchiveError::NotAnArchive(_) => ArchiveError::NotAnArchive(message),
+            ArchiveError::UnsupportedVersion(_) => ArchiveError::UnsupportedVersion(message),
+        }
+    }
+
+    /// Carried inside an io::Error, for failures that surface through Read
+    fn into_io(self, kind: std::io::ErrorKind) -> std::io::Error {
+        std::io::Error::new(kind, self)
+    }
+
+    fn of_io(error: &std::io::Error) -> Option<&ArchiveError> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// The archive failure behind `error`, if it is one; the outermost wins
+    fn classify(error: &anyhow::Error) -> Option<&ArchiveError> {
+        error.chain().find_map(|cause| {
+            cause
+                .downcast_ref::<ArchiveError>()
+                .or_else(|| ArchiveError::of_io(cause.downcast_ref()?))
+        })
+    }
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveError::WrongCredentials(message)
+            | ArchiveError::Truncated(message)
+            | ArchiveError::Corrupted(message)
+            | ArchiveError::NotAnArchive(message)
+            | ArchiveError::UnsupportedVersion(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+// Header Chunk Error (the first chunk failing to open means a wrong key, unless the key
+// commitment already vouched for the key, in which case the chunk is damaged)
+fn header_chunk_error(error: std::io::Error, prologue: &Prologue) -> anyhow::Error {
+    match ArchiveError::of_io(&error) {
+        Some(ArchiveError::Corrupted(_)) if prologue.commitment.is_some() => anyhow!(error)
+            .context("Failed to decrypt header (the key is right, the header is damaged)"),
+        Some(ArchiveError::Corrupted(message)) => ArchiveError::WrongCredentials(format!(
+            "Failed to decrypt header (Wrong password or Wrong Keyfile?): {}",
+            message
+        ))
+        .into(),
+        _ => anyhow!(error).context("Failed to decrypt header"),
+    }
+}
+
+// Authentication Failure Check (MAC errors surface as InvalidData)
+fn is_auth_failure(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::InvalidData)
+    })
+}
+
+// Damage Diagnosis (an authentication failure in an archive that no longer matches its
+// checksum footer is damage, not a wrong password, and is no longer reported as one)
+fn diagnose_damage(archive: &Path, error: anyhow::Error) -> anyhow::Error {
+    if !is_auth_failure(&error)
+        || matches!(
+            ArchiveError::classify(&error),
+            Some(ArchiveError::WrongCredentials(_))
+        )
+    {
+        return error;
+    }
+    eprintln!(
+        "Checking '{}' against its checksum footer...",
+        archive.display()
+    );
+    match check_footer(archive, &ProgressBar::hidden()) {
+        Ok(FooterCheck::Damaged { .. }) => ArchiveError::Corrupted(format!(
+            "'{}' is damaged: it no longer matches its checksum footer, so the password is not the problem ({:#})",
+            archive.display(),
+            error
+        ))
+        .into(),
+        _ => error,
+    }
+}
+
+// Entry Decoder (seeks through the chunk index when the archive has one naming every
+// wanted path, and says whether it did; otherwise decoding starts at the top)
+fn entry_decoder(
+    mut crypto_reader: ArchiveReader,
+    header: &RstfHeader,
+    wanted: &[String],
+) -> Result<(PayloadDecoder<ArchiveReader>, bool)> {
+    let target = header
+        .index
+        .as_ref()
+        .filter(|_| header.is_dir && !wanted.is_empty())
+        .and_then(|index| Some((index, index.first_entry(wanted)?)));
+    let Some((index, offset)) = target else {
+        return Ok((PayloadDecoder::new(crypto_reader, header)?, false));
+    };
+    let (frame, packed) = index.frame_before(offset);
+    crypto_reader.seek_payload(index, packed, index.packed_len)?;
+    let mut decoder = PayloadDecoder::new(crypto_reader, header)?;
+    let skip = offset - frame;
+    if std::io::copy(&mut (&mut decoder).take(skip), &mut std::io::sink())? < skip {
+        return Err(anyhow!("Chunk index points past the end of the payload"));
+    }
+    Ok((decoder, true))
+}
+
+// Single Archive Unpack
+fn unpack_archive(
+    input_path: &Path,
+    credentials: &Credentials,
+    args: &UnpackArgs,
+    filter: &PathFilter,
+    damage: Option<&DamageLog>,
+) -> Result<()> {
+    let to_stdout = args.stdout || args.dest == Path::new("-");
+    let policy = args.conflict_policy();
+
+    let options = OpenArchiveOptions {
+        allow_trailing: args.allow_trailing,
+        salvage: damage,
+    };
+    let (mut header, crypto_reader) = open_archive_with(input_path, options, |prologue| {
+        if let Some(limit) = args.memory_limit {
+            check_kdf_memory(&prologue.kdf, limit)?;
+        }
+        credentials.derive_key(&prologue.salt, &prologue.kdf)
+    })?;
+    attach_dictionary(&mut header, args.dict.as_deref())?;
+    if let Some(limit) = args.memory_limit {
+        let window_log = decoder_window_log(limit)?;
+        if args.verbose > 0 {
+            eprintln!(
+                "Memory limit {}: zstd windows up to {}",
+                HumanBytes(limit),
+                HumanBytes(1 << window_log)
+            );
+        }
+        header.window_log_limit = Some(window_log);
+    }
+    let (max_size, max_entries) = args.limits(&header);
+
+    let is_multi = !header.members.is_empty();
+    if is_multi && args.rename.is_some() {
+        return Err(anyhow!(
+            "--as cannot be used with archives of several inputs"
+        ));
+    }
+    let selective = !args.entry.is_empty() || filter.is_active() || args.strip_components > 0;
+    if selective && !header.is_dir {
+        return Err(anyhow!(
+            "--entry, --include, --exclude and --strip-components only apply to directory archives (this one holds the single file '{}')",
+            header.original_name
+        ));
+    }
+    let wanted: Vec<String> = args.entry.iter().map(|e| normalize_entry_path(e)).collect();
+    // Salvage reads the whole payload, so damage anywhere is listed
+    let seek_to = if to_stdout || damage.is_some() {
+        &[][..]
+    } else {
+        &wanted[..]
+    };
+    let (decoder, seeked) = entry_decoder(crypto_reader, &header, seek_to)?;
+    if seeked && args.verbose > 0 {
+        eprintln!("Seeking to the selected entries through the chunk index");
+    }
+    // Stored payloads stay aligned around the zeros of a damaged chunk by themselves
+    let decoder: Box<dyn Read> = match damage {
+        Some(log) if header.codec()? != Compression::None => {
+            Box::new(SalvageDecoder::new(decoder, &header, log))
+        }
+        _ => Box::new(decoder),
+    };
+    // Everything extracted is hashed on the way and checked against the stored digest
+    // (unless only part of the payload is read)
+    let mut payload_reader = HashingReader::new(decoder);
+
+    if to_stdout {
+        eprintln!("Unpacking: {} -> <stdout>", header.original_name);
+        let pb = ProgressBar::new(header.original_size);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})")?,
+        );
+        let mut stdout = pb.wrap_write(std::io::stdout().lock());
+        // Holes of a sparse file come out as the zeros they stand for
+        std::io::copy(
+            &mut HoleFiller::new(&mut payload_reader, &header),
+            &mut stdout,
+        )
+        .context("Failed to write to stdout")?;
+        stdout.flush()?;
+        pb.finish_and_clear();
+        return payload_reader
+            .verify(header.trailer.as_ref())
+            .context("The data written to stdout does not match what was packed");
+    }
+    if !args.dry_run && !args.no_space_check && !selective {
+        check_free_space(&args.dest, &header)?;
+    }
+
+    let output_name = match &args.rename {
+        Some(name) => name.clone(),
+        None => sanitize_stored_name(&header.original_name)?,
+    };
+    // A stored name that had to be cleaned up re-roots directory entries like --as does
+    let rename = args
+        .rename
+        .as_deref()
+        .or((output_name != header.original_name).then_some(output_name.as_str()));
+
+    let dest = if args.mkdir && header.is_dir {
+        let dir = args.dest.join(archive_stem(input_path));
+        if dir.exists() && policy == ConflictPolicy::Abort {
+            return Err(anyhow!(
+                "'{}' already exists (use --force to extract into it)",
+                dir.display()
+            ));
+        }
+        dir
+    } else {
+        args.dest.clone()
+    };
+
+    if !args.dry_run {
+        fs::create_dir_all(&dest).context("Failed to create destination directory")?;
+    }
+    // Multi-input and contents-only archives extract their entries straight into `dest`
+    let output_path =
+        if is_multi || args.strip_components > 0 || (header.contents_only && args.rename.is_none())
+        {
+            dest.clone()
+        } else {
+            dest.join(&output_name)
+        };
+
+    println!(
+        "Unpacking: {} -> {}",
+        header.original_name,
+        output_path.display()
+    );
+
+    let pb = ProgressBar::new(header.original_size);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes} ({bytes_per_sec})")?
+        .progress_chars("#>-"));
+    if args.dry_run {
+        pb.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+    }
+    let mut prompt = match args.interactive {
+        true if std::io::stdin().is_terminal() => Some(CollisionPrompt::default()),
+        true => {
+            eprintln!(
+                "Warning: --interactive needs a terminal, existing files will not be overwritten"
+            );
+            None
+        }
+        false => None,
+    };
+
+    if header.is_dir {
+        let extract_error = |e: std::io::Error| {
+            if e.kind() == std::io::ErrorKind::AlreadyExists {
+                anyhow!("{} (already exists, use --force to overwrite)", e)
+            } else {
+                anyhow!(e).context("Failed to extract tar")
+            }
+        };
+
+        let root_name = match rename {
+            Some(_) if header.contents_only || is_multi => None,
+            other => other,
+        };
+        let target_dir = if header.contents_only {
+            &output_path
+        } else {
+            &dest
+        };
+        let plan = Extraction {
+            dest: target_dir,
+            root_name,
+            strip: args.strip_components,
+            wanted: &wanted,
+            filter,
+            dry_run: args.dry_run,
+            policy,
+            suffix: &args.conflict_suffix,
+            allow_unsafe_paths: args.allow_unsafe_paths,
+            symlinks: match (args.keep_symlinks, args.skip_symlinks) {
+                (true, _) => SymlinkPolicy::Keep,
+                (_, true) => SymlinkPolicy::Skip,
+                _ => SymlinkPolicy::Refuse,
+            },
+            max_size,
+            max_entries,
+            keep_partial: args.keeps_partial(),
+            preserve_mtimes: !args.no_preserve_mtimes,
+            preserve_permissions: args.preserve_permissions,
+            salvage: damage.is_some(),
+            mode_mask: args.mode_mask(),
+            numeric_owner: args.numeric_owner,
+            xattrs: args.xattrs,
+            acls: args.acls,
+            progress: &pb,
+            verbosity: args.verbose,
+        };
+        let mut tar_stream = TarResync::new(&mut payload_reader, damage, header.index.as_ref());
+        let selection =
+            extract_entries(&mut tar_stream, &plan, prompt.as_mut()).map_err(extract_error)?;
+        if !selection.missing.is_empty() {
+            return Err(anyhow!(
+                "Not found in archive: {}",
+                selection.missing.join(", ")
+            ));
+        }
+        if !seeked && !selection.stopped_early {
+            payload_reader
+                .verify(header.trailer.as_ref())
+                .with_context(|| {
+                    format!(
+                        "The entries extracted to {} do not match what was packed",
+                        output_path.display()
+                    )
+                })?;
+        }
+        if args.dry_run {
+            print_dry_run_summary(&selection, &output_path, policy);
+            return Ok(());
+        }
+        pb.finish_with_message("Done!");
+        println!(
+            "Extracted {} entries to {}",
+            selection.written,
+            output_path.display()
+        );
+        if let Some(prompt) = prompt.filter(|p| p.skipped > 0) {
+            println!("Kept {} existing path(s)", prompt.skipped);
+        }
+        print_renamed(&selection.renamed);
+        if selection.skipped_links > 0 {
+            println!("Skipped {} symlink(s)", selection.skipped_links);
+        }
+        if selection.skipped_special > 0 {
+            println!(
+                "Skipped {} special file(s) (device nodes need root, FIFOs need Unix)",
+                selection.skipped_special
+            );
+        }
+        print_failures(
+            "extended attribute(s) refused by the destination",
+            &selection.xattr_failures,
+        );
+        print_failures(
+            "entries whose ACLs could not be restored",
+            &selection.acl_failures,
+        );
+        if selection.unowned > 0 {
+            println!(
+                "Ownership of {} entries not restored (only root can change it)",
+                selection.unowned
+            );
+        }
+    } else if args.dry_run {
+        let size = copy_limited(
+            &mut HoleFiller::new(&mut payload_reader, &header),
+            &mut std::io::sink(),
+            max_size,
+        )?;
+        payload_reader.verify(header.trailer.as_ref())?;
+        let exists = output_path.exists();
+        print_dry_run_entry(&output_path, size, false, exists, policy);
+        let selection = Selection {
+            written: 1,
+            total_size: size,
+            conflicts: exists as u64,
+            ..Default::default()
+        };
+        print_dry_run_summary(&selection, &output_path, policy);
+        return Ok(());
+    } else {
+        let mut force = policy == ConflictPolicy::Overwrite;
+        if let Some(prompt) = prompt.as_mut().filter(|_| output_path.exists()) {
+            if prompt.ask(&output_path)? == Resolution::Skip {
+                println!("Kept existing {}", output_path.display());
+                return Ok(());
+            }
+            force = true;
+        }
+        // An existing file is only replaced once the new one is complete
+        let staged = force && fs::symlink_metadata(&output_path).is_ok();
+        let (mut output_file, partial_path) = if policy == ConflictPolicy::Rename {
+            create_renamed(&output_path, &args.conflict_suffix)
+                .context("Failed to create output file")?
+        } else if staged {
+            let name = output_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let staging = output_path.with_file_name(format!(".{}.rstf-partial", name));
+            create_renamed(&staging, ".{n}").context("Failed to create staging file")?
+        } else {
+            let file = create_output_file(&output_path, false, "--force")?;
+            (file, output_path.clone())
+        };
+        let copied = match &header.extents {
+            Some(extents) => write_sparse(
+                &mut payload_reader,
+                &mut output_file,
+                &header,
+                extents,
+                max_size,
+                &pb,
+            ),
+            None => copy_limited(
+                &mut payload_reader,
+                &mut pb.wrap_write(&mut output_file),
+                max_size,
+            ),
+        }
+        // A file that fails the digest check is treated like an incomplete one
+        .and_then(|written| {
+            payload_reader.verify(header.trailer.as_ref())?;
+            Ok(written)
+        });
+        let written = match copied {
+            Ok(written) => written,
+            Err(e) => {
+                drop(output_file);
+                pb.finish_and_clear();
+                if args.keeps_partial() {
+                    eprintln!("Warning: kept incomplete output {}", partial_path.display());
+                } else {
+                    let _ = fs::remove_file(&partial_path);
+                    eprintln!("Removed incomplete output {}", partial_path.display());
+                }
+                return Err(e);
+            }
+        };
+        drop(output_file);
+        let written_path = if staged {
+            fs::rename(&partial_path, &output_path)
+                .context("Failed to replace the existing file")?;
+            output_path.clone()
+        } else {
+            partial_path
+        };
+        let unowned = match &header.owner {
+            Some(owner) => !restore_owner(&written_path, owner, args.numeric_owner)
+                .context("Failed to restore the file owner")?,
+            None => false,
+        };
+        apply_file_metadata(&written_path, &header, args)?;
+        if header.original_size > 0 && written != header.original_size {
+            eprintln!(
+                "Warning: extracted {} bytes but the header recorded {}",
+                written, header.original_size
+            );
+        }
+
+        pb.finish_with_message("Done!");
+        println!("Extracted to {}", written_path.display());
+        if let Some(owner) = header.owner.as_ref().filter(|_| unowned) {
+            println!(
+                "Ownership {}:{} not restored (only root can change it)",
+                owner.uid, owner.gid
+            );
+        }
+        if written_path != output_path {
+            print_renamed(&[(output_path, written_path)]);
+        }
+    }
+    Ok(())
+}
+
+// Free Space Check (extracted size against what the destination filesystem has left)
+fn check_free_space(dest: &Path, header: &RstfHeader) -> Result<()> {
+    // Streamed packs record no size up front, only the payload size in the trailer
+    let needed = match (header.original_size, &header.trailer) {
+        (0, Some(trailer)) => trailer.payload_size,
+        (0, None) => {
+            println!("Note: the archive does not record its size, skipping the free space check");
+            return Ok(());
+        }
+        // Holes of a sparse file take no space
+        (size, _) => match &header.extents {
+            Some(extents) => extents.iter().map(|(_, len)| len).sum(),
+            None => size,
+        },
+    };
+    // The destination may not exist yet; its nearest existing ancestor shares the filesystem
+    let existing = dest
+        .ancestors()
+        .find(|p| !p.as_os_str().is_empty() && p.exists())
+        .unwrap_or(Path::new("."));
+    let available = match fs4::available_space(existing) {
+        Ok(available) => available,
+        Err(e) => {
+            eprintln!(
+                "Warning: could not check free space in {}: {}",
+                existing.display(),
+                e
+            );
+            return Ok(());
+        }
+    };
+    if needed > available {
+        return Err(anyhow!(
+            "Not enough free space in {}: extracting needs {} but only {} is available (use --no-space-check to try anyway)",
+            existing.display(),
+            HumanBytes(needed),
+            HumanBytes(available)
+        ));
+    }
+    Ok(())
+}
+
+// List Function
+fn list(args: ListArgs) -> Result<()> {
+    let filter = PathFilter::new(&args.include, &args.exclude)?;
+
+    let credentials =
+        Credentials::for_archives(std::slice::from_ref(&args.input), args.keyfile.as_deref())?;
+    let (mut header, crypto_reader) = open_archive(&args.input, &credentials)?;
+    attach_dictionary(&mut header, args.dict.as_deref())?;
+
+    let archive_size = fs::metadata(&args.input)
+        .context("Failed to read archive metadata")?
+        .len();
+    let compressed_size = compressed_payload_size(archive_size, &header);
+    if args.json {
+        return list_json(
+            &header,
+            archive_size,
+            compressed_size,
+            crypto_reader,
+            &args,
+            &filter,
+        );
+    }
+
+    println!("\n[RSTF INFO]");
+    if header.plaintext {
+        println!("*** NOT ENCRYPTED: packed with --no-encrypt, anyone can read it ***");
+    }
+    println!("Name : {}", header.original_name);
+    let kind = if !header.members.is_empty() {
+        "Multiple"
+    } else if header.contents_only {
+        "Directory (contents only)"
+    } else if header.is_dir {
+        "Directory"
+    } else {
+        "File"
+    };
+    println!("Type : {}", kind);
+    println!("Codec: {}", header.codec_summary());
+    let size_summary = format_ratio(header.original_size, compressed_size);
+    if args.long {
+        println!(
+            "Size : {} ({} → {} bytes)",
+            size_summary, header.original_size, compressed_size
+        );
+    } else {
+        println!("Size : {}", size_summary);
+    }
+    if header.created_at > 0 {
+        println!(
+            "Created: {} (rstf {})",
+            format_unix_time(header.created_at, "%Y-%m-%d %H:%M:%S"),
+            header.tool_version
+        );
+    }
+    if !header.comment.is_empty() {
+        println!("Comment:");
+        for line in header.comment.lines() {
+            println!("  {}", line);
+        }
+    }
+    if let Some(extents) = &header.extents {
+        let data: u64 = extents.iter().map(|(_, len)| len).sum();
+        println!(
+            "Sparse : {} of data in {} extent(s)",
+            HumanBytes(data),
+            extents.len()
+        );
+    }
+    if let Some(trailer) = &header.trailer {
+        println!("Digest : {}", trailer.digest_string());
+    }
+    if header.is_dir && header.file_count + header.dir_count > 0 {
+        println!(
+            "Entries: {} files, {} dirs",
+            HumanCount(header.file_count),
+            HumanCount(header.dir_count)
+        );
+    }
+    if !header.members.is_empty() {
+        println!("Members ({}):", header.members.len());
+        for member in &header.members {
+            println!("  {}", member);
+        }
+    }
+
+    if header.is_dir {
+        let payload_reader = PayloadDecoder::new(crypto_reader, &header)?;
+        println!("Contents:");
+        let summary = list_entries(payload_reader, &args, &filter, |entry| {
+            let suffix = if entry.is_dir { "/" } else { "" };
+            match &entry.columns {
+                Some(columns) => println!("{}  {}{}", columns, entry.path, suffix),
+                None => println!("{:>14}  {}{}", entry.size, entry.path, suffix),
+            }
+            Ok(())
+        })?;
+
+        print!(
+            "{} entries, {} total",
+            summary.matched,
+            HumanBytes(summary.total_size)
+        );
+        if summary.shown < summary.matched {
+            print!(" ({} shown)", summary.shown);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+struct ListedEntry {
+    path: String,
+    is_dir: bool,
+    size: u64,
+    mtime: u64,
+    /// Preformatted --long columns
+    columns: Option<String>,
+}
+
+struct ListingSummary {
+    matched: u64,
+    shown: u64,
+    total_size: u64,
+}
+
+// Tar Entry Listing (streams in archive order unless --sort asks for buffering)
+fn list_entries<R: Read>(
+    reader: R,
+    args: &ListArgs,
+    filter: &PathFilter,
+    mut emit: impl FnMut(&ListedEntry) -> Result<()>,
+) -> Result<ListingSummary> {
+    let mut archive = tar::Archive::new(reader);
+    let limit = args.limit.unwrap_or(usize::MAX);
+    let mut summary = ListingSummary {
+        matched: 0,
+        shown: 0,
+        total_size: 0,
+    };
+    let mut buffered = Vec::new();
+
+    for entry in archive
+        .entries()
+        .context("Failed to read archive contents")?
+    {
+        let entry = entry.context("Failed to read archive entry")?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let path = path.trim_end_matches('/').to_string();
+        if !filter.matches(&path) {
+            continue;
+        }
+
+        let listed = ListedEntry {
+            path,
+            is_dir: entry.header().entry_type().is_dir(),
+            size: entry.header().size()?,
+            mtime: entry.header().mtime()?,
+            columns: if args.long {
+                Some(format_entry_metadata(entry.header())?)
+            } else {
+                None
+            },
+        };
+        summary.matched += 1;
+        summary.total_size += listed.size;
 
-    let pb = ProgressBar::new(header.original_size);
-    pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes} ({bytes_per_sec})")?
-        .progress_chars("#>-"));
+        match args.sort {
+            Some(key) => {
+                buffered.push(listed);
+                // With --limit only the current top entries need to be kept
+                if buffered.len() >= limit.saturating_mul(2).max(4096) {
+                    sort_listing(&mut buffered, key, args.reverse);
+                    buffered.truncate(limit);
+                }
+            }
+            None if (summary.shown as usize) < limit => {
+                emit(&listed)?;
+                summary.shown += 1;
+            }
+            None => {}
+        }
+    }
+
+    // Tar stops at its end marker; read on so every chunk gets authenticated
+    let mut reader = archive.into_inner();
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+
+    if let Some(key) = args.sort {
+        sort_listing(&mut buffered, key, args.reverse);
+        for listed in buffered.iter().take(limit) {
+            emit(listed)?;
+            summary.shown += 1;
+        }
+    }
+    Ok(summary)
+}
+
+// Listing Sort Helper (stable, so ties keep archive order)
+fn sort_listing(entries: &mut [ListedEntry], key: SortKey, reverse: bool) {
+    entries.sort_by(|a, b| {
+        let order = match key {
+            SortKey::Name => a.path.cmp(&b.path),
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Mtime => a.mtime.cmp(&b.mtime),
+        };
+        if reverse {
+            order.reverse()
+        } else {
+            order
+        }
+    });
+}
+
+// JSON Listing (entries are written as they are listed)
+fn list_json<R: Read>(
+    header: &RstfHeader,
+    archive_size: u64,
+    compressed_size: u64,
+    reader: R,
+    args: &ListArgs,
+    filter: &PathFilter,
+) -> Result<()> {
+    #[derive(Serialize)]
+    struct JsonEntry<'a> {
+        path: &'a str,
+        is_dir: bool,
+        size: u64,
+        mtime: u64,
+    }
+
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    write!(
+        out,
+        "{{\"original_name\":{},\"is_dir\":{},\"original_size\":{},\"compressed_size\":{},\"archive_size\":{},\"file_count\":{},\"dir_count\":{},\"created_at\":{},\"tool_version\":{},\"comment\":{},\"members\":{},\"compression\":{},\"level\":{},\"encrypted\":{},\"digest\":{}",
+        serde_json::to_string(&header.original_name)?,
+        header.is_dir,
+        header.original_size,
+        compressed_size,
+        archive_size,
+        header.file_count,
+        header.dir_count,
+        header.created_at,
+        serde_json::to_string(&header.tool_version)?,
+        serde_json::to_string(&header.comment)?,
+        serde_json::to_string(&header.members)?,
+        serde_json::to_string(&header.codec_name())?,
+        serde_json::to_string(&header.level)?,
+        !header.plaintext,
+        serde_json::to_string(&header.trailer.as_ref().map(Trailer::digest_string))?
+    )?;
 
     if header.is_dir {
-        let mut archive = tar::Archive::new(&mut zstd_reader);
-        archive.unpack(".").context("Failed to extract tar")?;
+        write!(out, ",\"entries\":[")?;
+        let mut first = true;
+        list_entries(
+            PayloadDecoder::new(reader, header)?,
+            args,
+            filter,
+            |entry| {
+                if !first {
+                    write!(out, ",")?;
+                }
+                first = false;
+                let json_entry = JsonEntry {
+                    path: &entry.path,
+                    is_dir: entry.is_dir,
+                    size: entry.size,
+                    mtime: entry.mtime,
+                };
+                serde_json::to_writer(&mut out, &json_entry)?;
+                Ok(())
+            },
+        )?;
+        write!(out, "]")?;
+    }
+
+    writeln!(out, "}}")?;
+    out.flush()?;
+    Ok(())
+}
+
+// Long Listing Columns (`drwxr-xr-x  user/group  size  mtime`, like `tar -tv`)
+fn format_entry_metadata(header: &tar::Header) -> Result<String> {
+    let kind = header.entry_type();
+    let type_char = if kind.is_dir() {
+        'd'
+    } else if kind.is_symlink() {
+        'l'
+    } else if kind.is_hard_link() {
+        'h'
+    } else if kind.is_character_special() {
+        'c'
+    } else if kind.is_block_special() {
+        'b'
+    } else if kind.is_fifo() {
+        'p'
+    } else {
+        '-'
+    };
+
+    let mode = header.mode()?;
+    let mut mode_str = String::with_capacity(10);
+    mode_str.push(type_char);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        mode_str.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        mode_str.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        mode_str.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+
+    let user = match header.username() {
+        Ok(Some(name)) if !name.is_empty() => name.to_string(),
+        _ => header.uid()?.to_string(),
+    };
+    let group = match header.groupname() {
+        Ok(Some(name)) if !name.is_empty() => name.to_string(),
+        _ => header.gid()?.to_string(),
+    };
+
+    let mtime = format_unix_time(header.mtime()?, "%Y-%m-%d %H:%M");
+
+    Ok(format!(
+        "{}  {:<17}  {:>14}  {:<16}",
+        mode_str,
+        format!("{}/{}", user, group),
+        header.size()?,
+        mtime
+    ))
+}
+
+// Info Function (clear prologue first, then the authenticated header fields)
+fn info(input_path: PathBuf, keyfile: Option<PathBuf>, clear_only: bool) -> Result<()> {
+    let mut input_file = File::open(&input_path).context("Failed to open .rstf")?;
+    let archive_size = input_file
+        .metadata()
+        .context("Failed to read archive metadata")?
+        .len();
+    let prologue = Prologue::read(&mut input_file)?;
+    let params = prologue.kdf;
+
+    println!("\n[RSTF CONTAINER]");
+    println!(
+        "File        : {} ({} bytes)",
+        input_path.display(),
+        archive_size
+    );
+    if prologue.version >= 2 {
+        println!("Format      : {}", prologue.version);
+    } else {
+        println!("Format      : 1 (no version prologue)");
+    }
+    if prologue.version >= 5 {
+        println!("Flags       : {:#04x}", prologue.flags);
+    }
+    if prologue.is_plaintext() {
+        println!("Encryption  : NONE (packed with --no-encrypt, anyone can read it)");
+    }
+    if prologue.is_plaintext() {
+        println!("KDF         : not used (no password)");
+    } else {
+        println!(
+            "KDF         : Argon2id v19, {} KiB memory, {} iterations, {} lane(s)",
+            params.m_cost, params.t_cost, params.p_cost
+        );
+    }
+    println!("Salt        : {}", to_hex(&prologue.salt));
+    if prologue.is_plaintext() {
+        println!("Cipher      : none (chunks carry BLAKE3 checksums)");
+    } else {
+        println!(
+            "Cipher      : {} (STREAM, 32-bit big-endian counter)",
+            prologue.cipher.name()
+        );
+    }
+    if prologue.version >= 9 {
+        println!("Prologue    : authenticated with every chunk");
+    }
+    println!("Nonce       : {}", to_hex(&prologue.nonce));
+    if let Some(commitment) = &prologue.commitment {
+        println!("Key commit  : {} (BLAKE3)", to_hex(commitment));
+    }
+    if prologue.version >= 2 {
+        println!(
+            "Chunk size  : {} (header sealed in its own chunks)",
+            HumanBytes(prologue.chunk_size as u64)
+        );
+    } else {
+        let payload_size = archive_size - prologue.len();
+        println!(
+            "Chunk size  : {} ({} chunk(s))",
+            HumanBytes(CHUNK_SIZE as u64),
+            payload_size.div_ceil((CHUNK_SIZE + TAG_SIZE) as u64)
+        );
+    }
+    if prologue.is_plaintext() {
+        println!("Compression : recorded in the header");
+    } else {
+        println!("Compression : recorded in the encrypted header");
+    }
+    println!("Keyfile     : not recorded");
+    if prologue.version >= 12 {
+        match ChecksumFooter::find(&mut input_file, archive_size)? {
+            Some(footer) => println!(
+                "Footer      : {} ({} bytes, `rstf check` verifies it)",
+                footer.digest_string(),
+                footer.len
+            ),
+            None => println!("Footer      : missing (the file is truncated or has data appended)"),
+        }
+    }
+
+    if clear_only {
+        return Ok(());
+    }
+
+    let credentials = if prologue.is_plaintext() {
+        Credentials::none()
+    } else {
+        Credentials::read(keyfile.as_deref(), false)?
+    };
+    let (header, _) = open_archive(&input_path, &credentials)?;
+
+    if header.plaintext {
+        println!("\n[HEADER]");
     } else {
-        let output_file = File::create(&header.original_name)?;
-        let mut output_with_pb = pb.wrap_write(output_file);
-        std::io::copy(&mut zstd_reader, &mut output_with_pb)?;
+        println!("\n[ENCRYPTED HEADER]");
+    }
+    println!("Header size : {} bytes", header.encoded_len);
+    if header.created_at > 0 {
+        println!(
+            "Created     : {}",
+            format_unix_time(header.created_at, "%Y-%m-%d %H:%M:%S %:z")
+        );
+        println!("Written by  : rstf {}", header.tool_version);
+    } else {
+        println!("Created     : not recorded");
+    }
+    println!("Compression : {}", header.codec_summary());
+    match (header.dict_id, &header.dictionary) {
+        (Some(id), Some(dictionary)) => println!(
+            "Dictionary  : {} (embedded, {})",
+            id,
+            HumanBytes(dictionary.len() as u64)
+        ),
+        (Some(id), None) => println!("Dictionary  : {} (external, pass it with --dict)", id),
+        (None, _) => {}
+    }
+    if let Some(window_log) = header.window_log {
+        println!(
+            "Long window : 2^{} ({} of memory to decompress)",
+            window_log,
+            HumanBytes(1u64.checked_shl(window_log).unwrap_or(u64::MAX))
+        );
+    }
+    match &header.trailer {
+        Some(trailer) => println!("Digest      : {}", trailer.digest_string()),
+        None => println!("Digest      : not recorded"),
+    }
+    if let (Some(span), Some(index)) = (header.index_span, &header.index) {
+        println!(
+            "Index       : {} frames of {}, {} entries (unpack --entry and cat seek)",
+            index.frames.len(),
+            HumanBytes(span),
+            index.entries.len()
+        );
+    }
+    if header.padded {
+        println!("Padding     : Padmé (the file size only reveals a range)");
     }
 
-    pb.finish_with_message("Done!");
     Ok(())
 }
 
-// List Function
-fn list(input_path: PathBuf, keyfile: Option<PathBuf>) -> Result<()> {
-    let mut input_file = File::open(&input_path)?;
+// Debug Header Function (reports how far parsing got on damaged files)
+fn debug_header(input_path: PathBuf, decrypt: bool, keyfile: Option<PathBuf>) -> Result<()> {
+    let mut input_file = File::open(&input_path).context("Failed to open .rstf")?;
+    let mut file_size = input_file
+        .metadata()
+        .context("Failed to read archive metadata")?
+        .len();
+
+    println!("\n[RSTF DEBUG]");
+    println!("File size     : {} bytes", file_size);
+
+    let mut start = [0u8; 4];
+    let got = read_fully(&mut input_file, &mut start)?;
     let mut salt = [0u8; 16];
-    let mut nonce = [0u8; 7];
-    input_file.read_exact(&mut salt)?;
-    input_file.read_exact(&mut nonce)?;
+    let mut offset = 0u64;
+    let mut kdf = KdfParams::standard();
+    let mut chunk_size = CHUNK_SIZE;
+    let mut flags = 0;
+    let mut cipher = Cipher::ChaCha20;
+    let version = if got == start.len() && &start == MAGIC {
+        println!("{:#010x}    : magic \"RSTF\"", offset);
+        let mut version = [0u8; 1];
+        if read_fully(&mut input_file, &mut version)? == 0 {
+            return Err(anyhow!(
+                "Parsing stopped at byte 4: file ends before the format version"
+            ));
+        }
+        println!("{:#010x}    : format version {}", 4, version[0]);
+        if version[0] > FORMAT_VERSION {
+            return Err(anyhow!(
+                "Parsing stopped at byte 5: format {} is newer than this rstf understands",
+                version[0]
+            ));
+        }
+        offset = 5;
+        if version[0] >= 5 {
+            let mut flag_byte = [0u8; 1];
+            if read_fully(&mut input_file, &mut flag_byte)? == 0 {
+                return Err(anyhow!(
+                    "Parsing stopped at byte 5: file ends before the prologue flags"
+                ));
+            }
+            flags = flag_byte[0];
+            println!("{:#010x}    : flags {:#04x}", offset, flags);
+            check_prologue_flags(flags).context("Parsing stopped at byte 5")?;
+            offset += 1;
+        }
+        if version[0] >= 11 {
+            let mut cipher_id = [0u8; 1];
+            if read_fully(&mut input_file, &mut cipher_id)? == 0 {
+                return Err(anyhow!(
+                    "Parsing stopped at byte {}: file ends before the cipher id",
+                    offset
+                ));
+            }
+            cipher = Cipher::from_id(cipher_id[0])
+                .with_context(|| format!("Parsing stopped at byte {}", offset))?;
+            println!(
+                "{:#010x}    : cipher {} ({})",
+                offset,
+                cipher_id[0],
+                cipher.name()
+            );
+            offset += 1;
+        }
+        if version[0] >= 3 {
+            let mut params = [0u8; KdfParams::LEN];
+            if read_fully(&mut input_file, &mut params)? < params.len() {
+                return Err(anyhow!(
+                    "Parsing stopped at byte {}: file ends inside the KDF parameters",
+                    offset
+                ));
+            }
+            kdf = KdfParams::from_bytes(&params);
+            println!(
+                "{:#010x}    : argon2 params {} KiB, {} pass(es), {} lane(s)",
+                offset, kdf.m_cost, kdf.t_cost, kdf.p_cost
+            );
+            offset += KdfParams::LEN as u64;
+        }
+        if version[0] >= 4 {
+            let mut chunk_log = [0u8; 1];
+            if read_fully(&mut input_file, &mut chunk_log)? == 0 {
+                return Err(anyhow!(
+                    "Parsing stopped at byte {}: file ends before the chunk size",
+                    offset
+                ));
+            }
+            chunk_size = chunk_size_from_log(chunk_log[0])
+                .with_context(|| format!("Parsing stopped at byte {}", offset))?;
+            println!(
+                "{:#010x}    : chunk size 2^{} ({} bytes)",
+                offset, chunk_log[0], chunk_size
+            );
+            offset += 1;
+        }
+        version[0]
+    } else {
+        println!("Prologue      : none (format 1 starts directly with the salt)");
+        salt[..got].copy_from_slice(&start[..got]);
+        1
+    };
+
+    // Format 1 already consumed the first salt bytes while looking for the magic
+    let salt_prefix = if version >= 2 { 0 } else { got };
+    let mut nonce = vec![0u8; cipher.nonce_len()];
+    let got = salt_prefix + read_fully(&mut input_file, &mut salt[salt_prefix..])?;
+    println!(
+        "{:#010x}    : salt  ({}/16 bytes) {}",
+        offset,
+        got,
+        to_hex(&salt[..got])
+    );
+    if got < salt.len() {
+        return Err(anyhow!(
+            "Parsing stopped at byte {}: file ends inside the salt",
+            offset + got as u64
+        ));
+    }
+    offset += 16;
+    let got = read_fully(&mut input_file, &mut nonce)?;
+    println!(
+        "{:#010x}    : nonce ({}/{} bytes) {}",
+        offset,
+        got,
+        nonce.len(),
+        to_hex(&nonce[..got])
+    );
+    if got < nonce.len() {
+        return Err(anyhow!(
+            "Parsing stopped at byte {}: file ends inside the nonce",
+            offset + got as u64
+        ));
+    }
+    offset += nonce.len() as u64;
+    let mut commitment = None;
+    if version >= 10 {
+        let mut stored = [0u8; 32];
+        let got = read_fully(&mut input_file, &mut stored)?;
+        println!(
+            "{:#010x}    : key commitment ({}/32 bytes) {}",
+            offset,
+            got,
+            to_hex(&stored[..got])
+        );
+        if got < stored.len() {
+            return Err(anyhow!(
+                "Parsing stopped at byte {}: file ends inside the key commitment",
+                offset + got as u64
+            ));
+        }
+        commitment = Some(stored);
+        offset += 32;
+    }
+    if version >= 12 {
+        // Everything below is framed against the end of the archive, before the footer
+        let resume = input_file.stream_position()?;
+        match ChecksumFooter::find(&mut input_file, file_size) {
+            Ok(Some(footer)) => {
+                file_size -= footer.len;
+                println!(
+                    "{:#010x}    : checksum footer ({} bytes) {}",
+                    file_size,
+                    footer.len,
+                    footer.digest_string()
+                );
+            }
+            Ok(None) => println!("Warning       : no checksum footer at the end of the file"),
+            Err(e) => println!("Warning       : {:#}", e),
+        }
+        input_file.seek(SeekFrom::Start(resume))?;
+    }
 
-    let key = process_credentials(&salt, keyfile)?;
+    let tag = TAG_SIZE as u64;
+    if version < 2 {
+        print_chunk_framing(offset, file_size - offset, chunk_size)?;
+    } else {
+        println!(
+            "{:#010x}    : header length chunk ({} bytes)",
+            offset,
+            4 + tag
+        );
+        if file_size < offset + 4 + tag {
+            return Err(anyhow!(
+                "Parsing stopped at byte {}: file ends inside the header length chunk",
+                file_size
+            ));
+        }
+        if !decrypt {
+            println!(
+                "Header chunk  : follows at {:#010x}; its size needs --decrypt",
+                offset + 4 + tag
+            );
+            return Ok(());
+        }
+    }
 
-    let key_struct = chacha20poly1305::Key::from_slice(&key);
-    let aead = ChaCha20Poly1305::new(key_struct);
+    if !decrypt {
+        return Ok(());
+    }
 
-    let s_nonce = TipeNonce::from_slice(&nonce);
-    let decryptor = DecryptorBE32::from_aead(aead, s_nonce);
-    let mut crypto_reader = DecryptedReader::new(input_file, decryptor);
+    let prologue = Prologue {
+        version,
+        flags,
+        kdf,
+        chunk_size,
+        cipher,
+        salt,
+        nonce,
+        commitment,
+    };
+    let key = if prologue.is_plaintext() {
+        [0u8; 32]
+    } else {
+        cipher.check_available()?;
+        let credentials = Credentials::read(keyfile.as_deref(), false)?;
+        credentials.derive_key(&salt, &kdf)?
+    };
+    if commitment.is_some() {
+        prologue
+            .check_key(&key)
+            .context("Parsing stopped at the key commitment")?;
+        println!("Key commitment: matches the derived key");
+    }
+    let mut crypto_reader = decrypting_reader(input_file, &key, &prologue)?;
+    let wrong_key =
+        "Parsing stopped in the first chunk (wrong password, wrong keyfile or corrupt data)";
 
     let mut len_bytes = [0u8; 4];
-    crypto_reader
-        .read_exact(&mut len_bytes)
-        .context("Decrypt failed (Wrong password or keyfile?)")?;
-    let header_len = u32::from_le_bytes(len_bytes) as usize;
-    let mut header_data = vec![0u8; header_len];
-    crypto_reader.read_exact(&mut header_data)?;
-    let header: RstfHeader = bincode::deserialize(&header_data)?;
+    if version >= 2 {
+        let chunk = crypto_reader.read_chunk(4 + TAG_SIZE).context(wrong_key)?;
+        len_bytes.copy_from_slice(&chunk);
+    } else {
+        crypto_reader
+            .read_exact(&mut len_bytes)
+            .context(wrong_key)?;
+    }
+    let header_len = u32::from_le_bytes(len_bytes) as u64;
+    println!(
+        "Header length : {} bytes (u32 LE {})",
+        header_len,
+        to_hex(&len_bytes)
+    );
 
-    println!("\n[RSTF INFO]");
-    println!("Name : {}", header.original_name);
+    let header_data = if version >= 2 {
+        let header_offset = offset + 4 + tag;
+        println!(
+            "{:#010x}    : header chunk ({} bytes)",
+            header_offset,
+            header_len + tag
+        );
+        if header_offset + header_len + tag > file_size {
+            return Err(anyhow!(
+                "Parsing stopped: header chunk runs past the end of the file"
+            ));
+        }
+        let header_data = crypto_reader
+            .read_chunk(header_len as usize + TAG_SIZE)
+            .context("Parsing stopped inside the header chunk")?;
+        let payload_offset = header_offset + header_len + tag;
+        let sealed_trailer = match RstfHeader::from_bytes(&header_data, version) {
+            Ok(header) if header.trailer_len > 0 => header.trailer_len as u64 + tag,
+            _ => 0,
+        };
+        print_chunk_framing(
+            payload_offset,
+            (file_size - payload_offset).saturating_sub(sealed_trailer),
+            chunk_size,
+        )?;
+        if sealed_trailer > 0 {
+            println!(
+                "{:#010x}    : trailer chunk ({} bytes){}",
+                file_size.saturating_sub(sealed_trailer),
+                sealed_trailer,
+                if version >= 8 {
+                    ", sealed as the last chunk"
+                } else {
+                    ""
+                }
+            );
+        }
+        header_data
+    } else {
+        let ciphertext = file_size - offset;
+        let plaintext = ciphertext - tag * ciphertext.div_ceil((CHUNK_SIZE + TAG_SIZE) as u64);
+        if header_len + 4 > plaintext {
+            return Err(anyhow!(
+                "Parsing stopped: header length exceeds the {} byte plaintext",
+                plaintext
+            ));
+        }
+        let mut header_data = vec![0u8; header_len as usize];
+        crypto_reader
+            .read_exact(&mut header_data)
+            .context("Parsing stopped inside the header")?;
+        header_data
+    };
+
+    let preview = &header_data[..header_data.len().min(64)];
+    println!(
+        "Header bytes  : {}{}",
+        to_hex(preview),
+        if preview.len() < header_data.len() {
+            "..."
+        } else {
+            ""
+        }
+    );
+    if version == 6 && header_data.len() >= 2 {
+        println!(
+            "Header version: {} (this rstf reads up to {})",
+            u16::from_le_bytes([header_data[0], header_data[1]]),
+            HEADER_VERSION
+        );
+    }
+    if version >= 7 {
+        let mut rest = header_data.as_slice();
+        while !rest.is_empty() {
+            let offset = header_data.len() - rest.len();
+            let (tag, value) = next_header_record(&mut rest)
+                .with_context(|| format!("Parsing stopped at header byte {}", offset))?;
+            println!(
+                "Header record : {:#06x} tag {:#04x}{}, {} byte(s)",
+                offset,
+                tag,
+                if tag & HEADER_CRITICAL != 0 {
+                    " (critical)"
+                } else {
+                    ""
+                },
+                value.len()
+            );
+        }
+    }
+    let header = RstfHeader::from_bytes(&header_data, version)
+        .context("Parsing stopped decoding the header")?;
+    println!("Header fields : {:#?}", header);
+
+    Ok(())
+}
+
+// Chunk Framing Report (payload chunks starting at `offset`)
+fn print_chunk_framing(offset: u64, ciphertext: u64, chunk_size: usize) -> Result<()> {
+    let sealed_chunk = (chunk_size + TAG_SIZE) as u64;
+    let full_chunks = ciphertext / sealed_chunk;
+    let final_chunk = ciphertext % sealed_chunk;
+    println!(
+        "{:#010x}    : payload ciphertext ({} bytes)",
+        offset, ciphertext
+    );
+    println!(
+        "Chunk framing : {} full chunk(s) of {} bytes, final chunk of {} bytes at {:#010x}",
+        full_chunks,
+        sealed_chunk,
+        final_chunk,
+        offset + full_chunks * sealed_chunk
+    );
+    if ciphertext == 0 {
+        return Err(anyhow!(
+            "Parsing stopped at byte {}: no payload ciphertext",
+            offset
+        ));
+    } else if final_chunk == 0 {
+        println!("Warning       : no short final chunk; the file may be cut on a chunk boundary");
+    } else if final_chunk < TAG_SIZE as u64 {
+        println!("Warning       : final chunk is shorter than its 16-byte tag (truncated)");
+    }
+    Ok(())
+}
+
+// Exhaustive Read Helper (returns how many bytes were available)
+fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// Unix Time Formatting Helper (local time, `-` when out of range)
+fn format_unix_time(secs: u64, format: &str) -> String {
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|t| t.with_timezone(&chrono::Local).format(format).to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// Hex Encoding Helper
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Checksum Function (stored digest, optionally re-verified against the payload)
+fn checksum(
+    input_path: PathBuf,
+    keyfile: Option<PathBuf>,
+    recompute: bool,
+    dict: Option<PathBuf>,
+) -> Result<()> {
+    let credentials =
+        Credentials::for_archives(std::slice::from_ref(&input_path), keyfile.as_deref())?;
+    let (mut header, crypto_reader) = open_archive(&input_path, &credentials)?;
+    attach_dictionary(&mut header, dict.as_deref())?;
+
+    let stored = header.trailer.clone();
+    match &stored {
+        Some(trailer) => println!("{}  {}", trailer.digest_string(), header.original_name),
+        None if !recompute => {
+            return Err(anyhow!(
+                "'{}' has no stored digest (use --recompute to hash its contents)",
+                input_path.display()
+            ))
+        }
+        None => {}
+    }
+    if !recompute {
+        return Ok(());
+    }
+
+    let pb = ProgressBar::new(header.original_size);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})")?,
+    );
+    let mut hasher = HashingWriter::new(std::io::sink());
+    let mut payload_reader = pb.wrap_read(PayloadDecoder::new(crypto_reader, &header)?);
+    std::io::copy(&mut payload_reader, &mut hasher).context("Failed to decrypt payload")?;
+    pb.finish_and_clear();
+    let (_, computed) = hasher.finish();
+
+    match stored {
+        Some(trailer) if trailer == computed => {
+            println!("Digest verified ({} bytes)", computed.payload_size);
+            Ok(())
+        }
+        Some(trailer) => Err(anyhow!(
+            "Digest mismatch: stored {} ({} bytes), computed {} ({} bytes)",
+            trailer.digest_string(),
+            trailer.payload_size,
+            computed.digest_string(),
+            computed.payload_size
+        )),
+        None => {
+            println!("{}  {}", computed.digest_string(), header.original_name);
+            Ok(())
+        }
+    }
+}
+
+// Check Function (media damage against the checksum footer, no password needed)
+fn check(inputs: &[PathBuf]) -> Result<()> {
+    let archives = collect_archive_paths(inputs)?;
+    let mut failed = 0;
+    for archive in &archives {
+        let pb = ProgressBar::new(0);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec})")?
+                .progress_chars("#>-"),
+        );
+        let result = check_footer(archive, &pb);
+        pb.finish_and_clear();
+        match result {
+            Ok(FooterCheck::Intact(footer)) => {
+                println!("{}: OK ({})", archive.display(), footer.digest_string());
+                continue;
+            }
+            Ok(FooterCheck::Damaged { stored, computed }) => println!(
+                "{}: DAMAGED (footer {}, contents hash to blake3:{})",
+                archive.display(),
+                stored.digest_string(),
+                to_hex(&computed)
+            ),
+            Ok(FooterCheck::Missing(reason)) => {
+                println!("{}: NOT CHECKED ({})", archive.display(), reason)
+            }
+            Err(e) => println!("{}: ERROR ({:#})", archive.display(), e),
+        }
+        failed += 1;
+    }
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} archive(s) failed the check",
+            failed,
+            archives.len()
+        ));
+    }
+    Ok(())
+}
+
+// What re-hashing an archive against its checksum footer found
+enum FooterCheck {
+    Intact(ChecksumFooter),
+    Damaged {
+        stored: ChecksumFooter,
+        computed: [u8; 32],
+    },
+    /// Why there is no footer to check against
+    Missing(String),
+}
+
+// Footer Verification (everything before the footer is re-hashed; the prologue is only
+// read to explain a missing footer, so a damaged one is still caught)
+fn check_footer(path: &Path, pb: &ProgressBar) -> Result<FooterCheck> {
+    let mut file = File::open(path).context("Failed to open .rstf")?;
+    let file_size = file
+        .metadata()
+        .context("Failed to read archive metadata")?
+        .len();
+    let Some(footer) = ChecksumFooter::find(&mut file, file_size)? else {
+        file.rewind()?;
+        let reason = match Prologue::read(&mut file) {
+            Ok(prologue) if prologue.version < 12 => format!(
+                "format {} archives have no checksum footer; `rstf checksum --recompute` checks them with the password",
+                prologue.version
+            ),
+            Ok(_) => "no checksum footer at the end; the file is truncated or has data appended".to_string(),
+            Err(e) => format!("{:#}", e),
+        };
+        return Ok(FooterCheck::Missing(reason));
+    };
+    if footer.algorithm != ChecksumFooter::BLAKE3 {
+        return Err(anyhow!(
+            "Unknown digest algorithm {} in checksum footer",
+            footer.algorithm
+        ));
+    }
+
+    let covered = file_size - footer.len;
+    pb.set_length(covered);
+    file.rewind()?;
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut pb.wrap_read(file.take(covered)), &mut hasher)
+        .context("Failed to read archive")?;
+    let computed = *hasher.finalize().as_bytes();
+    if computed == footer.digest {
+        Ok(FooterCheck::Intact(footer))
+    } else {
+        Ok(FooterCheck::Damaged {
+            stored: footer,
+            computed,
+        })
+    }
+}
+
+// Cat Function
+fn cat(
+    input_path: PathBuf,
+    entry: Option<String>,
+    keyfile: Option<PathBuf>,
+    dict: Option<PathBuf>,
+) -> Result<()> {
+    let credentials =
+        Credentials::for_archives(std::slice::from_ref(&input_path), keyfile.as_deref())?;
+    let (mut header, crypto_reader) = open_archive(&input_path, &credentials)?;
+    attach_dictionary(&mut header, dict.as_deref())?;
+    let mut stdout = std::io::stdout().lock();
+
+    if !header.is_dir {
+        let mut payload_reader = PayloadDecoder::new(crypto_reader, &header)?;
+        if let Some(entry) = entry.as_deref() {
+            if normalize_entry_path(entry) != header.original_name {
+                return Err(anyhow!(
+                    "Entry '{}' not found (archive contains only '{}')",
+                    entry,
+                    header.original_name
+                ));
+            }
+        }
+        std::io::copy(
+            &mut HoleFiller::new(&mut payload_reader, &header),
+            &mut stdout,
+        )
+        .context("Failed to write to stdout")?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    let wanted = match entry.as_deref() {
+        Some(entry) => normalize_entry_path(entry),
+        None => return Err(anyhow!("Directory archives require an entry path to cat")),
+    };
+
+    let (payload_reader, _) = entry_decoder(crypto_reader, &header, std::slice::from_ref(&wanted))?;
+    let Some(first) = cat_tar_entry(payload_reader, &wanted, &mut stdout)? else {
+        return Ok(());
+    };
+    // A hard link's data went by with its first occurrence, so read the archive again
+    let (_, crypto_reader) = open_archive(&input_path, &credentials)?;
+    let (payload_reader, _) = entry_decoder(crypto_reader, &header, std::slice::from_ref(&first))?;
+    match cat_tar_entry(payload_reader, &first, &mut stdout)? {
+        None => Ok(()),
+        Some(_) => Err(anyhow!("Entry '{}' links to another hard link", wanted)),
+    }
+}
+
+// Cat Entry Helper (returns the link target instead when the entry is a hard link)
+fn cat_tar_entry<R: Read>(
+    reader: R,
+    wanted: &str,
+    stdout: &mut impl Write,
+) -> Result<Option<String>> {
+    let mut archive = tar::Archive::new(reader);
+    for tar_entry in archive.entries()? {
+        let mut tar_entry = tar_entry?;
+        let path = tar_entry.path()?.to_string_lossy().to_string();
+        if normalize_entry_path(&path) != wanted {
+            continue;
+        }
+        if tar_entry.header().entry_type().is_hard_link() {
+            let first = tar_entry.link_name()?.unwrap_or_default();
+            return Ok(Some(normalize_entry_path(&first.to_string_lossy())));
+        }
+        if !tar_entry.header().entry_type().is_file() {
+            return Err(anyhow!("Entry '{}' is not a regular file", wanted));
+        }
+        std::io::copy(&mut tar_entry, stdout).context("Failed to write to stdout")?;
+        stdout.flush()?;
+        return Ok(None);
+    }
+
+    Err(anyhow!("Entry '{}' not found in archive", wanted))
+}
+
+// Entry Path Normalization (`./a//b/` and `a/b` refer to the same entry)
+fn normalize_entry_path(path: &str) -> String {
+    path.split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Samples longer than this only contribute their start to dictionary training
+const DICT_SAMPLE_LIMIT: u64 = 128 * 1024;
+
+// Repack Function (old payload decoded and re-encoded in one stream; nothing hits the disk)
+fn repack(args: RepackArgs) -> Result<()> {
+    let mut settings = PackArgs {
+        level: args.level,
+        preset: args.preset,
+        compression: args.compression,
+        store: args.store,
+        long: args.long,
+        dict: args.dict.clone(),
+        embed_dict: args.embed_dict,
+        threads: args.threads,
+        chunk_size: args.chunk_size,
+        cipher: args.cipher,
+        no_encrypt: args.no_encrypt,
+        low_memory: args.low_memory,
+        verify: args.verify,
+        quiet: args.quiet,
+        overwrite: args.overwrite,
+        ..Default::default()
+    };
+    resolve_codec_args(&mut settings)?;
+
+    let output_path = match &args.output {
+        Some(output) => {
+            let same = match (fs::canonicalize(output), fs::canonicalize(&args.input)) {
+                (Ok(output), Ok(input)) => output == input,
+                _ => false,
+            };
+            if same {
+                return Err(anyhow!(
+                    "--output is the archive being repacked; use --replace without --output instead"
+                ));
+            }
+            output.clone()
+        }
+        None => {
+            let mut temp = args.input.clone().into_os_string();
+            temp.push(".repack");
+            settings.overwrite = false;
+            PathBuf::from(temp)
+        }
+    };
+
+    // Both sides take the same credentials, so only a plaintext-to-plaintext repack skips them
+    let credentials = if args.no_encrypt {
+        Credentials::for_archives(std::slice::from_ref(&args.input), args.keyfile.as_deref())?
+    } else {
+        Credentials::read(args.keyfile.as_deref(), args.quiet)?
+    };
+    let (mut old, crypto_reader) = open_archive(&args.input, &credentials)?;
+    attach_dictionary(&mut old, args.old_dict.as_deref())?;
+    // Padding hides the size, so a repack keeps it
+    settings.pad = old.padded;
+
+    // Everything but the codec carries over; tool_version names the rstf that wrote it
+    let mut header = RstfHeader {
+        dictionary: None,
+        dict_id: None,
+        window_log: None,
+        level: None,
+        segmented: false,
+        class_levels: None,
+        trailer_len: Trailer::LEN as u32,
+        index_span: None,
+        index: None,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        ..old.clone()
+    };
+    record_codec(&mut header, &settings, None);
+
+    if !args.quiet {
+        println!(
+            "Repacking {} ({} → {})...",
+            args.input.display(),
+            old.codec_summary(),
+            header.codec_summary()
+        );
+    }
+    // Older archives without a trailer only know the size of the original input
+    let payload_size = old
+        .trailer
+        .as_ref()
+        .map_or(old.original_size, |trailer| trailer.payload_size);
+    let written = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let pb = ProgressBar::new(payload_size);
+    pb.set_style(ProgressStyle::default_bar()
+        .with_key("written", written_key(written.clone()))
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} decoded, {written} ({bytes_per_sec}, {eta})")?
+        .progress_chars("#>-"));
+    if args.quiet {
+        pb.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+    } else if !std::io::stderr().is_terminal() {
+        report_progress_lines(&pb, describe_bytes);
+    }
+
+    let job = PackJob {
+        inputs: Vec::new(),
+        header,
+        output_path,
+        from_stdin: false,
+        written,
+        progress: None,
+    };
+    let mut source = pb.wrap_read(PayloadDecoder::new(crypto_reader, &old)?);
+    let outcome = write_archive_from(&job, &credentials, &settings, &pb, Some(&mut source))?;
+    pb.finish_and_clear();
+
+    // The new final chunk has to open, and both archives must hold the same payload
+    let checked = open_archive(&job.output_path, &credentials).and_then(|(new, _)| {
+        match (&new.trailer, &old.trailer) {
+            (Some(new), _) if *new != outcome.digest => Err(anyhow!(
+                "The new archive's trailer does not match the payload written"
+            )),
+            (_, Some(old)) if *old != outcome.digest => Err(anyhow!(
+                "The payload decoded to {} but the old archive recorded {}",
+                outcome.digest.digest_string(),
+                old.digest_string()
+            )),
+            _ => Ok(()),
+        }
+    });
+    if let Err(e) = checked {
+        let _ = fs::remove_file(&job.output_path);
+        return Err(e.context(format!(
+            "Repacking '{}' failed; the old archive was left as it is",
+            args.input.display()
+        )));
+    }
+
+    let old_size = fs::metadata(&args.input)
+        .context("Failed to read archive metadata")?
+        .len();
+    let final_path = if args.output.is_none() {
+        fs::rename(&job.output_path, &args.input).with_context(|| {
+            format!(
+                "Failed to move '{}' over '{}'",
+                job.output_path.display(),
+                args.input.display()
+            )
+        })?;
+        args.input.clone()
+    } else {
+        if args.replace {
+            fs::remove_file(&args.input).with_context(|| {
+                format!(
+                    "Failed to delete the old archive '{}'",
+                    args.input.display()
+                )
+            })?;
+        }
+        job.output_path.clone()
+    };
+    if !args.quiet {
+        println!("Archive written to {}", final_path.display());
+        if args.verify {
+            println!("Verified: {}", outcome.digest.digest_string());
+        }
+        println!(
+            "Repacked: {} in {}",
+            format_ratio(old_size, outcome.archive_size),
+            format_elapsed(outcome.elapsed)
+        );
+    }
+    Ok(())
+}
+
+// Dictionary Training (train-dict): every regular file below the inputs is a sample
+fn train_dict(inputs: &[PathBuf], output: &Path, max_size: u64, overwrite: bool) -> Result<()> {
+    let mut samples = Vec::new();
+    for input in inputs {
+        let mut walker = ignore::WalkBuilder::new(input);
+        walker.standard_filters(false).sort_by_file_path(Path::cmp);
+        for entry in walker.build() {
+            let entry = entry.with_context(|| format!("Failed to walk '{}'", input.display()))?;
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let mut sample = Vec::new();
+            File::open(entry.path())
+                .and_then(|file| file.take(DICT_SAMPLE_LIMIT).read_to_end(&mut sample))
+                .with_context(|| format!("Failed to read '{}'", entry.path().display()))?;
+            if !sample.is_empty() {
+                samples.push(sample);
+            }
+        }
+    }
+    if samples.is_empty() {
+        return Err(anyhow!("No non-empty sample files found"));
+    }
+
+    let total: u64 = samples.iter().map(|s| s.len() as u64).sum();
     println!(
-        "Type : {}",
-        if header.is_dir { "Directory" } else { "File" }
+        "Training on {} sample(s), {}...",
+        HumanCount(samples.len() as u64),
+        HumanBytes(total)
     );
-    println!("Size : {}", HumanBytes(header.original_size));
+    let dictionary = zstd::dict::from_samples(&samples, max_size as usize).map_err(|e| {
+        anyhow!(
+            "Dictionary training failed: {} (it needs many small, similar samples)",
+            e
+        )
+    })?;
 
+    let mut file = create_output_file(output, overwrite, "--overwrite")?;
+    file.write_all(&dictionary)
+        .context("Failed to write dictionary")?;
+    println!(
+        "Dictionary {} ({}) written to {}",
+        dictionary_id(&dictionary),
+        HumanBytes(dictionary.len() as u64),
+        output.display()
+    );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests;
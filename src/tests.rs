@@ -0,0 +1,2547 @@
+use super::*;
+use std::cell::Cell;
+
+thread_local! {
+    // Password `Credentials::read` hands out in test builds
+    static PASSWORD: Cell<&'static str> = const { Cell::new("correct horse") };
+}
+
+pub(super) fn password() -> String {
+    PASSWORD.with(|p| p.get().to_string())
+}
+
+fn set_password(password: &'static str) {
+    PASSWORD.with(|p| p.set(password));
+}
+
+// Exit status `exit_with` unwinds with in test builds
+pub(super) struct Exit(pub(super) i32);
+
+fn exit_status(body: impl FnOnce() -> Result<()>) -> Result<Option<i32>> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(body)) {
+        Ok(result) => result.map(|()| None),
+        Err(payload) => match payload.downcast::<Exit>() {
+            Ok(exit) => Ok(Some(exit.0)),
+            Err(payload) => std::panic::resume_unwind(payload),
+        },
+    }
+}
+
+// Scratch Directory (removed again on drop)
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("rstf-test-{}-{}", std::process::id(), n));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Command Runner (parses `args` the way the binary would, then runs it)
+fn rstf<I, T>(args: I) -> Result<()>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = std::iter::once("rstf".into()).chain(args.into_iter().map(Into::into));
+    run(Cli::try_parse_from(args)?)
+}
+
+// Pack Arguments (parsed from a `pack` command line, inputs included)
+fn pack_args(args: &[&str]) -> PackArgs {
+    let args = ["rstf", "pack"].iter().chain(args);
+    match Cli::try_parse_from(args).unwrap().command {
+        Commands::Pack(args) => args,
+        _ => unreachable!(),
+    }
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+fn write(path: &Path, contents: impl AsRef<[u8]>) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, contents).unwrap();
+}
+
+// Sample Tree (a few files of different sizes in nested directories)
+fn sample_tree(root: &Path) {
+    write(&root.join("a.txt"), "alpha\n");
+    write(&root.join("sub/b.txt"), "bravo ".repeat(1000));
+    write(
+        &root.join("sub/deeper/c.bin"),
+        (0..70_000u32)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>(),
+    );
+    fs::create_dir_all(root.join("empty")).unwrap();
+}
+
+// Tree Listing (relative path and contents of every file, directories as None)
+fn tree_listing(root: &Path) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+    fn walk(root: &Path, dir: &Path, out: &mut Vec<(PathBuf, Option<Vec<u8>>)>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let relative = path.strip_prefix(root).unwrap().to_path_buf();
+            if path.is_dir() {
+                out.push((relative, None));
+                walk(root, &path, out);
+            } else {
+                out.push((relative, Some(fs::read(&path).unwrap())));
+            }
+        }
+    }
+    let mut out = Vec::new();
+    walk(root, root, &mut out);
+    out.sort();
+    out
+}
+
+// Archive Opener (header and payload reader, with the password of the running test)
+fn open(archive: &Path) -> Result<(RstfHeader, ArchiveReader)> {
+    open_archive(archive, &Credentials::read(None, true)?)
+}
+
+// Entry Headers (path and tar header of every entry in a directory archive, in order)
+fn entry_headers(archive: &Path) -> Vec<(String, tar::Header)> {
+    let (header, reader) = open(archive).unwrap();
+    let mut tar = tar::Archive::new(PayloadDecoder::new(reader, &header).unwrap());
+    tar.entries()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            (
+                path.trim_end_matches('/').to_string(),
+                entry.header().clone(),
+            )
+        })
+        .collect()
+}
+
+fn entry_paths(archive: &Path) -> Vec<String> {
+    entry_headers(archive)
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect()
+}
+
+// Raw Tar Builder (writes names as given, `..` and absolute paths included, unlike
+// tar::Builder)
+#[derive(Default)]
+struct RawTar(Vec<u8>);
+
+impl RawTar {
+    fn entry(&mut self, path: &str, kind: tar::EntryType, link: &str, data: &[u8]) -> &mut Self {
+        let mut header = tar::Header::new_gnu();
+        let old = header.as_old_mut();
+        old.name[..path.len()].copy_from_slice(path.as_bytes());
+        old.linkname[..link.len()].copy_from_slice(link.as_bytes());
+        header.set_entry_type(kind);
+        header.set_mode(if kind.is_dir() { 0o755 } else { 0o644 });
+        header.set_size(data.len() as u64);
+        header.set_mtime(1_700_000_000);
+        header.set_uid(unsafe { libc::getuid() } as u64);
+        header.set_gid(unsafe { libc::getgid() } as u64);
+        header.set_cksum();
+        self.0.extend_from_slice(header.as_bytes());
+        self.0.extend_from_slice(data);
+        self.0.resize(self.0.len().next_multiple_of(512), 0);
+        self
+    }
+
+    fn file(&mut self, path: &str, data: &str) -> &mut Self {
+        self.entry(path, tar::EntryType::Regular, "", data.as_bytes())
+    }
+
+    fn symlink(&mut self, path: &str, link: &str) -> &mut Self {
+        self.entry(path, tar::EntryType::Symlink, link, &[])
+    }
+
+    fn hard_link(&mut self, path: &str, link: &str) -> &mut Self {
+        self.entry(path, tar::EntryType::Link, link, &[])
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let mut bytes = std::mem::take(&mut self.0);
+        bytes.extend_from_slice(&[0; 1024]);
+        bytes
+    }
+}
+
+// Extraction Plan Runner (hands `body` a default unpack into `dest`)
+fn with_plan<T>(dest: &Path, body: impl FnOnce(&mut Extraction) -> T) -> T {
+    let filter = PathFilter::new(&[], &[]).unwrap();
+    let progress = ProgressBar::hidden();
+    let mut plan = Extraction {
+        dest,
+        root_name: None,
+        strip: 0,
+        wanted: &[],
+        filter: &filter,
+        dry_run: false,
+        policy: ConflictPolicy::Abort,
+        suffix: ".{n}",
+        allow_unsafe_paths: false,
+        symlinks: SymlinkPolicy::Refuse,
+        max_size: None,
+        max_entries: None,
+        keep_partial: false,
+        preserve_mtimes: true,
+        preserve_permissions: false,
+        salvage: false,
+        mode_mask: 0o755,
+        numeric_owner: false,
+        xattrs: false,
+        acls: false,
+        progress: &progress,
+        verbosity: 0,
+    };
+    body(&mut plan)
+}
+
+// Tar Extraction Runner (`tar` unpacked into `dest`, the plan adjusted by `configure`)
+fn extract_tar(
+    tar: &[u8],
+    dest: &Path,
+    configure: impl FnOnce(&mut Extraction),
+) -> std::io::Result<Selection> {
+    with_plan(dest, |plan| {
+        configure(plan);
+        extract_entries(&mut TarResync::new(tar, None, None), plan, None)
+    })
+}
+
+fn pack_input(path: &Path) -> PackInput {
+    PackInput {
+        path: path.to_path_buf(),
+        name: archive_base_name(path),
+        is_dir: path.is_dir(),
+        size: 0,
+        recursive: true,
+        entries: Vec::new(),
+        skips: WalkSkips::default(),
+    }
+}
+
+#[test]
+fn pack_refuses_to_overwrite_its_input() {
+    let dir = TempDir::new();
+    let input = dir.join("notes.txt");
+    write(&input, "notes");
+
+    assert!(check_pack_paths(&input, &input, false).is_err());
+    assert!(check_pack_paths(&input, &dir.join("notes.txt.rstf"), false).is_ok());
+
+    // A hard link is another name for the same inode
+    let link = dir.join("link.rstf");
+    fs::hard_link(&input, &link).unwrap();
+    assert!(check_pack_paths(&input, &link, false).is_err());
+
+    // Nor may a directory's archive land inside it
+    let tree = dir.join("tree");
+    fs::create_dir(&tree).unwrap();
+    assert!(check_pack_paths(&tree, &tree.join("tree.rstf"), true).is_err());
+    assert!(check_pack_paths(&tree, &dir.join("tree.rstf"), true).is_ok());
+}
+
+#[test]
+fn pack_asks_for_force_on_archives_whatever_the_case() {
+    let dir = TempDir::new();
+    let output = dir.join("out.rstf");
+    for name in ["old.rstf", "OLD.RSTF", "Old.Rstf"] {
+        let input = dir.join(name);
+        write(&input, "archive");
+        assert!(check_pack_input(&pack_input(&input), &output, false).is_err());
+        assert!(check_pack_input(&pack_input(&input), &output, true).is_ok());
+    }
+    let input = dir.join("old.rstf.txt");
+    write(&input, "text");
+    assert!(check_pack_input(&pack_input(&input), &output, false).is_ok());
+}
+
+#[test]
+fn archives_of_dot_dotdot_and_root_take_a_real_name() {
+    let cwd = std::env::current_dir().unwrap();
+    let name = |path: &Path| path.file_name().unwrap().to_string_lossy().to_string();
+
+    assert_eq!(archive_base_name(Path::new(".")), name(&cwd));
+    assert_eq!(
+        archive_base_name(Path::new("..")),
+        name(cwd.parent().unwrap())
+    );
+    assert_eq!(archive_base_name(Path::new("/")), "root");
+    assert_eq!(archive_base_name(Path::new("some/dir")), "dir");
+
+    // Archives of `.` and `..` go next to the directory they resolve to
+    assert_eq!(
+        derive_output_path(Path::new("."), None, None),
+        cwd.parent().unwrap().join(format!("{}.rstf", name(&cwd)))
+    );
+    let parent = cwd.parent().unwrap();
+    assert_eq!(
+        derive_output_path(Path::new(".."), None, None),
+        parent
+            .parent()
+            .unwrap()
+            .join(format!("{}.rstf", name(parent)))
+    );
+    assert_eq!(
+        derive_output_path(Path::new("/"), None, Some("2024")),
+        PathBuf::from("root-2024.rstf")
+    );
+    assert_eq!(
+        derive_output_path(Path::new("some/dir"), None, None),
+        PathBuf::from("some/dir.rstf")
+    );
+
+    let dir = TempDir::new();
+    assert_eq!(
+        derive_output_path(Path::new("."), Some(dir.path()), None),
+        dir.join(format!("{}.rstf", name(&cwd)))
+    );
+}
+
+#[test]
+fn trailing_separators_name_the_same_archive() {
+    for (raw, normalized) in [
+        ("dir/", "dir"),
+        ("dir/.", "dir"),
+        ("./dir//", "./dir"),
+        ("a/./b/", "a/b"),
+        ("./", "."),
+        ("/", "/"),
+    ] {
+        assert_eq!(normalize_input_path(Path::new(raw)), Path::new(normalized));
+    }
+
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    sample_tree(&tree);
+    let with_slash = format!("{}/", tree.display());
+    rstf(["pack", &with_slash, "--quiet"]).unwrap();
+    let dotted = dir.join("dotted.rstf");
+    rstf([
+        "pack",
+        path_str(&dir.join("tree/.")),
+        "-o",
+        path_str(&dotted),
+        "--quiet",
+    ])
+    .unwrap();
+
+    // `tree/` lands next to the directory as tree.rstf, not inside it
+    assert!(!tree.join(".rstf").exists());
+    let (slashed, _) = open(&dir.join("tree.rstf")).unwrap();
+    let (dotted, _) = open(&dotted).unwrap();
+    for header in [&slashed, &dotted] {
+        assert_eq!(header.original_name, "tree");
+        assert!(header.is_dir);
+        assert_eq!((header.file_count, header.dir_count), (3, 4));
+    }
+    assert_eq!(slashed.original_size, dotted.original_size);
+}
+
+#[test]
+fn remove_archive_deletes_file_and_directory_archives_once_extracted() {
+    let dir = TempDir::new();
+    write(&dir.join("single.txt"), "one file");
+    sample_tree(&dir.join("tree"));
+    let out = dir.join("out");
+    for name in ["single.txt", "tree"] {
+        let input = dir.join(name);
+        rstf(["pack", path_str(&input), "--no-encrypt", "--quiet"]).unwrap();
+        let archive = dir.join(format!("{}.rstf", name));
+        assert!(archive.exists());
+
+        // Without --remove-archive the archive stays
+        rstf(["unpack", path_str(&archive), "-C", path_str(&out)]).unwrap();
+        assert!(archive.exists());
+        fs::remove_dir_all(&out).unwrap();
+
+        rstf([
+            "unpack",
+            path_str(&archive),
+            "-C",
+            path_str(&out),
+            "--remove-archive",
+            "-y",
+        ])
+        .unwrap();
+        assert!(!archive.exists());
+        assert!(out.join(name).exists());
+        fs::remove_dir_all(&out).unwrap();
+    }
+}
+
+#[test]
+fn remove_archive_keeps_archives_that_failed_to_extract() {
+    let dir = TempDir::new();
+    let input = dir.join("single.txt");
+    write(&input, "one file");
+    rstf(["pack", path_str(&input), "--no-encrypt", "--quiet"]).unwrap();
+    let archive = dir.join("single.txt.rstf");
+
+    // single.txt is already there, so extraction aborts before finishing
+    let result = rstf([
+        "unpack",
+        path_str(&archive),
+        "-C",
+        path_str(dir.path()),
+        "--remove-archive",
+        "-y",
+    ]);
+    assert!(result.is_err());
+    assert!(archive.exists());
+}
+
+#[test]
+fn mkdir_keeps_archives_with_the_same_top_level_name_apart() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    sample_tree(&tree);
+    let (first, second) = (dir.join("first.rstf"), dir.join("second.rstf"));
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&first),
+        "--no-encrypt",
+        "--quiet",
+    ])
+    .unwrap();
+    let packed_first = tree_listing(&tree);
+    write(&tree.join("a.txt"), "changed\n");
+    write(&tree.join("second-only.txt"), "new");
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&second),
+        "--no-encrypt",
+        "--quiet",
+    ])
+    .unwrap();
+
+    let out = dir.join("out");
+    rstf([
+        "unpack",
+        path_str(&first),
+        path_str(&second),
+        "-C",
+        path_str(&out),
+        "--mkdir",
+    ])
+    .unwrap();
+    assert_eq!(tree_listing(&out.join("first/tree")), packed_first);
+    assert_eq!(tree_listing(&out.join("second/tree")), tree_listing(&tree));
+
+    // Both would be extracted to out/tree without it, and the second one clashes
+    let flat = dir.join("flat");
+    assert!(rstf([
+        "unpack",
+        path_str(&first),
+        path_str(&second),
+        "-C",
+        path_str(&flat)
+    ])
+    .is_err());
+    // An existing per-archive directory is not extracted into without --force
+    assert!(rstf(["unpack", path_str(&first), "-C", path_str(&out), "--mkdir"]).is_err());
+    rstf([
+        "unpack",
+        path_str(&first),
+        "-C",
+        path_str(&out),
+        "--mkdir",
+        "--force",
+    ])
+    .unwrap();
+}
+
+#[test]
+fn contents_only_drops_the_top_level_name() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    sample_tree(&tree);
+    let (named, bare) = (dir.join("named.rstf"), dir.join("bare.rstf"));
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&named),
+        "--no-encrypt",
+        "--quiet",
+    ])
+    .unwrap();
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&bare),
+        "--no-encrypt",
+        "--quiet",
+        "--contents-only",
+    ])
+    .unwrap();
+
+    let mut named_paths = entry_paths(&named);
+    let mut bare_paths = entry_paths(&bare);
+    named_paths.sort();
+    bare_paths.sort();
+    assert_eq!(
+        named_paths,
+        [
+            "tree",
+            "tree/a.txt",
+            "tree/empty",
+            "tree/sub",
+            "tree/sub/b.txt",
+            "tree/sub/deeper",
+            "tree/sub/deeper/c.bin"
+        ]
+    );
+    assert_eq!(
+        bare_paths,
+        [
+            "a.txt",
+            "empty",
+            "sub",
+            "sub/b.txt",
+            "sub/deeper",
+            "sub/deeper/c.bin"
+        ]
+    );
+    let (header, _) = open(&bare).unwrap();
+    assert!(header.contents_only);
+    assert_eq!((header.file_count, header.dir_count), (3, 3));
+
+    // The entries land straight in the destination, or under --as
+    let out = dir.join("out");
+    rstf(["unpack", path_str(&bare), "-C", path_str(&out)]).unwrap();
+    assert_eq!(tree_listing(&out), tree_listing(&tree));
+    rstf([
+        "unpack",
+        path_str(&bare),
+        "-C",
+        path_str(&out),
+        "--as",
+        "renamed",
+    ])
+    .unwrap();
+    assert_eq!(tree_listing(&out.join("renamed")), tree_listing(&tree));
+}
+
+#[test]
+fn stripped_target_refuses_absolute_and_parent_paths() {
+    let dest = Path::new("/dest");
+    let target = |path: &str, strip| stripped_target(dest, Path::new(path), strip);
+    assert_eq!(target("a/b", 0).unwrap(), Some(dest.join("a/b")));
+    assert_eq!(target("./a/./b", 0).unwrap(), Some(dest.join("a/b")));
+    assert_eq!(target("a/b/c", 2).unwrap(), Some(dest.join("c")));
+    assert_eq!(target("a/b", 2).unwrap(), None);
+    assert_eq!(target(".", 0).unwrap(), None);
+    for hostile in ["/etc/passwd", "../x", "a/../../x", "a/../b", "a/b/.."] {
+        for strip in [0, 1] {
+            assert!(target(hostile, strip).is_err(), "{} was accepted", hostile);
+        }
+    }
+}
+
+#[test]
+fn place_entry_keeps_entries_inside_the_destination() {
+    let dir = TempDir::new();
+    let dest = dir.join("dest");
+    fs::create_dir(&dest).unwrap();
+    let root = fs::canonicalize(&dest).unwrap();
+    let place = |plan: &Extraction, path: &str| place_entry(plan, Some(&root), Path::new(path));
+    with_plan(&dest, |plan| {
+        // --as swaps the first component, whatever it was
+        plan.root_name = Some("renamed");
+        assert_eq!(
+            place(plan, "tree/a.txt").unwrap(),
+            Some(dest.join("renamed/a.txt"))
+        );
+        assert_eq!(place(plan, "tree").unwrap(), Some(dest.join("renamed")));
+        for hostile in ["/etc/passwd", "../escape", "tree/../../escape"] {
+            assert!(place(plan, hostile).is_err(), "{} was accepted", hostile);
+        }
+
+        // A directory symlink already in the destination does not lead entries out of it
+        std::os::unix::fs::symlink(dir.path(), dest.join("out")).unwrap();
+        plan.root_name = None;
+        assert!(place(plan, "out/escape").is_err());
+
+        plan.allow_unsafe_paths = true;
+        assert_eq!(
+            place(plan, "../escape").unwrap(),
+            Some(dest.join("../escape"))
+        );
+    });
+}
+
+#[test]
+fn hostile_tar_entries_never_land_outside_the_destination() {
+    let dir = TempDir::new();
+    let dest = dir.join("dest");
+    let hostile = [
+        RawTar::default()
+            .file("ok.txt", "fine")
+            .file("../escape.txt", "out")
+            .finish(),
+        RawTar::default().file("a/../../escape.txt", "out").finish(),
+        RawTar::default()
+            .file("/tmp/rstf-absolute-escape.txt", "out")
+            .finish(),
+        RawTar::default()
+            .file("d/ok.txt", "fine")
+            .hard_link("d/link", "../escape.txt")
+            .finish(),
+    ];
+    write(&dir.join("escape.txt"), "untouched");
+    for (i, tar) in hostile.iter().enumerate() {
+        for strip in [0, 1] {
+            let result = extract_tar(tar, &dest, |plan| plan.strip = strip);
+            let error = result
+                .err()
+                .unwrap_or_else(|| panic!("hostile archive {} extracted with strip {}", i, strip));
+            assert!(error.to_string().contains("unsafe path"), "{}", error);
+            assert_eq!(
+                fs::read_to_string(dir.join("escape.txt")).unwrap(),
+                "untouched"
+            );
+            assert!(!Path::new("/tmp/rstf-absolute-escape.txt").exists());
+            // Whatever was written before the bad entry is removed again
+            assert!(!dest.join("ok.txt").exists() && !dest.join("d").exists());
+        }
+    }
+
+    let safe = RawTar::default().file("./a/./b.txt", "inside").finish();
+    extract_tar(&safe, &dest, |_| {}).unwrap();
+    assert_eq!(fs::read_to_string(dest.join("a/b.txt")).unwrap(), "inside");
+}
+
+#[test]
+fn link_stays_inside_resolves_from_the_link_directory() {
+    let dir = TempDir::new();
+    let dest = dir.path();
+    let inside =
+        |target: &str, link: &str| link_stays_inside(dest, &dest.join(target), Path::new(link));
+    assert!(inside("l", "a/b"));
+    assert!(inside("l", "./a/../b"));
+    assert!(inside("a/b/l", "../../c"));
+    assert!(!inside("l", "../x"));
+    assert!(!inside("a/l", "../../x"));
+    assert!(!inside("l", "a/../../x"));
+    assert!(!inside("l", "/etc/passwd"));
+    assert!(!link_stays_inside(
+        dest,
+        Path::new("/elsewhere/l"),
+        Path::new("a")
+    ));
+
+    // `..` may not climb back up through a symlink, which could lead anywhere
+    fs::create_dir(dest.join("real")).unwrap();
+    std::os::unix::fs::symlink("real", dest.join("via")).unwrap();
+    assert!(inside("real/l", "../x"));
+    assert!(!inside("via/l", "../x"));
+}
+
+#[test]
+fn nothing_is_written_through_an_extracted_symlink() {
+    let dir = TempDir::new();
+    let dest = dir.join("dest");
+
+    // The link itself points inside, but the entry after it writes through it
+    let through = RawTar::default()
+        .file("sub/keep.txt", "kept")
+        .symlink("link", "sub")
+        .file("link/keep.txt", "overwritten")
+        .finish();
+    let error = extract_tar(&through, &dest, |_| {}).err().unwrap();
+    assert!(
+        error.to_string().contains("extracted before it"),
+        "{}",
+        error
+    );
+    assert!(!dest.join("sub").exists() && !dest.join("link").exists());
+
+    // Even --keep-symlinks, which lets the link point out, does not write through it
+    let escaping = RawTar::default()
+        .symlink("out", "..")
+        .file("out/escape.txt", "out")
+        .finish();
+    let error = extract_tar(&escaping, &dest, |plan| plan.symlinks = SymlinkPolicy::Keep)
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("unsafe path"), "{}", error);
+    assert!(!dir.join("escape.txt").exists());
+
+    let error = extract_tar(&escaping, &dest, |_| {}).err().unwrap();
+    assert!(error.to_string().contains("points outside"), "{}", error);
+    assert!(!dir.join("escape.txt").exists());
+
+    // --skip-symlinks leaves the link out, so the file is written as a plain path
+    let selection =
+        extract_tar(&escaping, &dest, |plan| plan.symlinks = SymlinkPolicy::Skip).unwrap();
+    assert_eq!(selection.skipped_links, 1);
+    assert!(dest.join("out").is_dir());
+    assert_eq!(
+        fs::read_to_string(dest.join("out/escape.txt")).unwrap(),
+        "out"
+    );
+    assert!(!dir.join("escape.txt").exists());
+
+    // Links that stay inside are extracted as they are
+    let fs_dest = dir.join("links");
+    let inside = RawTar::default()
+        .file("sub/keep.txt", "kept")
+        .symlink("link", "sub/keep.txt")
+        .finish();
+    extract_tar(&inside, &fs_dest, |_| {}).unwrap();
+    assert_eq!(
+        fs::read_link(fs_dest.join("link")).unwrap(),
+        Path::new("sub/keep.txt")
+    );
+    assert_eq!(fs::read_to_string(fs_dest.join("link")).unwrap(), "kept");
+}
+
+#[cfg(not(all(target_os = "linux", feature = "sandbox")))]
+#[test]
+fn sandbox_needs_the_feature() {
+    let error = enter_sandbox(None, &[]).unwrap_err();
+    assert!(error.to_string().contains("`sandbox` feature"), "{}", error);
+    let dir = TempDir::new();
+    let input = dir.join("single.txt");
+    write(&input, "one file");
+    rstf(["pack", path_str(&input), "--no-encrypt", "--quiet"]).unwrap();
+    let archive = dir.join("single.txt.rstf");
+    let out = dir.join("out");
+    assert!(rstf([
+        "unpack",
+        path_str(&archive),
+        "-C",
+        path_str(&out),
+        "--sandbox"
+    ])
+    .is_err());
+    assert!(!out.join("single.txt").exists());
+}
+
+// Landlock and seccomp confine the calling thread and the threads it starts, so the
+// sandbox is entered on a thread of its own and the rest of the test run is unaffected
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+#[test]
+fn sandbox_confines_writes_reads_and_exec() {
+    let dir = TempDir::new();
+    let (dest, archives, elsewhere) = (
+        dir.join("dest"),
+        dir.join("archives"),
+        dir.join("elsewhere"),
+    );
+    for path in [&dest, &archives, &elsewhere] {
+        fs::create_dir(path).unwrap();
+    }
+    write(&archives.join("a.rstf"), "archive");
+    write(&elsewhere.join("secret"), "secret");
+
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                enter_sandbox(Some(&dest), &[&archives]).unwrap();
+
+                fs::write(dest.join("written"), "inside").unwrap();
+                assert_eq!(
+                    fs::read_to_string(archives.join("a.rstf")).unwrap(),
+                    "archive"
+                );
+                assert!(std::process::Command::new("/bin/true").status().is_err());
+
+                // Kernels without Landlock only get the seccomp half
+                let outside = fs::write(elsewhere.join("written"), "outside");
+                if outside.is_ok() {
+                    eprintln!("Landlock is not enforced on this kernel; skipping the file checks");
+                    return;
+                }
+                assert!(fs::read_to_string(elsewhere.join("secret")).is_err());
+                assert!(fs::write(archives.join("a.rstf"), "changed").is_err());
+            })
+            .join()
+            .unwrap();
+    });
+    assert_eq!(fs::read_to_string(dest.join("written")).unwrap(), "inside");
+}
+
+#[test]
+fn owner_names_travel_with_the_ids_unless_numeric_owner() {
+    use std::os::unix::fs::MetadataExt;
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    sample_tree(&tree);
+    let metadata = fs::metadata(tree.join("a.txt")).unwrap();
+    let (uid, gid) = (metadata.uid(), metadata.gid());
+    let (user, group) = (
+        user_name(uid).unwrap_or_default(),
+        group_name(gid).unwrap_or_default(),
+    );
+
+    let pack = |input: &Path, name: &str, extra: &[&str]| {
+        let archive = dir.join(name);
+        let mut args = vec![
+            "pack",
+            path_str(input),
+            "-o",
+            path_str(&archive),
+            "--no-encrypt",
+            "--quiet",
+        ];
+        args.extend(extra);
+        rstf(args).unwrap();
+        archive
+    };
+
+    // Directory entries carry the names in their tar headers
+    for (name, extra, named) in [
+        ("named.rstf", &[][..], true),
+        ("numeric.rstf", &["--numeric-owner"][..], false),
+        ("reproducible.rstf", &["--reproducible"][..], false),
+    ] {
+        let archive = pack(&tree, name, extra);
+        for (path, header) in entry_headers(&archive) {
+            let names = (
+                header.username().unwrap().unwrap_or_default(),
+                header.groupname().unwrap().unwrap_or_default(),
+            );
+            if named {
+                assert_eq!(names, (user.as_str(), group.as_str()), "{}", path);
+                assert_eq!(
+                    (header.uid().unwrap(), header.gid().unwrap()),
+                    (uid as u64, gid as u64)
+                );
+            } else {
+                assert_eq!(names, ("", ""), "{}", path);
+            }
+        }
+    }
+
+    // A single file's owner goes into the rstf header, through its encoding
+    let file = tree.join("a.txt");
+    let (header, _) = open(&pack(&file, "file.rstf", &[])).unwrap();
+    let owner = FileOwner {
+        uid,
+        gid,
+        user: user.clone(),
+        group: group.clone(),
+    };
+    assert_eq!(header.owner.as_ref(), Some(&owner));
+    let (header, _) = open(&pack(&file, "file-numeric.rstf", &["--numeric-owner"])).unwrap();
+    assert_eq!(
+        header.owner,
+        Some(FileOwner {
+            uid,
+            gid,
+            ..FileOwner::default()
+        })
+    );
+    let (header, _) = open(&pack(&file, "file-reproducible.rstf", &["--reproducible"])).unwrap();
+    assert_eq!(header.owner, None);
+
+    let mut raw = tar::Header::new_gnu();
+    raw.set_uid(uid as u64);
+    raw.set_gid(gid as u64);
+    set_owner_names(&mut raw, &metadata);
+    assert_eq!(tar_owner(&raw).unwrap(), owner);
+}
+
+#[test]
+fn restore_owner_prefers_names_over_ids() {
+    use std::os::unix::fs::MetadataExt;
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("not running as root; skipping the chown checks");
+        return;
+    }
+    let dir = TempDir::new();
+    let path = dir.join("owned");
+    write(&path, "owned");
+    let root = user_name(0).unwrap();
+    let root_group = group_name(0).unwrap();
+
+    let owner = FileOwner {
+        uid: 4242,
+        gid: 4343,
+        user: root.clone(),
+        group: root_group.clone(),
+    };
+    assert!(restore_owner(&path, &owner, true).unwrap());
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!((metadata.uid(), metadata.gid()), (4242, 4343));
+
+    // The name wins where it exists here; unknown names fall back to the ids
+    assert!(restore_owner(&path, &owner, false).unwrap());
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!((metadata.uid(), metadata.gid()), (0, 0));
+    let unknown = FileOwner {
+        user: "no-such-user-rstf".into(),
+        group: "no-such-group-rstf".into(),
+        ..owner
+    };
+    assert!(restore_owner(&path, &unknown, false).unwrap());
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!((metadata.uid(), metadata.gid()), (4242, 4343));
+}
+
+#[test]
+fn gitignore_walker_skips_ignored_paths() {
+    let dir = TempDir::new();
+    let repo = dir.join("repo");
+    fs::create_dir_all(repo.join(".git/info")).unwrap();
+    write(&repo.join(".git/info/exclude"), "local-only.txt\n");
+    write(&repo.join(".gitignore"), "*.log\nbuild/\n!keep.log\n");
+    write(&repo.join("src/main.rs"), "fn main() {}");
+    write(&repo.join("src/.gitignore"), "generated.rs\n");
+    write(&repo.join("src/generated.rs"), "// generated");
+    write(&repo.join("build/out.o"), "object");
+    write(&repo.join("debug.log"), "noise");
+    write(&repo.join("keep.log"), "kept");
+    write(&repo.join("local-only.txt"), "mine");
+    write(&repo.join("README"), "read me");
+
+    let mut walked: Vec<String> = gitignore_walker(&repo)
+        .build()
+        .map(|entry| {
+            let path = entry.unwrap().into_path();
+            path.strip_prefix(&repo)
+                .unwrap()
+                .to_string_lossy()
+                .to_string()
+        })
+        .filter(|path| !path.starts_with(".git/") && path != ".git")
+        .collect();
+    walked.sort();
+    assert_eq!(
+        walked,
+        [
+            "",
+            ".gitignore",
+            "README",
+            "keep.log",
+            "src",
+            "src/.gitignore",
+            "src/main.rs"
+        ]
+    );
+
+    // Packed with --gitignore, and with --exclude-vcs for the .git directory itself
+    let archive = dir.join("repo.rstf");
+    rstf([
+        "pack",
+        path_str(&repo),
+        "-o",
+        path_str(&archive),
+        "--no-encrypt",
+        "--quiet",
+        "--gitignore",
+        "--exclude-vcs",
+    ])
+    .unwrap();
+    let mut paths = entry_paths(&archive);
+    paths.sort();
+    assert_eq!(
+        paths,
+        [
+            "repo",
+            "repo/.gitignore",
+            "repo/README",
+            "repo/keep.log",
+            "repo/src",
+            "repo/src/.gitignore",
+            "repo/src/main.rs"
+        ]
+    );
+    let (header, _) = open(&archive).unwrap();
+    assert_eq!((header.file_count, header.dir_count), (5, 2));
+}
+
+#[test]
+fn parse_size_reads_binary_suffixes() {
+    for (value, size) in [
+        ("0", 0),
+        ("4096", 4096),
+        ("12B", 12),
+        ("512K", 512 << 10),
+        ("512kb", 512 << 10),
+        ("3KiB", 3 << 10),
+        ("20M", 20 << 20),
+        ("20 MiB", 20 << 20),
+        ("2G", 2 << 30),
+        ("2gib", 2 << 30),
+        ("1TiB", 1 << 40),
+        (" 7T ", 7 << 40),
+    ] {
+        assert_eq!(parse_size(value), Ok(size), "{}", value);
+    }
+    for value in ["", "K", "-1", "1.5G", "10X", "10 PB", "20000000T"] {
+        assert!(parse_size(value).is_err(), "{} was accepted", value);
+    }
+}
+
+#[test]
+fn size_filters_leave_files_out_by_size() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    write(&tree.join("small"), [0u8; 100]);
+    write(&tree.join("medium"), [0u8; 2048]);
+    write(&tree.join("large"), vec![0u8; 5000]);
+    let archive = dir.join("tree.rstf");
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&archive),
+        "--no-encrypt",
+        "--quiet",
+        "--exclude-smaller-than",
+        "1K",
+        "--exclude-larger-than",
+        "4k",
+    ])
+    .unwrap();
+    assert_eq!(entry_paths(&archive), ["tree", "tree/medium"]);
+}
+
+#[test]
+fn mode_and_mtime_round_trip() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TempDir::new();
+    let mode_of = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+    let mtime_of = |path: &Path| {
+        filetime::FileTime::from_last_modification_time(&fs::metadata(path).unwrap()).unix_seconds()
+    };
+    let set = |path: &Path, mode: u32| {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+        filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(1_600_000_000, 0))
+            .unwrap();
+    };
+    let umask = process_umask();
+
+    // A single file keeps them in the rstf header
+    let file = dir.join("tool");
+    write(&file, "#!/bin/sh\n");
+    set(&file, 0o4750);
+    rstf(["pack", path_str(&file), "--no-encrypt", "--quiet"]).unwrap();
+    let archive = dir.join("tool.rstf");
+    let (header, _) = open(&archive).unwrap();
+    assert_eq!(
+        (header.mode, header.mtime),
+        (Some(0o4750), Some(1_600_000_000))
+    );
+
+    let out = dir.join("out");
+    rstf(["unpack", path_str(&archive), "-C", path_str(&out)]).unwrap();
+    assert_eq!(mode_of(&out.join("tool")), 0o750 & !umask);
+    assert_eq!(mtime_of(&out.join("tool")), 1_600_000_000);
+    let kept = dir.join("kept");
+    rstf([
+        "unpack",
+        path_str(&archive),
+        "-C",
+        path_str(&kept),
+        "-p",
+        "--no-preserve-mtimes",
+    ])
+    .unwrap();
+    assert_eq!(mode_of(&kept.join("tool")) & 0o1777, 0o750);
+    assert_ne!(mtime_of(&kept.join("tool")), 1_600_000_000);
+
+    // Directory entries keep them in their tar headers
+    let tree = dir.join("tree");
+    write(&tree.join("private/notes"), "notes");
+    write(&tree.join("script"), "#!/bin/sh\n");
+    set(&tree.join("private/notes"), 0o600);
+    set(&tree.join("script"), 0o755);
+    set(&tree.join("private"), 0o700);
+    rstf(["pack", path_str(&tree), "--no-encrypt", "--quiet"]).unwrap();
+    let restored = dir.join("restored");
+    rstf([
+        "unpack",
+        path_str(&dir.join("tree.rstf")),
+        "-C",
+        path_str(&restored),
+        "-p",
+    ])
+    .unwrap();
+    for (path, mode) in [
+        ("private/notes", 0o600),
+        ("script", 0o755),
+        ("private", 0o700),
+    ] {
+        let path = restored.join("tree").join(path);
+        assert_eq!(mode_of(&path), mode, "{}", path.display());
+        assert_eq!(mtime_of(&path), 1_600_000_000, "{}", path.display());
+    }
+}
+
+#[cfg(all(unix, feature = "xattrs"))]
+#[test]
+fn xattrs_round_trip() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    write(&tree.join("tagged"), "tagged");
+    write(&tree.join("plain"), "plain");
+    let value = b"\x00binary value\xff".to_vec();
+    if let Err(e) = xattr::set(tree.join("tagged"), "user.rstf.test", &value) {
+        eprintln!("no user xattrs on this filesystem ({}); skipping", e);
+        return;
+    }
+    xattr::set(&tree, "user.rstf.dir", b"on the directory").unwrap();
+
+    let archive = dir.join("tree.rstf");
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&archive),
+        "--no-encrypt",
+        "--quiet",
+        "--xattrs",
+    ])
+    .unwrap();
+    let with = dir.join("with");
+    rstf([
+        "unpack",
+        path_str(&archive),
+        "-C",
+        path_str(&with),
+        "--xattrs",
+    ])
+    .unwrap();
+    let restored = with.join("tree");
+    assert_eq!(
+        xattr::get(restored.join("tagged"), "user.rstf.test").unwrap(),
+        Some(value.clone())
+    );
+    assert_eq!(
+        xattr::get(&restored, "user.rstf.dir").unwrap().as_deref(),
+        Some(&b"on the directory"[..])
+    );
+    assert_eq!(xattr::list(restored.join("plain")).unwrap().count(), 0);
+
+    // Unpacking leaves them out unless asked for
+    let without = dir.join("without");
+    rstf(["unpack", path_str(&archive), "-C", path_str(&without)]).unwrap();
+    assert_eq!(
+        xattr::get(without.join("tree/tagged"), "user.rstf.test").unwrap(),
+        None
+    );
+
+    // Refusals are collected rather than failing the extraction
+    let mut failures = Vec::new();
+    let target = without.join("tree/plain");
+    restore_xattrs(
+        &target,
+        &[("user.rstf.ok".into(), b"ok".to_vec())],
+        &mut failures,
+    );
+    restore_xattrs(
+        &target,
+        &[("bogus.namespace".into(), b"x".to_vec())],
+        &mut failures,
+    );
+    assert_eq!(
+        xattr::get(&target, "user.rstf.ok").unwrap().as_deref(),
+        Some(&b"ok"[..])
+    );
+    assert_eq!(failures.len(), 1, "{:?}", failures);
+}
+
+#[test]
+fn hard_links_round_trip_with_their_link_count() {
+    use std::os::unix::fs::MetadataExt;
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    write(&tree.join("a"), "shared contents");
+    fs::hard_link(tree.join("a"), tree.join("b")).unwrap();
+    fs::create_dir(tree.join("sub")).unwrap();
+    fs::hard_link(tree.join("a"), tree.join("sub/c")).unwrap();
+    write(&tree.join("alone"), "shared contents");
+    let inode = |path: &Path| {
+        let metadata = fs::metadata(path).unwrap();
+        (metadata.ino(), metadata.nlink())
+    };
+
+    let archive = dir.join("tree.rstf");
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&archive),
+        "--no-encrypt",
+        "--quiet",
+    ])
+    .unwrap();
+    let out = dir.join("out");
+    rstf(["unpack", path_str(&archive), "-C", path_str(&out)]).unwrap();
+    let restored = out.join("tree");
+    let (ino, links) = inode(&restored.join("a"));
+    assert_eq!(links, 3);
+    assert_eq!(inode(&restored.join("b")), (ino, 3));
+    assert_eq!(inode(&restored.join("sub/c")), (ino, 3));
+    assert_eq!(inode(&restored.join("alone")).1, 1);
+    assert_eq!(
+        fs::read_to_string(restored.join("sub/c")).unwrap(),
+        "shared contents"
+    );
+
+    // Renamed on conflict, the copies link to each other rather than to the originals
+    rstf([
+        "unpack",
+        path_str(&archive),
+        "-C",
+        path_str(&out),
+        "--on-conflict",
+        "rename",
+    ])
+    .unwrap();
+    let (renamed, links) = inode(&restored.join("a.1"));
+    assert_ne!(renamed, ino);
+    assert_eq!(links, 3);
+    assert_eq!(inode(&restored.join("b.1")), (renamed, 3));
+    assert_eq!(inode(&restored.join("sub/c.1")), (renamed, 3));
+    assert_eq!(inode(&restored.join("a")), (ino, 3));
+
+    // --no-hard-links stores every name as a file of its own
+    let separate = dir.join("separate.rstf");
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&separate),
+        "--no-encrypt",
+        "--quiet",
+        "--no-hard-links",
+    ])
+    .unwrap();
+    let apart = dir.join("apart");
+    rstf(["unpack", path_str(&separate), "-C", path_str(&apart)]).unwrap();
+    for name in ["a", "b", "sub/c"] {
+        assert_eq!(inode(&apart.join("tree").join(name)).1, 1, "{}", name);
+    }
+}
+
+#[test]
+fn store_round_trips_files_and_directories_uncompressed() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    sample_tree(&tree);
+    let file = dir.join("single.txt");
+    write(&file, "stored as is ".repeat(500));
+    for input in [&tree, &file] {
+        let archive = dir.join("stored.rstf");
+        rstf([
+            "pack",
+            path_str(input),
+            "-o",
+            path_str(&archive),
+            "--store",
+            "--quiet",
+        ])
+        .unwrap();
+        let (header, _) = open(&archive).unwrap();
+        assert_eq!(header.compression, Compression::None.id());
+        assert_eq!(header.level, None);
+        // Nothing is compressed, so the archive is at least as large as the input
+        assert!(fs::metadata(&archive).unwrap().len() > header.original_size);
+
+        let out = dir.join("out");
+        rstf(["unpack", path_str(&archive), "-C", path_str(&out)]).unwrap();
+        let name = input.file_name().unwrap();
+        if input.is_dir() {
+            assert_eq!(tree_listing(&out.join(name)), tree_listing(input));
+        } else {
+            assert_eq!(fs::read(out.join(name)).unwrap(), fs::read(input).unwrap());
+        }
+        fs::remove_dir_all(&out).unwrap();
+        fs::remove_file(&archive).unwrap();
+    }
+    assert!(rstf(["pack", path_str(&file), "--store", "--compression", "zstd"]).is_err());
+}
+
+#[test]
+fn low_memory_lowers_the_kdf_and_encoder_footprint() {
+    let low = KdfParams::low_memory();
+    assert_eq!((low.m_cost, low.t_cost, low.p_cost), (9 * 1024, 4, 1));
+    assert_eq!(KdfParams::for_pack(&pack_args(&["x", "--low-memory"])), low);
+    assert_eq!(
+        KdfParams::for_pack(&pack_args(&["x"])),
+        KdfParams::standard()
+    );
+    assert!(KdfParams::standard().m_cost > low.m_cost);
+
+    // The archive records the cheaper parameters for unpacking to use
+    let dir = TempDir::new();
+    let file = dir.join("data.bin");
+    write(&file, "low memory ".repeat(100));
+    rstf(["pack", path_str(&file), "--low-memory", "--quiet"]).unwrap();
+    let prologue = Prologue::read(&mut File::open(dir.join("data.bin.rstf")).unwrap()).unwrap();
+    assert_eq!(prologue.kdf, low);
+    assert!(open(&dir.join("data.bin.rstf")).is_ok());
+
+    // At level 19 the encoder would use an 8 MiB window; --low-memory keeps it at
+    // 2^LOW_MEMORY_WINDOW_LOG, which a decoder limited to that window can read
+    let data: Vec<u8> = (0..3u32 << 20)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    let compress = |args: &PackArgs| {
+        let mut encoder = zstd_encoder(Vec::new(), args, 19).unwrap();
+        encoder.write_all(&data).unwrap();
+        encoder.finish().unwrap()
+    };
+    let decompress = |frame: &[u8]| {
+        let mut decoder = ZstdDecoder::new(frame).unwrap();
+        decoder.window_log_max(LOW_MEMORY_WINDOW_LOG).unwrap();
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out).map(|_| out)
+    };
+    let low_args = pack_args(&["x", "--low-memory"]);
+    assert_eq!(decompress(&compress(&low_args)).unwrap(), data);
+    assert!(decompress(&compress(&pack_args(&["x"]))).is_err());
+
+    assert!(compression_memory(&low_args, 19, 1) < compression_memory(&pack_args(&["x"]), 19, 1));
+    assert_eq!(zstd_logs(&low_args, 19).0, LOW_MEMORY_WINDOW_LOG);
+    // Fast levels already fit and are left alone
+    assert_eq!(zstd_logs(&low_args, 1), zstd_logs(&pack_args(&["x"]), 1));
+}
+
+#[test]
+fn memory_limit_fits_automatic_settings_and_reports_explicit_ones() {
+    const MIB: u64 = 1 << 20;
+    let fits = |args: &PackArgs, limit: u64| {
+        BASE_MEMORY + compression_memory(args, args.level(), compression_threads(args) as usize)
+            <= limit
+    };
+
+    // Automatic threads and level come down until the estimate fits
+    for limit in [32 * MIB, 64 * MIB, 256 * MIB] {
+        let mut args = pack_args(&["x"]);
+        fit_memory_limit(&mut args, limit).unwrap();
+        assert!(fits(&args, limit), "{}", limit);
+    }
+    // A 128 MiB --long window leaves only the level to give; level 1 just fits
+    let long = pack_args(&["x", "--long"]);
+    let limit = BASE_MEMORY + compression_memory(&long, 1, 1);
+    let mut args = pack_args(&["x", "--long"]);
+    fit_memory_limit(&mut args, limit).unwrap();
+    assert_eq!((args.level, compression_threads(&args)), (Some(1), 1));
+    assert!(fit_memory_limit(&mut pack_args(&["x", "--long"]), limit - 1).is_err());
+    let mut args = pack_args(&["x", "--threads", "1"]);
+    fit_memory_limit(&mut args, 40 * MIB).unwrap();
+    assert_eq!(args.threads, 1);
+
+    // Settings given on the command line are kept, and refused when they do not fit
+    let mut args = pack_args(&["x", "--level", "22", "--threads", "1"]);
+    let error = fit_memory_limit(&mut args, 64 * MIB).unwrap_err();
+    assert!(
+        error.to_string().contains("level 22 with 1 thread(s)"),
+        "{}",
+        error
+    );
+    let mut args = pack_args(&["x", "--level", "3", "--threads", "1"]);
+    fit_memory_limit(&mut args, 64 * MIB).unwrap();
+    assert_eq!(args.level, Some(3));
+
+    // The KDF has to fit as well; --low-memory brings it down to 9 MiB
+    let error = fit_memory_limit(&mut pack_args(&["x"]), 24 * MIB).unwrap_err();
+    assert!(error.to_string().contains("--low-memory"), "{}", error);
+    fit_memory_limit(&mut pack_args(&["x", "--low-memory"]), 24 * MIB).unwrap();
+    assert!(fit_memory_limit(&mut pack_args(&["x"]), BASE_MEMORY).is_err());
+
+    // Every extra worker adds its own tables and job buffers
+    let args = pack_args(&["x", "--level", "9"]);
+    assert!(compression_memory(&args, 9, 4) > 3 * compression_memory(&args, 9, 1));
+    assert!(compression_memory(&args, 19, 1) > compression_memory(&args, 3, 1));
+    assert_eq!(
+        compression_memory(&pack_args(&["x", "--store"]), 5, 1),
+        compression_memory(&args, 5, 1)
+    );
+    let mut stored = pack_args(&["x", "--store"]);
+    resolve_codec_args(&mut stored).unwrap();
+    assert_eq!(compression_memory(&stored, 5, 4), 0);
+}
+
+#[test]
+fn decoder_window_log_fits_the_unpack_limit() {
+    const MIB: u64 = 1 << 20;
+    let overhead = BASE_MEMORY + ZSTD_DECODER_OVERHEAD;
+    assert_eq!(decoder_window_log(overhead + (1 << 10)).unwrap(), 10);
+    assert_eq!(decoder_window_log(overhead + MIB).unwrap(), 20);
+    assert_eq!(decoder_window_log(overhead + MIB - 1).unwrap(), 19);
+    assert_eq!(decoder_window_log(overhead + 128 * MIB).unwrap(), 27);
+    assert_eq!(decoder_window_log(64 << 30).unwrap(), MAX_WINDOW_LOG);
+    assert!(decoder_window_log(overhead).is_err());
+    assert!(decoder_window_log(BASE_MEMORY).is_err());
+    assert!(decoder_window_log(MIB).is_err());
+
+    // An archive packed with a window larger than the limit allows is refused
+    let dir = TempDir::new();
+    let file = dir.join("data.bin");
+    write(
+        &file,
+        (0..3u32 << 20)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect::<Vec<_>>(),
+    );
+    rstf([
+        "pack",
+        path_str(&file),
+        "--no-encrypt",
+        "--quiet",
+        "--long=23",
+    ])
+    .unwrap();
+    let archive = dir.join("data.bin.rstf");
+    let out = dir.join("out");
+    assert!(rstf([
+        "unpack",
+        path_str(&archive),
+        "-C",
+        path_str(&out),
+        "--memory-limit",
+        "16M"
+    ])
+    .is_err());
+    rstf([
+        "unpack",
+        path_str(&archive),
+        "-C",
+        path_str(&out),
+        "--memory-limit",
+        "64M",
+    ])
+    .unwrap();
+    assert_eq!(
+        fs::read(out.join("data.bin")).unwrap(),
+        fs::read(&file).unwrap()
+    );
+}
+
+// Format Fixtures (tests/fixtures/format-N.rstf: the same two-file `fixture` directory
+// packed by the rstf release that wrote format N, password "fixture")
+fn fixture(version: u8) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/fixtures/format-{}.rstf", version))
+}
+
+fn fixture_tree() -> Vec<(PathBuf, Option<Vec<u8>>)> {
+    vec![
+        (PathBuf::from("hello.txt"), Some(b"hello, rstf\n".to_vec())),
+        (PathBuf::from("sub"), None),
+        (
+            PathBuf::from("sub/data.bin"),
+            Some((0..3000u32).map(|i| (i * 7 % 251) as u8).collect()),
+        ),
+    ]
+}
+
+#[test]
+fn every_format_version_still_opens_and_extracts() {
+    set_password("fixture");
+    let dir = TempDir::new();
+    for version in 1..=FORMAT_VERSION {
+        let (header, _) =
+            open(&fixture(version)).unwrap_or_else(|e| panic!("format {}: {:?}", version, e));
+        assert_eq!(header.format_version, version);
+        assert_eq!(header.original_name, "fixture");
+        assert!(header.is_dir);
+
+        let out = dir.join(format!("v{}", version));
+        rstf(["unpack", path_str(&fixture(version)), "-C", path_str(&out)]).unwrap();
+        assert_eq!(
+            tree_listing(&out.join("fixture")),
+            fixture_tree(),
+            "format {}",
+            version
+        );
+    }
+
+    set_password("not the password");
+    for version in 1..=FORMAT_VERSION {
+        assert!(open(&fixture(version)).is_err(), "format {}", version);
+    }
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    let digits: Vec<u8> = text.bytes().filter(u8::is_ascii_hexdigit).collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn header_records_match_golden_bytes() {
+    let header = RstfHeader {
+        is_dir: true,
+        original_name: "docs".into(),
+        original_size: 42,
+        file_count: 3,
+        dir_count: 1,
+        created_at: 1_700_000_000,
+        tool_version: "1.0.0".into(),
+        trailer_len: Trailer::LEN as u32,
+        level: Some(5),
+        ..Default::default()
+    };
+    let golden = hex("01 00000000
+         02 04000000 646f6373
+         03 08000000 2a00000000000000
+         06 08000000 0300000000000000
+         07 08000000 0100000000000000
+         08 08000000 00f1536500000000
+         09 05000000 312e302e30
+         0e 04000000 05000000
+         81 04000000 29000000
+         83 01000000 00");
+    assert_eq!(header.to_bytes().unwrap(), golden);
+
+    let decoded = RstfHeader::from_bytes(&golden, FORMAT_VERSION).unwrap();
+    assert_eq!(decoded.encoded_len, golden.len());
+    assert_eq!(
+        format!(
+            "{:?}",
+            RstfHeader {
+                encoded_len: 0,
+                ..decoded
+            }
+        ),
+        format!("{:?}", header)
+    );
+}
+
+#[test]
+fn header_records_round_trip_every_field() {
+    let header = RstfHeader {
+        is_dir: true,
+        original_name: "name with ünïcode".into(),
+        original_size: u64::MAX,
+        members: vec!["first".into(), "second".into()],
+        contents_only: true,
+        file_count: 7,
+        dir_count: 2,
+        created_at: 1_700_000_000,
+        tool_version: "9.9.9".into(),
+        comment: "a comment\nover two lines".into(),
+        trailer_len: Trailer::PADDED_LEN as u32,
+        owner: Some(FileOwner {
+            uid: 1000,
+            gid: 100,
+            user: "alice".into(),
+            group: "users".into(),
+        }),
+        mode: Some(0o4755),
+        mtime: Some(-86_400),
+        extents: Some(vec![(0, 4096), (1 << 40, 17)]),
+        compression: Compression::None.id(),
+        window_log: Some(27),
+        dict_id: Some(0xdead_beef),
+        dictionary: Some(vec![1, 2, 3]),
+        level: Some(-5),
+        segmented: true,
+        class_levels: Some(vec![("text".into(), Some(19)), ("media".into(), None)]),
+        index_span: Some(4 << 20),
+        padded: true,
+        ..Default::default()
+    };
+    let bytes = header.to_bytes().unwrap();
+    let decoded = RstfHeader::from_bytes(&bytes, FORMAT_VERSION).unwrap();
+    assert_eq!(
+        format!(
+            "{:?}",
+            RstfHeader {
+                encoded_len: 0,
+                ..decoded.clone()
+            }
+        ),
+        format!("{:?}", header)
+    );
+    assert_eq!(decoded.to_bytes().unwrap(), bytes);
+}
+
+#[test]
+fn header_records_skip_unknown_fields_unless_critical() {
+    let header = RstfHeader {
+        original_name: "x".into(),
+        ..Default::default()
+    };
+    let bytes = header.to_bytes().unwrap();
+    let with = |record: &str| [&bytes[..], &hex(record)].concat();
+
+    // Informational fields from a newer rstf are skipped
+    let decoded = RstfHeader::from_bytes(&with("7e 03000000 010203"), FORMAT_VERSION).unwrap();
+    assert_eq!(decoded.original_name, "x");
+
+    // Critical ones change how the payload reads, so they are refused
+    let error = RstfHeader::from_bytes(&with("fe 00000000"), FORMAT_VERSION).unwrap_err();
+    assert!(
+        matches!(
+            ArchiveError::classify(&error),
+            Some(ArchiveError::UnsupportedVersion(_))
+        ),
+        "{:?}",
+        error
+    );
+    assert!(error.to_string().contains("0xfe"), "{}", error);
+
+    for malformed in [
+        with("02 01000000 79"),   // the name twice
+        with("0c 02000000 0000"), // a mode of two bytes
+        with("03 08000000 2a"),   // a value cut short
+        with("03 0800"),          // a length cut short
+        hex("02 01000000 ff"),    // a name that is not UTF-8
+    ] {
+        assert!(
+            RstfHeader::from_bytes(&malformed, FORMAT_VERSION).is_err(),
+            "{:02x?} was accepted",
+            malformed
+        );
+    }
+}
+
+// Whole-Archive Read (opens `archive` and authenticates every chunk of its payload)
+fn read_archive(archive: &Path) -> Result<()> {
+    let (_, mut reader) = open(archive)?;
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    Ok(())
+}
+
+#[test]
+fn archives_cut_anywhere_read_as_truncated() {
+    let dir = TempDir::new();
+    let file = dir.join("data.bin");
+    write(
+        &file,
+        (0..10_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8)
+            .collect::<Vec<_>>(),
+    );
+    let archive = dir.join("data.bin.rstf");
+    rstf([
+        "pack",
+        path_str(&file),
+        "--no-encrypt",
+        "--store",
+        "--chunk-size",
+        "4K",
+        "--quiet",
+    ])
+    .unwrap();
+    read_archive(&archive).unwrap();
+
+    let bytes = fs::read(&archive).unwrap();
+    let total = bytes.len();
+    let prologue_len = Prologue::read(&mut &bytes[..]).unwrap().len() as usize;
+    let (header, _) = open(&archive).unwrap();
+    let payload_start = prologue_len + 4 + TAG_SIZE + header.encoded_len + TAG_SIZE;
+    let sealed_chunk = 4096 + TAG_SIZE;
+    let payload_end = total - ChecksumFooter::LEN - (header.trailer_len as usize + TAG_SIZE);
+    assert_eq!((payload_end - payload_start).div_ceil(sealed_chunk), 3);
+
+    // Inside the prologue and the header chunks, on and next to every payload chunk
+    // boundary, halfway through each chunk, and anywhere in the trailer and footer
+    let mut cuts: Vec<usize> = (0..payload_start + 2).collect();
+    for boundary in (payload_start..payload_end).step_by(sealed_chunk) {
+        cuts.extend([
+            boundary - 1,
+            boundary,
+            boundary + 1,
+            boundary + sealed_chunk / 2,
+        ]);
+    }
+    cuts.extend(payload_end - 2..total);
+    cuts.retain(|&len| len < total);
+    cuts.sort_unstable();
+    cuts.dedup();
+    let cut = dir.join("cut.rstf");
+    for len in cuts {
+        fs::write(&cut, &bytes[..len]).unwrap();
+        let result = read_archive(&cut);
+        if len == total - ChecksumFooter::LEN {
+            // Just the footer gone: the trailer still shows the stream is complete
+            assert!(result.is_ok(), "cut to {} bytes: {:?}", len, result);
+            continue;
+        }
+        let error = result
+            .err()
+            .unwrap_or_else(|| panic!("cut to {} bytes opened", len));
+        let expected = if len < prologue_len { 7 } else { 5 };
+        assert_eq!(
+            ArchiveError::classify(&error).map(|class| class.exit_code()),
+            Some(expected),
+            "cut to {} bytes: {:?}",
+            len,
+            error
+        );
+    }
+}
+
+#[test]
+fn data_after_the_archive_end_is_found_and_refused() {
+    let dir = TempDir::new();
+    let file = dir.join("data.bin");
+    write(
+        &file,
+        (0..10_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8)
+            .collect::<Vec<_>>(),
+    );
+    let other = dir.join("other.txt");
+    write(&other, "the second archive");
+    for extra in [&[][..], &["--no-encrypt"][..]] {
+        let archive = dir.join("data.bin.rstf");
+        let second = dir.join("other.txt.rstf");
+        for (input, output) in [(&file, &archive), (&other, &second)] {
+            let args = [
+                &[
+                    "pack",
+                    path_str(input),
+                    "-o",
+                    path_str(output),
+                    "--store",
+                    "--chunk-size",
+                    "4K",
+                    "--quiet",
+                    "--overwrite",
+                ][..],
+                extra,
+            ]
+            .concat();
+            rstf(args).unwrap();
+        }
+        let original = fs::read(&archive).unwrap();
+
+        let mut random = vec![0u8; 10_000];
+        rand::thread_rng().fill(&mut random[..]);
+        let joined = dir.join("joined.rstf");
+        for appended in [
+            &random[..1],
+            &random[..45],
+            &random[..4112],
+            &random[..],
+            &fs::read(&second).unwrap()[..],
+        ] {
+            fs::write(&joined, [&original[..], appended].concat()).unwrap();
+            let error = read_archive(&joined).unwrap_err();
+            assert!(
+                error.to_string().contains("--allow-trailing"),
+                "{} bytes appended: {:?}",
+                appended.len(),
+                error
+            );
+
+            // find_end walks the payload to the trailer and reports where the archive stops
+            let options = OpenArchiveOptions {
+                allow_trailing: true,
+                ..Default::default()
+            };
+            let credentials = Credentials::read(None, true).unwrap();
+            let (header, mut reader) = open_archive_with(&joined, options, |prologue| {
+                credentials.derive_key(&prologue.salt, &prologue.kdf)
+            })
+            .unwrap();
+            let mut payload = Vec::new();
+            PayloadDecoder::new(&mut reader, &header)
+                .unwrap()
+                .read_to_end(&mut payload)
+                .unwrap();
+            std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+            assert_eq!(payload, fs::read(&file).unwrap());
+
+            let out = dir.join("out");
+            rstf([
+                "unpack",
+                path_str(&joined),
+                "-C",
+                path_str(&out),
+                "--allow-trailing",
+            ])
+            .unwrap();
+            assert_eq!(
+                fs::read(out.join("data.bin")).unwrap(),
+                fs::read(&file).unwrap()
+            );
+            fs::remove_dir_all(&out).unwrap();
+        }
+    }
+}
+
+#[test]
+fn every_prologue_byte_is_authenticated() {
+    let dir = TempDir::new();
+    let file = dir.join("data.txt");
+    write(&file, "authenticated ".repeat(100));
+    for extra in [&[][..], &["--no-encrypt"][..]] {
+        let archive = dir.join("data.txt.rstf");
+        rstf(
+            [
+                &["pack", path_str(&file), "--quiet", "--overwrite"][..],
+                extra,
+            ]
+            .concat(),
+        )
+        .unwrap();
+        let bytes = fs::read(&archive).unwrap();
+        let prologue = Prologue::read(&mut &bytes[..]).unwrap();
+        let credentials = Credentials::read(None, true).unwrap();
+        let key = credentials
+            .derive_key(&prologue.salt, &prologue.kdf)
+            .unwrap();
+
+        // The right key is handed over whatever the changed prologue says, so only the
+        // associated data can tell (and no changed KDF cost has to be paid)
+        let read_with_key = |path: &Path| -> Result<()> {
+            let (_, mut reader) =
+                open_archive_with(path, OpenArchiveOptions::default(), |_| Ok(key))?;
+            std::io::copy(&mut reader, &mut std::io::sink())?;
+            Ok(())
+        };
+        read_with_key(&archive).unwrap();
+        let tampered = dir.join("tampered.rstf");
+        for position in 0..prologue.len() as usize {
+            let mut changed = bytes.clone();
+            changed[position] ^= 0x01;
+            fs::write(&tampered, &changed).unwrap();
+            assert!(
+                read_with_key(&tampered).is_err(),
+                "byte {} of {:?} changed unnoticed",
+                position,
+                extra
+            );
+        }
+    }
+
+    // Formats before 9 had no associated data and still open
+    set_password("fixture");
+    for version in 1..=8 {
+        read_archive(&fixture(version)).unwrap_or_else(|e| panic!("format {}: {:?}", version, e));
+    }
+}
+
+#[test]
+fn kdf_costs_from_a_prologue_are_bounded() {
+    let standard = KdfParams::standard();
+    assert!(standard.argon2().is_ok());
+    assert!(KdfParams::low_memory().argon2().is_ok());
+    for crafted in [
+        KdfParams {
+            m_cost: MAX_KDF_MEMORY_KIB + 1,
+            ..standard
+        },
+        KdfParams {
+            t_cost: MAX_KDF_PASSES + 1,
+            ..standard
+        },
+        KdfParams {
+            t_cost: 1 << 24,
+            ..standard
+        },
+        KdfParams {
+            p_cost: 0,
+            ..standard
+        },
+    ] {
+        assert!(crafted.argon2().is_err(), "{:?} was accepted", crafted);
+    }
+}
+
+#[test]
+fn key_commitment_accepts_only_the_archive_key() {
+    let key = [7u8; 32];
+    let other = [8u8; 32];
+    assert_eq!(key_commitment(&key), key_commitment(&key));
+    assert_ne!(key_commitment(&key), key_commitment(&other));
+    // Derived under a context of its own, not a plain hash of the key
+    assert_ne!(key_commitment(&key), *blake3::hash(&key).as_bytes());
+
+    let dir = TempDir::new();
+    let file = dir.join("data.txt");
+    write(&file, "committed");
+    rstf(["pack", path_str(&file), "--quiet"]).unwrap();
+    let archive = dir.join("data.txt.rstf");
+    let mut prologue = Prologue::read(&mut File::open(&archive).unwrap()).unwrap();
+    let credentials = Credentials::read(None, true).unwrap();
+    let key = credentials
+        .derive_key(&prologue.salt, &prologue.kdf)
+        .unwrap();
+    assert_eq!(prologue.commitment, Some(key_commitment(&key)));
+    prologue.check_key(&key).unwrap();
+    let error = prologue.check_key(&other).unwrap_err();
+    assert!(matches!(
+        ArchiveError::classify(&error),
+        Some(ArchiveError::WrongCredentials(_))
+    ));
+
+    // Without a commitment (before format 10) any key gets as far as the first chunk
+    prologue.commitment = None;
+    prologue.check_key(&other).unwrap();
+
+    set_password("wrong password");
+    let error = read_archive(&archive).unwrap_err();
+    assert_eq!(
+        ArchiveError::classify(&error).map(|class| class.exit_code()),
+        Some(4)
+    );
+    assert!(error.to_string().contains("key commitment"), "{}", error);
+}
+
+#[test]
+fn xchacha20_round_trips_and_ciphers_do_not_mix() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    sample_tree(&tree);
+    let archive = dir.join("tree.rstf");
+    rstf(["pack", path_str(&tree), "--cipher", "xchacha20", "--quiet"]).unwrap();
+    let prologue = Prologue::read(&mut File::open(&archive).unwrap()).unwrap();
+    assert_eq!(prologue.cipher, Cipher::XChaCha20);
+    assert_eq!(prologue.nonce.len(), 19);
+    let out = dir.join("out");
+    rstf(["unpack", path_str(&archive), "-C", path_str(&out)]).unwrap();
+    assert_eq!(tree_listing(&out.join("tree")), tree_listing(&tree));
+
+    // The same key and nonce prefix under the other cipher opens nothing
+    let credentials = Credentials::read(None, true).unwrap();
+    let key = credentials
+        .derive_key(&prologue.salt, &prologue.kdf)
+        .unwrap();
+    let chacha_prologue = Prologue {
+        cipher: Cipher::ChaCha20,
+        nonce: prologue.nonce[..Cipher::ChaCha20.nonce_len()].to_vec(),
+        ..prologue
+    };
+    let xchacha = ChunkCipher::new(&prologue, &key).unwrap();
+    let chacha = ChunkCipher::new(&chacha_prologue, &key).unwrap();
+    fn payload(msg: &[u8]) -> Payload<'_, '_> {
+        Payload { msg, aad: b"aad" }
+    }
+    for (sealer, other) in [(&xchacha, &chacha), (&chacha, &xchacha)] {
+        let sealed = sealer.encrypt(0, true, payload(b"chunk")).unwrap();
+        assert_eq!(sealer.decrypt(0, true, payload(&sealed)).unwrap(), b"chunk");
+        assert!(other.decrypt(0, true, payload(&sealed)).is_none());
+    }
+
+    // Nor does an archive whose prologue names the other cipher, even with the right key
+    let mut bytes = fs::read(&archive).unwrap();
+    assert_eq!(bytes[6], Cipher::XChaCha20.id());
+    bytes[6] = Cipher::ChaCha20.id();
+    let swapped = dir.join("swapped.rstf");
+    fs::write(&swapped, &bytes).unwrap();
+    assert!(open_archive_with(&swapped, OpenArchiveOptions::default(), |_| Ok(key)).is_err());
+}
+
+fn unpack_args(args: &[&str]) -> UnpackArgs {
+    let args = ["rstf", "unpack"].iter().chain(args);
+    match Cli::try_parse_from(args).unwrap().command {
+        Commands::Unpack(args) => args,
+        _ => unreachable!(),
+    }
+}
+
+// Noise Tree (files that do not compress, so payload chunks map closely to file data)
+fn noise_tree(root: &Path) {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for n in 0..12 {
+        let data: Vec<u8> = (0..20_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        write(&root.join(format!("file{:02}.bin", n)), data);
+    }
+}
+
+#[test]
+fn salvage_recovers_what_surrounds_a_damaged_chunk() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    noise_tree(&tree);
+    let original = tree_listing(&tree);
+    for (packing, recovers_past_damage) in [
+        (&["--index=16K"][..], true),
+        (&["--store"][..], true),
+        (&[][..], false),
+    ] {
+        let archive = dir.join("tree.rstf");
+        rstf(
+            [
+                &["pack", path_str(&tree), "--quiet", "--overwrite"][..],
+                &["--chunk-size", "4K"],
+                packing,
+            ]
+            .concat(),
+        )
+        .unwrap();
+        let mut bytes = fs::read(&archive).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0x40;
+        fs::write(&archive, &bytes).unwrap();
+
+        // Without --salvage the damage ends the unpack
+        let out = dir.join("out");
+        let error = rstf(["unpack", path_str(&archive), "-C", path_str(&out)]).unwrap_err();
+        assert_eq!(
+            ArchiveError::classify(&error).map(|class| class.exit_code()),
+            Some(6),
+            "{:?}",
+            packing
+        );
+        let _ = fs::remove_dir_all(&out);
+
+        let args = unpack_args(&[path_str(&archive), "-C", path_str(&out), "--salvage"]);
+        let filter = PathFilter::new(&args.include, &args.exclude).unwrap();
+        let credentials = Credentials::read(None, true).unwrap();
+        let damage = DamageLog::default();
+        let outcome = unpack_archive(&archive, &credentials, &args, &filter, Some(&damage));
+        let damaged = damage.lock().unwrap().clone();
+        assert_eq!(damaged.len(), 1, "{:?}", packing);
+        let chunk = damaged[0];
+        assert!(chunk.file_offset <= middle as u64);
+        assert!(middle as u64 - chunk.file_offset < 4096 + TAG_SIZE as u64);
+        assert_eq!(chunk.len, 4096);
+
+        if recovers_past_damage {
+            let recovered = tree_listing(&out.join("tree"));
+            let intact = original
+                .iter()
+                .filter(|entry| recovered.contains(entry))
+                .count();
+            // The zeros fail the extracted-entries digest, which finish_salvage reports
+            // alongside the damage; files past the damaged span still come back
+            assert!(outcome.is_err());
+            // One file, perhaps two, overlaps the span read as zeros; the rest are intact
+            assert!(
+                intact >= original.len() - 2,
+                "{:?}: {} intact",
+                packing,
+                intact
+            );
+            let last = original.last().unwrap();
+            assert!(recovered.contains(last), "{:?}", packing);
+        } else {
+            // zstd decodes whole blocks, so what precedes the damage in its block is
+            // lost as well; decoding stops there with a pointer to --index
+            let error = outcome.unwrap_err();
+            assert!(
+                format!("{:#}", error).contains("only archives packed with --index"),
+                "{:#}",
+                error
+            );
+        }
+        let _ = fs::remove_dir_all(&out);
+    }
+
+    // An undamaged archive salvages like a plain unpack, with nothing logged
+    let archive = dir.join("clean.rstf");
+    rstf(["pack", path_str(&tree), "-o", path_str(&archive), "--quiet"]).unwrap();
+    let out = dir.join("clean");
+    rstf([
+        "unpack",
+        path_str(&archive),
+        "-C",
+        path_str(&out),
+        "--salvage",
+    ])
+    .unwrap();
+    assert_eq!(tree_listing(&out.join("tree")), original);
+
+    // A salvaged archive is never deleted, however the unpack ends
+    assert!(rstf([
+        "unpack",
+        path_str(&archive),
+        "--salvage",
+        "--remove-archive"
+    ])
+    .is_err());
+}
+
+#[test]
+fn each_failure_has_its_own_class_and_exit_code() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    noise_tree(&tree);
+    let archive = dir.join("tree.rstf");
+    rstf(["pack", path_str(&tree), "--chunk-size", "4K", "--quiet"]).unwrap();
+    let bytes = fs::read(&archive).unwrap();
+    let exit_code = |path: &Path| {
+        let error = rstf(["unpack", path_str(path), "-C", path_str(&dir.join("out"))])
+            .expect_err("unpack succeeded");
+        let _ = fs::remove_dir_all(dir.join("out"));
+        ArchiveError::classify(&error).map(|class| class.exit_code())
+    };
+    let variant = |name: &str, change: &dyn Fn(&mut Vec<u8>)| {
+        let mut changed = bytes.clone();
+        change(&mut changed);
+        let path = dir.join(name);
+        fs::write(&path, changed).unwrap();
+        path
+    };
+
+    let truncated = variant("truncated.rstf", &|b| b.truncate(b.len() * 2 / 3));
+    let corrupted = variant("corrupted.rstf", &|b| {
+        let middle = b.len() / 2;
+        b[middle] ^= 0x01;
+    });
+    let not_archive = variant("not-archive.rstf", &|b| b[..4].copy_from_slice(b"ZIP!"));
+    let newer = variant("newer.rstf", &|b| b[4] = FORMAT_VERSION + 1);
+    assert_eq!(exit_code(&truncated), Some(5));
+    assert_eq!(exit_code(&corrupted), Some(6));
+    assert_eq!(exit_code(&not_archive), Some(7));
+    assert_eq!(exit_code(&newer), Some(8));
+
+    set_password("wrong password");
+    assert_eq!(exit_code(&archive), Some(4));
+    // Before key commitments (format 10) a wrong key shows at the first chunk instead
+    assert_eq!(exit_code(&fixture(9)), Some(4));
+    set_password("correct horse");
+
+    // A batch exits with the status of its first failure
+    let batch = |archives: &[&Path]| {
+        let paths: Vec<&str> = archives.iter().map(|p| path_str(p)).collect();
+        let out = dir.join("batch");
+        let error = rstf([&["unpack", "-C", path_str(&out)][..], &paths].concat())
+            .expect_err("batch succeeded");
+        let _ = fs::remove_dir_all(&out);
+        ArchiveError::classify(&error).map(|class| class.exit_code())
+    };
+    assert_eq!(batch(&[&archive, &corrupted, &truncated]), Some(6));
+    assert_eq!(batch(&[&truncated, &archive, &corrupted]), Some(5));
+}
+
+#[test]
+fn padme_len_rounds_up_by_at_most_twelve_percent() {
+    for (len, padded) in [
+        (0, 0),
+        (1, 1),
+        (2, 2),
+        (7, 7),
+        (9, 10),
+        (1000, 1024),
+        (1025, 1088),
+        (1 << 20, 1 << 20),
+        ((1 << 20) + 1, (1 << 20) + (1 << 15)),
+    ] {
+        assert_eq!(padme_len(len), padded, "padme_len({})", len);
+    }
+    let mut len = 2u64;
+    while len < 1 << 40 {
+        for len in [len - 1, len, len + 1, len * 3 / 2] {
+            let padded = padme_len(len);
+            assert!(padded >= len && padded - len <= len * 12 / 100, "{}", len);
+            assert_eq!(padme_len(padded), padded, "{}", len);
+        }
+        len <<= 1;
+    }
+}
+
+#[test]
+fn padded_and_unpadded_archives_unpack_identically() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    sample_tree(&tree);
+    let plain = dir.join("plain.rstf");
+    let padded = dir.join("padded.rstf");
+    rstf(["pack", path_str(&tree), "-o", path_str(&plain), "--quiet"]).unwrap();
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&padded),
+        "--pad",
+        "--quiet",
+    ])
+    .unwrap();
+    let (plain_header, _) = open(&plain).unwrap();
+    let (padded_header, _) = open(&padded).unwrap();
+    assert!(!plain_header.padded && padded_header.padded);
+    // The header keeps the true size; only the file grows
+    assert_eq!(padded_header.original_size, plain_header.original_size);
+    assert!(fs::metadata(&padded).unwrap().len() > fs::metadata(&plain).unwrap().len());
+    for archive in [&plain, &padded] {
+        let out = dir.join("out");
+        rstf(["unpack", path_str(archive), "-C", path_str(&out)]).unwrap();
+        assert_eq!(tree_listing(&out.join("tree")), tree_listing(&tree));
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    // Inputs a little apart in size mostly share a padded size
+    let sizes = |pad: bool| {
+        let mut sizes: Vec<u64> = (0..4)
+            .map(|k| {
+                let file = dir.join("sized.bin");
+                write(&file, vec![0x5a; 100_000 + k * 500]);
+                let archive = dir.join("sized.rstf");
+                let common = [
+                    "pack",
+                    path_str(&file),
+                    "-o",
+                    path_str(&archive),
+                    "--store",
+                    "--overwrite",
+                    "--quiet",
+                ];
+                rstf([&common[..], if pad { &["--pad"][..] } else { &[] }].concat()).unwrap();
+                fs::metadata(&archive).unwrap().len()
+            })
+            .collect();
+        sizes.dedup();
+        sizes.len()
+    };
+    assert_eq!(sizes(false), 4);
+    assert!(sizes(true) <= 2);
+}
+
+#[test]
+fn shrink_padding_fills_a_shrunken_file_to_its_header_size() {
+    let path = Path::new("shrunk.txt");
+    let mut shrunk = Vec::new();
+    let mut padding = ShrinkPadding {
+        inner: &b"0123456789"[..],
+        remaining: 16,
+        path,
+        shrunk: false,
+    };
+    padding.read_to_end(&mut shrunk).unwrap();
+    assert_eq!(shrunk, b"0123456789\0\0\0\0\0\0");
+    assert!(padding.shrunk);
+
+    // Nothing past the header size is handed out
+    let mut grown = Vec::new();
+    let mut padding = ShrinkPadding {
+        inner: &b"0123456789"[..],
+        remaining: 4,
+        path,
+        shrunk: false,
+    };
+    padding.read_to_end(&mut grown).unwrap();
+    assert_eq!(grown, b"0123");
+    assert!(!padding.shrunk);
+}
+
+#[test]
+fn reproducible_packs_of_the_same_tree_have_identical_payloads() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    sample_tree(&tree);
+    let listing = tree_listing(&tree);
+    let epoch = 1_600_000_000u64;
+
+    // Each pack sees the tree created in another order, with other mtimes past the epoch
+    let pack_round = |round: u64| {
+        fs::remove_dir_all(&tree).unwrap();
+        let entries: Vec<_> = if round == 0 {
+            listing.iter().collect()
+        } else {
+            listing.iter().rev().collect()
+        };
+        for (relative, contents) in &entries {
+            match contents {
+                Some(contents) => write(&tree.join(relative), contents),
+                None => fs::create_dir_all(tree.join(relative)).unwrap(),
+            }
+        }
+        for (n, (relative, contents)) in entries.iter().enumerate() {
+            if contents.is_some() {
+                let mtime = epoch + 1000 * (round + 1) + n as u64;
+                File::options()
+                    .write(true)
+                    .open(tree.join(relative))
+                    .unwrap()
+                    .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))
+                    .unwrap();
+            }
+        }
+        assert_eq!(tree_listing(&tree), listing);
+
+        let archive = dir.join(format!("round{}.rstf", round));
+        std::env::set_var("SOURCE_DATE_EPOCH", epoch.to_string());
+        let packed = rstf([
+            "pack",
+            path_str(&tree),
+            "-o",
+            path_str(&archive),
+            "--reproducible",
+            "--quiet",
+        ]);
+        std::env::remove_var("SOURCE_DATE_EPOCH");
+        packed.unwrap();
+        let (header, mut reader) = open(&archive).unwrap();
+        let mut compressed = Vec::new();
+        reader.read_to_end(&mut compressed).unwrap();
+        (header, compressed, fs::read(&archive).unwrap())
+    };
+    let (first, first_payload, first_file) = pack_round(0);
+    let (second, second_payload, second_file) = pack_round(1);
+
+    let digest = |header: &RstfHeader| header.trailer.as_ref().unwrap().digest_string();
+    assert_eq!(digest(&first), digest(&second));
+    assert_eq!(first_payload, second_payload);
+    assert_eq!(first.created_at, epoch);
+    // The random salt and nonce still set the archive files apart
+    assert_ne!(first_file, second_file);
+    for (path, header) in entry_headers(&dir.join("round1.rstf")) {
+        assert_eq!(header.mtime().unwrap(), epoch, "{}", path);
+    }
+}
+
+#[test]
+fn sparse_files_round_trip_under_every_tar_format_that_holds_them() {
+    use std::os::unix::fs::FileExt;
+
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    write(&tree.join("small.txt"), "next to a sparse file");
+    let holey = tree.join("holey.bin");
+    let file = File::create(&holey).unwrap();
+    file.set_len(4 << 20).unwrap();
+    file.write_all_at(b"start", 0).unwrap();
+    file.write_all_at(&[0xab; 9000], 2 << 20).unwrap();
+    drop(file);
+    let Some(extents) = sparse_extents(&holey) else {
+        eprintln!("skipped: the temporary directory does not report holes");
+        return;
+    };
+    assert!(extents.iter().map(|&(_, len)| len).sum::<u64>() < 1 << 20);
+
+    for format in ["gnu", "pax"] {
+        let archive = dir.join(format!("{}.rstf", format));
+        rstf([
+            "pack",
+            path_str(&tree),
+            "-o",
+            path_str(&archive),
+            "--sparse",
+            "--tar-format",
+            format,
+            "--quiet",
+        ])
+        .unwrap();
+        let headers = entry_headers(&archive);
+        let (_, header) = headers
+            .iter()
+            .find(|(path, _)| path == "tree/holey.bin")
+            .unwrap();
+        assert_eq!(header.entry_type(), tar::EntryType::GNUSparse, "{}", format);
+        // The holes are not stored
+        assert!(fs::metadata(&archive).unwrap().len() < 1 << 20);
+
+        let out = dir.join("out");
+        rstf(["unpack", path_str(&archive), "-C", path_str(&out)]).unwrap();
+        assert_eq!(tree_listing(&out.join("tree")), tree_listing(&tree));
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    let error = rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&dir.join("ustar.rstf")),
+        "--sparse",
+        "--tar-format",
+        "ustar",
+        "--quiet",
+    ])
+    .unwrap_err();
+    assert!(error.to_string().contains("GNU extension"), "{}", error);
+}
+
+// Permission Checks For Root (drops CAP_DAC_OVERRIDE and CAP_DAC_READ_SEARCH from the
+// calling thread, so mode bits apply to it as to anyone else; other threads keep them)
+fn honor_permissions() {
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: i32,
+    }
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    let mut header = CapHeader {
+        version: 0x2008_0522,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    unsafe {
+        assert_eq!(
+            libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()),
+            0
+        );
+        data[0].effective &= !(1 << 1 | 1 << 2);
+        assert_eq!(
+            libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()),
+            0
+        );
+    }
+}
+
+#[test]
+fn unreadable_paths_are_skipped_without_writing_anything() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    write(&tree.join("a.txt"), "readable");
+    write(&tree.join("sub/ok.txt"), "readable too");
+    let secret = tree.join("secret.txt");
+    write(&secret, "mode 000");
+    // Past the --low-memory read limit, so it takes the large-file path
+    let large = tree.join("large.bin");
+    write(&large, vec![7u8; 2 << 20]);
+    let locked = tree.join("locked");
+    write(&locked.join("inner.txt"), "behind a mode 000 directory");
+    let socket = tree.join("socket");
+    let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+    for path in [&secret, &large, &locked] {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o000)).unwrap();
+    }
+
+    let archive = dir.join("tree.rstf");
+    let status = std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                honor_permissions();
+                assert!(File::open(&secret).is_err());
+                exit_status(|| {
+                    rstf([
+                        "pack",
+                        path_str(&tree),
+                        "-o",
+                        path_str(&archive),
+                        "--skip-unreadable",
+                        "--low-memory",
+                        "--quiet",
+                    ])
+                })
+            })
+            .join()
+            .unwrap()
+    });
+    assert_eq!(status.unwrap(), Some(EXIT_SKIPPED));
+    for path in [&secret, &large, &locked] {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let paths = entry_paths(&archive);
+    for skipped in [
+        "tree/secret.txt",
+        "tree/large.bin",
+        "tree/locked/inner.txt",
+        "tree/socket",
+    ] {
+        assert!(
+            !paths.iter().any(|p| p == skipped),
+            "{} was packed",
+            skipped
+        );
+    }
+    let out = dir.join("out");
+    rstf(["unpack", path_str(&archive), "-C", path_str(&out)]).unwrap();
+    let restored = out.join("tree");
+    assert_eq!(
+        fs::read_to_string(restored.join("a.txt")).unwrap(),
+        "readable"
+    );
+    assert_eq!(
+        fs::read_to_string(restored.join("sub/ok.txt")).unwrap(),
+        "readable too"
+    );
+
+    // Nothing skipped, nothing to report
+    fs::remove_file(&socket).unwrap();
+    let status = exit_status(|| {
+        rstf([
+            "pack",
+            path_str(&tree),
+            "-o",
+            path_str(&archive),
+            "--skip-unreadable",
+            "--overwrite",
+            "--quiet",
+        ])
+    });
+    assert_eq!(status.unwrap(), None);
+}
+
+#[test]
+fn rename_on_conflict_numbers_symlinks_and_special_files() {
+    let dir = TempDir::new();
+    let tree = dir.join("tree");
+    write(&tree.join("a"), "target");
+    std::os::unix::fs::symlink("a", tree.join("link")).unwrap();
+    let fifo = std::ffi::CString::new(path_str(&tree.join("fifo"))).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+    let archive = dir.join("tree.rstf");
+    rstf([
+        "pack",
+        path_str(&tree),
+        "-o",
+        path_str(&archive),
+        "--special-files",
+        "--quiet",
+    ])
+    .unwrap();
+
+    let out = dir.join("out");
+    for _ in 0..2 {
+        rstf([
+            "unpack",
+            path_str(&archive),
+            "-C",
+            path_str(&out),
+            "--on-conflict",
+            "rename",
+        ])
+        .unwrap();
+    }
+    let restored = out.join("tree");
+    for link in ["link", "link.1"] {
+        assert_eq!(
+            fs::read_link(restored.join(link)).unwrap(),
+            Path::new("a"),
+            "{}",
+            link
+        );
+    }
+    assert_eq!(fs::read_to_string(restored.join("a.1")).unwrap(), "target");
+    for name in ["fifo", "fifo.1"] {
+        use std::os::unix::fs::FileTypeExt;
+        let kind = fs::symlink_metadata(restored.join(name))
+            .unwrap()
+            .file_type();
+        assert!(kind.is_fifo(), "{}", name);
+    }
+    assert!(!restored.join("link.2").exists());
+}